@@ -0,0 +1,46 @@
+// This file is part of the aoraki-labs library.
+
+// The aoraki-labs library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// The aoraki-labs library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with the aoraki-labs library. If not, see <https://www.gnu.org/licenses/>.
+
+//! generates typed bindings for the pool contract from its ABI, read from ZKPOOL_ABI (a JSON file or a
+//! directory of them, default abi/) into OUT_DIR/zkpool_bindings.rs, included by src/bindings.rs
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+#[path = "codegen/loader.rs"]
+mod loader;
+#[path = "codegen/generate.rs"]
+mod generate;
+
+const DEFAULT_ABI_PATH: &str = "abi";
+const BINDINGS_FILE: &str = "zkpool_bindings.rs";
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=ZKPOOL_ABI");
+    let abi_path = PathBuf::from(env::var("ZKPOOL_ABI").unwrap_or_else(|_| DEFAULT_ABI_PATH.to_string()));
+    println!("cargo:rerun-if-changed={}", abi_path.display());
+    println!("cargo:rerun-if-changed=codegen");
+
+    let entries = loader::abi_files(&abi_path).and_then(|files| {
+        for file in files.iter() {
+            println!("cargo:rerun-if-changed={}", file.display());
+        }
+        loader::load_entries(&files)
+    }).unwrap_or_else(|e| panic!("contract abi {}: {}", abi_path.display(), e));
+    let bindings = generate::generate(&entries).unwrap_or_else(|e| panic!("contract abi {}: {}", abi_path.display(), e));
+    let out = PathBuf::from(env::var("OUT_DIR").expect("OUT_DIR set by cargo")).join(BINDINGS_FILE);
+    fs::write(&out, bindings).unwrap_or_else(|e| panic!("write {}: {}", out.display(), e));
+}
@@ -0,0 +1,516 @@
+// This file is part of the aoraki-labs library.
+
+// The aoraki-labs library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// The aoraki-labs library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with the aoraki-labs library. If not, see <https://www.gnu.org/licenses/>.
+
+//! the typed bindings source for a contract ABI, run by build.rs and unit tested through the crate
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use serde_json::Value;
+
+const KEYWORDS: [&str; 38] = [
+    "as", "async", "await", "box", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref",
+    "return", "self", "static", "struct", "super", "trait", "true", "type", "unsafe", "use", "where", "while",
+];
+
+#[derive(Clone, Debug)]
+enum Kind {
+    Address,
+    Uint(usize),
+    Int(usize),
+    Bool,
+    String,
+    Bytes,
+    FixedBytes(usize),
+    Array(Box<Kind>),
+    FixedArray(Box<Kind>, usize),
+    Tuple(String, Vec<Param>),
+}
+
+#[derive(Clone, Debug)]
+struct Param {
+    name: String,
+    kind: Kind,
+    indexed: bool,
+}
+
+fn parse_params(value: Option<&Value>) -> Result<Vec<Param>, String> {
+    let items = match value {
+        Some(Value::Array(items)) => items,
+        None => return Ok(vec![]),
+        Some(other) => return Err(format!("expected parameter list, got {}", other)),
+    };
+    items.iter().enumerate().map(|(i, item)| {
+        let type_name = item["type"].as_str().ok_or(format!("parameter without type: {}", item))?;
+        let name = item["name"].as_str().unwrap_or_default();
+        Ok(Param {
+            name: if name.is_empty() { format!("arg{}", i) } else { name.to_string() },
+            kind: parse_kind(type_name, item)?,
+            indexed: item["indexed"].as_bool().unwrap_or(false),
+        })
+    }).collect()
+}
+
+fn parse_kind(type_name: &str, item: &Value) -> Result<Kind, String> {
+    if let Some(inner) = type_name.strip_suffix(']') {
+        let open = inner.rfind('[').ok_or(format!("invalid type {}", type_name))?;
+        let element = Box::new(parse_kind(&inner[..open], item)?);
+        let size = &inner[open + 1..];
+        return Ok(if size.is_empty() {
+            Kind::Array(element)
+        } else {
+            Kind::FixedArray(element, size.parse().map_err(|_| format!("invalid type {}", type_name))?)
+        });
+    }
+    let bits = |prefix: &str| -> Result<usize, String> {
+        let size = &type_name[prefix.len()..];
+        if size.is_empty() { Ok(256) } else { size.parse().map_err(|_| format!("invalid type {}", type_name)) }
+    };
+    Ok(match type_name {
+        "address" => Kind::Address,
+        "bool" => Kind::Bool,
+        "string" => Kind::String,
+        "bytes" => Kind::Bytes,
+        "tuple" => {
+            //"struct Pool.TaskStatus[]" names the struct TaskStatus
+            let internal = item["internalType"].as_str().unwrap_or_default();
+            let internal = internal.trim_start_matches("struct ").split('[').next().unwrap_or_default();
+            let name = internal.rsplit('.').next().unwrap_or_default();
+            let name = if name.is_empty() { upper_camel(item["name"].as_str().unwrap_or("Tuple")) } else { name.to_string() };
+            Kind::Tuple(name, parse_params(item.get("components"))?)
+        },
+        t if t.starts_with("uint") => Kind::Uint(bits("uint")?),
+        t if t.starts_with("int") => Kind::Int(bits("int")?),
+        t if t.starts_with("bytes") => Kind::FixedBytes(t["bytes".len()..].parse().map_err(|_| format!("invalid type {}", t))?),
+        t => return Err(format!("unsupported type {}", t)),
+    })
+}
+
+/// the canonical type, as hashed into selectors and topics
+fn canonical(kind: &Kind) -> String {
+    match kind {
+        Kind::Address => "address".to_string(),
+        Kind::Uint(bits) => format!("uint{}", bits),
+        Kind::Int(bits) => format!("int{}", bits),
+        Kind::Bool => "bool".to_string(),
+        Kind::String => "string".to_string(),
+        Kind::Bytes => "bytes".to_string(),
+        Kind::FixedBytes(size) => format!("bytes{}", size),
+        Kind::Array(element) => format!("{}[]", canonical(element)),
+        Kind::FixedArray(element, size) => format!("{}[{}]", canonical(element), size),
+        Kind::Tuple(_, components) => format!("({})", components.iter().map(|p| canonical(&p.kind)).collect::<Vec<_>>().join(",")),
+    }
+}
+
+fn signature(name: &str, params: &[Param]) -> String {
+    format!("{}({})", name, params.iter().map(|p| canonical(&p.kind)).collect::<Vec<_>>().join(","))
+}
+
+fn is_dynamic(kind: &Kind) -> bool {
+    match kind {
+        Kind::String | Kind::Bytes | Kind::Array(_) => true,
+        Kind::FixedArray(element, _) => is_dynamic(element),
+        Kind::Tuple(_, components) => components.iter().any(|p| is_dynamic(&p.kind)),
+        _ => false,
+    }
+}
+
+fn rust_type(kind: &Kind) -> String {
+    match kind {
+        Kind::Address => "web3::types::Address".to_string(),
+        Kind::Uint(bits) if *bits <= 64 => "u64".to_string(),
+        Kind::Uint(_) | Kind::Int(_) => "web3::types::U256".to_string(),
+        Kind::Bool => "bool".to_string(),
+        Kind::String => "String".to_string(),
+        Kind::Bytes => "Vec<u8>".to_string(),
+        Kind::FixedBytes(size) => format!("[u8; {}]", size),
+        Kind::Array(element) | Kind::FixedArray(element, _) => format!("Vec<{}>", rust_type(element)),
+        Kind::Tuple(name, _) => format!("crate::bindings::structs::{}", name),
+    }
+}
+
+fn param_type(kind: &Kind) -> String {
+    match kind {
+        Kind::Address => "ethabi::ParamType::Address".to_string(),
+        Kind::Uint(bits) => format!("ethabi::ParamType::Uint({})", bits),
+        Kind::Int(bits) => format!("ethabi::ParamType::Int({})", bits),
+        Kind::Bool => "ethabi::ParamType::Bool".to_string(),
+        Kind::String => "ethabi::ParamType::String".to_string(),
+        Kind::Bytes => "ethabi::ParamType::Bytes".to_string(),
+        Kind::FixedBytes(size) => format!("ethabi::ParamType::FixedBytes({})", size),
+        Kind::Array(element) => format!("ethabi::ParamType::Array(Box::new({}))", param_type(element)),
+        Kind::FixedArray(element, size) => format!("ethabi::ParamType::FixedArray(Box::new({}), {})", param_type(element), size),
+        Kind::Tuple(name, _) => format!("crate::bindings::structs::{}::param_type()", name),
+    }
+}
+
+fn param_types(params: &[&Param]) -> String {
+    params.iter().map(|p| param_type(&p.kind)).collect::<Vec<_>>().join(", ")
+}
+
+/// expression turning the value expr into a Token, depth names the closure variables of nested arrays
+fn to_token(kind: &Kind, expr: &str, depth: usize) -> String {
+    match kind {
+        Kind::Address => format!("ethabi::Token::Address({})", expr),
+        Kind::Uint(bits) if *bits <= 64 => format!("ethabi::Token::Uint({}.into())", expr),
+        Kind::Uint(_) => format!("ethabi::Token::Uint({})", expr),
+        Kind::Int(_) => format!("ethabi::Token::Int({})", expr),
+        Kind::Bool => format!("ethabi::Token::Bool({})", expr),
+        Kind::String => format!("ethabi::Token::String({})", expr),
+        Kind::Bytes => format!("ethabi::Token::Bytes({})", expr),
+        Kind::FixedBytes(_) => format!("ethabi::Token::FixedBytes({}.to_vec())", expr),
+        Kind::Array(element) | Kind::FixedArray(element, _) => {
+            let variant = if matches!(kind, Kind::Array(_)) { "Array" } else { "FixedArray" };
+            let var = format!("v{}", depth);
+            format!("ethabi::Token::{}({}.into_iter().map(|{}| {}).collect())", variant, expr, var, to_token(element, &var, depth + 1))
+        },
+        Kind::Tuple(..) => format!("{}.into_token()", expr),
+    }
+}
+
+/// expression turning the Token expr into Result<value, String>
+fn from_token(kind: &Kind, expr: &str, depth: usize) -> String {
+    let expect = |method: &str, what: &str| format!("{}.{}().ok_or_else(|| \"expected {}\".to_string())", expr, method, what);
+    match kind {
+        Kind::Address => expect("into_address", "address"),
+        Kind::Uint(bits) if *bits <= 64 => format!(
+            "{}.and_then(|v| if v > web3::types::U256::from(u64::MAX) {{ Err(\"uint{} out of range\".to_string()) }} else {{ Ok(v.low_u64()) }})",
+            expect("into_uint", "uint"), bits),
+        Kind::Uint(_) => expect("into_uint", "uint"),
+        Kind::Int(_) => expect("into_int", "int"),
+        Kind::Bool => expect("into_bool", "bool"),
+        Kind::String => expect("into_string", "string"),
+        Kind::Bytes => expect("into_bytes", "bytes"),
+        Kind::FixedBytes(size) => format!(
+            "{}.and_then(|v| <[u8; {}]>::try_from(v.as_slice()).map_err(|_| \"expected bytes{}\".to_string()))",
+            expect("into_fixed_bytes", "fixed bytes"), size, size),
+        Kind::Array(element) | Kind::FixedArray(element, _) => {
+            let method = if matches!(kind, Kind::Array(_)) { "into_array" } else { "into_fixed_array" };
+            let var = format!("v{}", depth);
+            format!("{}.and_then(|v| v.into_iter().map(|{}| {}).collect::<Result<Vec<_>, String>>())",
+                expect(method, "array"), var, from_token(element, &var, depth + 1))
+        },
+        Kind::Tuple(name, _) => format!("crate::bindings::structs::{}::from_token({})", name, expr),
+    }
+}
+
+fn snake(name: &str) -> String {
+    let chars: Vec<char> = name.trim_start_matches('_').chars().collect();
+    let mut out = String::new();
+    for (i, c) in chars.iter().enumerate() {
+        if c.is_uppercase() {
+            let after_lower = i > 0 && (chars[i - 1].is_lowercase() || chars[i - 1].is_ascii_digit());
+            let before_lower = i > 0 && chars[i - 1].is_uppercase() && chars.get(i + 1).map(|n| n.is_lowercase()).unwrap_or(false);
+            if (after_lower || before_lower) && !out.ends_with('_') {
+                out.push('_');
+            }
+            out.extend(c.to_lowercase());
+        } else {
+            out.push(*c);
+        }
+    }
+    if KEYWORDS.contains(&out.as_str()) {
+        out.push('_');
+    }
+    out
+}
+
+fn upper_camel(name: &str) -> String {
+    let name = name.trim_start_matches('_');
+    if name.contains('_') || name.chars().all(|c| !c.is_lowercase()) {
+        //INVALID_PROOF becomes InvalidProof
+        name.split('_').filter(|part| !part.is_empty()).map(|part| {
+            let mut chars = part.chars();
+            chars.next().map(|first| first.to_uppercase().chain(chars.flat_map(|c| c.to_lowercase())).collect::<String>()).unwrap_or_default()
+        }).collect()
+    } else {
+        let mut chars = name.chars();
+        chars.next().map(|first| first.to_uppercase().chain(chars).collect()).unwrap_or_default()
+    }
+}
+
+fn fields(out: &mut String, params: &[Param], indent: &str) {
+    for p in params.iter() {
+        writeln!(out, "{}pub {}: {},", indent, snake(&p.name), rust_type(&p.kind)).unwrap();
+    }
+}
+
+/// statements reading the params, in order, from the token iterator `tokens`
+fn read_fields(out: &mut String, params: &[&Param], context: &str, indent: &str) {
+    for p in params.iter() {
+        writeln!(out, "{}let {} = {}.map_err(|e| format!(\"{} {}: {{}}\", e))?;", indent, snake(&p.name),
+            from_token(&p.kind, &format!("tokens.next().ok_or(\"{} too short\")?", context), 0), context, p.name).unwrap();
+    }
+}
+
+fn field_names(params: &[Param]) -> String {
+    params.iter().map(|p| snake(&p.name)).collect::<Vec<_>>().join(", ")
+}
+
+fn collect_structs(params: &[Param], structs: &mut BTreeMap<String, Vec<Param>>) {
+    for p in params.iter() {
+        let mut kind = &p.kind;
+        while let Kind::Array(element) | Kind::FixedArray(element, _) = kind {
+            kind = element;
+        }
+        if let Kind::Tuple(name, components) = kind {
+            collect_structs(components, structs);
+            structs.insert(name.clone(), components.clone());
+        }
+    }
+}
+
+pub fn generate(entries: &[Value]) -> Result<String, String> {
+    let mut functions = vec![];
+    let mut events = vec![];
+    let mut errors = vec![];
+    let mut structs = BTreeMap::new();
+    let mut seen = HashMap::new();
+    for entry in entries.iter() {
+        let kind = entry["type"].as_str().unwrap_or("function");
+        let name = match entry["name"].as_str() {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        let inputs = parse_params(entry.get("inputs"))?;
+        let outputs = parse_params(entry.get("outputs"))?;
+        //entries repeated across artifacts are generated once
+        if seen.insert((kind.to_string(), signature(&name, &inputs)), ()).is_some() {
+            continue;
+        }
+        collect_structs(&inputs, &mut structs);
+        collect_structs(&outputs, &mut structs);
+        match kind {
+            "function" => functions.push((name, inputs, outputs, entry["stateMutability"].as_str().unwrap_or_default().to_string())),
+            "event" => events.push((name, inputs, entry["anonymous"].as_bool().unwrap_or(false))),
+            "error" => errors.push((name, inputs)),
+            _ => (),
+        }
+    }
+
+    let mut out = String::new();
+    writeln!(out, "// generated by build.rs from the contract abi, do not edit\n").unwrap();
+
+    writeln!(out, "pub mod structs {{\n    use web3::ethabi;").unwrap();
+    for (name, components) in structs.iter() {
+        let refs: Vec<&Param> = components.iter().collect();
+        writeln!(out, "\n    /// `{}`", canonical(&Kind::Tuple(name.clone(), components.clone()))).unwrap();
+        writeln!(out, "    #[derive(Debug, Clone, PartialEq)]\n    pub struct {} {{", name).unwrap();
+        fields(&mut out, components, "        ");
+        writeln!(out, "    }}\n\n    impl {} {{", name).unwrap();
+        writeln!(out, "        pub fn param_type() -> ethabi::ParamType {{\n            ethabi::ParamType::Tuple(vec![{}])\n        }}\n", param_types(&refs)).unwrap();
+        writeln!(out, "        pub fn into_token(self) -> ethabi::Token {{\n            ethabi::Token::Tuple(vec![{}])\n        }}\n",
+            components.iter().map(|p| to_token(&p.kind, &format!("self.{}", snake(&p.name)), 0)).collect::<Vec<_>>().join(", ")).unwrap();
+        writeln!(out, "        pub fn from_token(token: ethabi::Token) -> Result<Self, String> {{").unwrap();
+        writeln!(out, "            let mut tokens = token.into_tuple().ok_or(\"expected tuple {}\")?.into_iter();", name).unwrap();
+        read_fields(&mut out, &refs, name, "            ");
+        writeln!(out, "            Ok({} {{ {} }})\n        }}\n    }}", name, field_names(components)).unwrap();
+    }
+    writeln!(out, "}}\n").unwrap();
+
+    writeln!(out, "pub mod functions {{").unwrap();
+    let mut overloads: HashMap<String, usize> = HashMap::new();
+    for (name, inputs, outputs, mutability) in functions.iter() {
+        let count = overloads.entry(snake(name)).or_insert(0);
+        let module = if *count == 0 { snake(name) } else { format!("{}_{}", snake(name), count) };
+        *count += 1;
+        let input_refs: Vec<&Param> = inputs.iter().collect();
+        let output_refs: Vec<&Param> = outputs.iter().collect();
+        writeln!(out, "\n    /// `{}`, {}\n    pub mod {} {{\n        use web3::ethabi;\n", signature(name, inputs), mutability, module).unwrap();
+        writeln!(out, "        pub const NAME: &str = \"{}\";\n        pub const SIGNATURE: &str = \"{}\";\n", name, signature(name, inputs)).unwrap();
+        writeln!(out, "        pub fn selector() -> [u8; 4] {{\n            ethabi::short_signature(NAME, &[{}])\n        }}\n", param_types(&input_refs)).unwrap();
+        let args = inputs.iter().map(|p| format!("{}: {}", snake(&p.name), rust_type(&p.kind))).collect::<Vec<_>>().join(", ");
+        writeln!(out, "        pub fn encode_input({}) -> Vec<u8> {{", args).unwrap();
+        writeln!(out, "            let tokens = vec![{}];", inputs.iter().map(|p| to_token(&p.kind, &snake(&p.name), 0)).collect::<Vec<_>>().join(", ")).unwrap();
+        writeln!(out, "            let mut data = selector().to_vec();\n            data.extend(ethabi::encode(&tokens));\n            data\n        }}\n").unwrap();
+        let output_type = match outputs.len() {
+            0 => "()".to_string(),
+            1 => rust_type(&outputs[0].kind),
+            _ => format!("({})", outputs.iter().map(|p| rust_type(&p.kind)).collect::<Vec<_>>().join(", ")),
+        };
+        writeln!(out, "        pub fn decode_output(data: &[u8]) -> Result<{}, String> {{", output_type).unwrap();
+        writeln!(out, "            let tokens = ethabi::decode(&[{}], data).map_err(|e| format!(\"decode {{}} output: {{}}\", SIGNATURE, e))?;", param_types(&output_refs)).unwrap();
+        writeln!(out, "            let mut tokens = tokens.into_iter();").unwrap();
+        read_fields(&mut out, &output_refs, name, "            ");
+        let names = outputs.iter().map(|p| snake(&p.name)).collect::<Vec<_>>();
+        writeln!(out, "            Ok({})\n        }}\n    }}", if names.len() == 1 { names[0].clone() } else { format!("({})", names.join(", ")) }).unwrap();
+    }
+    writeln!(out, "}}\n").unwrap();
+
+    writeln!(out, "pub mod events {{\n    use web3::ethabi;\n    use web3::types::H256;").unwrap();
+    for (name, inputs, anonymous) in events.iter() {
+        //indexed dynamic values are only there as their hash
+        let params: Vec<Param> = inputs.iter().map(|p| if p.indexed && is_dynamic(&p.kind) {
+            Param { kind: Kind::FixedBytes(32), ..p.clone() }
+        } else {
+            p.clone()
+        }).collect();
+        let indexed: Vec<&Param> = params.iter().filter(|p| p.indexed).collect();
+        let data: Vec<&Param> = params.iter().filter(|p| !p.indexed).collect();
+        let first_topic = if *anonymous { 0 } else { 1 };
+        writeln!(out, "\n    /// `{}`\n    #[derive(Debug, Clone, PartialEq)]\n    pub struct {} {{", signature(name, inputs), upper_camel(name)).unwrap();
+        fields(&mut out, &params, "        ");
+        writeln!(out, "    }}\n\n    impl {} {{", upper_camel(name)).unwrap();
+        writeln!(out, "        pub const NAME: &'static str = \"{}\";\n        pub const SIGNATURE: &'static str = \"{}\";\n", name, signature(name, inputs)).unwrap();
+        writeln!(out, "        pub fn topic() -> H256 {{\n            H256::from(web3::signing::keccak256(Self::SIGNATURE.as_bytes()))\n        }}\n").unwrap();
+        writeln!(out, "        pub fn decode(topics: &[H256], data: &[u8]) -> Result<Self, String> {{").unwrap();
+        if !anonymous {
+            writeln!(out, "            if topics.first() != Some(&Self::topic()) {{\n                return Err(format!(\"not a {{}} log\", Self::NAME));\n            }}").unwrap();
+        }
+        writeln!(out, "            if topics.len() != {} {{\n                return Err(format!(\"{{}} log with {{}} topics\", Self::NAME, topics.len()));\n            }}", first_topic + indexed.len()).unwrap();
+        writeln!(out, "            let mut tokens = vec![];").unwrap();
+        for (i, p) in indexed.iter().enumerate() {
+            writeln!(out, "            tokens.extend(ethabi::decode(&[{}], topics[{}].as_bytes()).map_err(|e| format!(\"decode {} {}: {{}}\", e))?);",
+                param_type(&p.kind), first_topic + i, name, p.name).unwrap();
+        }
+        writeln!(out, "            let mut tokens = tokens.into_iter();").unwrap();
+        read_fields(&mut out, &indexed, name, "            ");
+        writeln!(out, "            let mut tokens = ethabi::decode(&[{}], data).map_err(|e| format!(\"decode {} data: {{}}\", e))?.into_iter();",
+            param_types(&data), name).unwrap();
+        read_fields(&mut out, &data, name, "            ");
+        writeln!(out, "            Ok({} {{ {} }})\n        }}\n    }}", upper_camel(name), field_names(&params)).unwrap();
+    }
+    writeln!(out, "}}\n").unwrap();
+
+    writeln!(out, "pub mod errors {{\n    use web3::ethabi;").unwrap();
+    for (name, inputs) in errors.iter() {
+        let refs: Vec<&Param> = inputs.iter().collect();
+        writeln!(out, "\n    /// `{}`\n    #[derive(Debug, Clone, PartialEq)]\n    pub struct {} {{", signature(name, inputs), upper_camel(name)).unwrap();
+        fields(&mut out, inputs, "        ");
+        writeln!(out, "    }}\n\n    impl {} {{", upper_camel(name)).unwrap();
+        writeln!(out, "        pub const NAME: &'static str = \"{}\";\n        pub const SIGNATURE: &'static str = \"{}\";\n", name, signature(name, inputs)).unwrap();
+        writeln!(out, "        pub fn selector() -> [u8; 4] {{\n            ethabi::short_signature(Self::NAME, &[{}])\n        }}\n", param_types(&refs)).unwrap();
+        writeln!(out, "        /// the arguments following the selector\n        pub fn decode(data: &[u8]) -> Result<Self, String> {{").unwrap();
+        writeln!(out, "            let mut tokens = ethabi::decode(&[{}], data).map_err(|e| format!(\"decode {} arguments: {{}}\", e))?.into_iter();", param_types(&refs), name).unwrap();
+        read_fields(&mut out, &refs, name, "            ");
+        writeln!(out, "            Ok({} {{ {} }})\n        }}\n    }}", upper_camel(name), field_names(inputs)).unwrap();
+    }
+    writeln!(out, "\n    /// any custom error of the contract\n    #[derive(Debug, Clone, PartialEq)]\n    pub enum ContractError {{").unwrap();
+    for (name, _) in errors.iter() {
+        writeln!(out, "        {}({}),", upper_camel(name), upper_camel(name)).unwrap();
+    }
+    writeln!(out, "    }}\n\n    impl ContractError {{\n        /// the custom error in revert data, None for other reverts").unwrap();
+    writeln!(out, "        pub fn decode(revert: &[u8]) -> Option<Self> {{\n            if revert.len() < 4 {{\n                return None;\n            }}").unwrap();
+    writeln!(out, "            let (selector, data) = revert.split_at(4);").unwrap();
+    for (name, _) in errors.iter() {
+        writeln!(out, "            if selector == {}::selector() {{\n                return {}::decode(data).ok().map(ContractError::{});\n            }}",
+            upper_camel(name), upper_camel(name), upper_camel(name)).unwrap();
+    }
+    writeln!(out, "            None\n        }}\n    }}\n").unwrap();
+    //an abi without custom errors leaves the enum empty, matched by dereferencing
+    let scrutinee = if errors.is_empty() { "*self" } else { "self" };
+    writeln!(out, "    impl std::fmt::Display for ContractError {{\n        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {{\n            match {} {{", scrutinee).unwrap();
+    for (name, _) in errors.iter() {
+        writeln!(out, "                ContractError::{}(e) => write!(f, \"{{:?}}\", e),", upper_camel(name)).unwrap();
+    }
+    writeln!(out, "            }}\n        }}\n    }}\n}}").unwrap();
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn snake_names() {
+        assert_eq!(snake("proveTask"), "prove_task");
+        assert_eq!(snake("readProofStatus"), "read_proof_status");
+        assert_eq!(snake("taskKey"), "task_key");
+        assert_eq!(snake("_owner"), "owner");
+        assert_eq!(snake("ERC20Token"), "erc20_token");
+        assert_eq!(snake("uint64Value"), "uint64_value");
+        assert_eq!(snake("type"), "type_");
+        assert_eq!(snake("already_snake"), "already_snake");
+    }
+
+    #[test]
+    fn upper_camel_names() {
+        assert_eq!(upper_camel("TaskNoneExist"), "TaskNoneExist");
+        assert_eq!(upper_camel("taskSubmitted"), "TaskSubmitted");
+        assert_eq!(upper_camel("INVALID_PROOF"), "InvalidProof");
+        assert_eq!(upper_camel("_reentrant_call"), "ReentrantCall");
+        assert_eq!(upper_camel("OWNER"), "Owner");
+    }
+
+    #[test]
+    fn canonical_tuples() {
+        let item = json!({
+            "type": "tuple[]",
+            "internalType": "struct Pool.Quote[]",
+            "components": [
+                {"name": "prover", "type": "address"},
+                {"name": "terms", "type": "tuple", "internalType": "struct Pool.Terms", "components": [
+                    {"name": "window", "type": "uint64"},
+                    {"name": "instances", "type": "bytes[2]"},
+                ]},
+            ],
+        });
+        let kind = parse_kind("tuple[]", &item).unwrap();
+        assert_eq!(canonical(&kind), "(address,(uint64,bytes[2]))[]");
+        match &kind {
+            Kind::Array(element) => match element.as_ref() {
+                Kind::Tuple(name, components) => {
+                    assert_eq!(name, "Quote");
+                    assert!(matches!(&components[1].kind, Kind::Tuple(inner, _) if inner == "Terms"));
+                },
+                other => panic!("expected a tuple, got {:?}", other),
+            },
+            other => panic!("expected an array, got {:?}", other),
+        }
+        let params = parse_params(Some(&json!([item, {"name": "", "type": "uint256"}]))).unwrap();
+        assert_eq!(params[1].name, "arg1");
+        assert_eq!(signature("submit", &params), "submit((address,(uint64,bytes[2]))[],uint256)");
+    }
+
+    #[test]
+    fn unsupported_types() {
+        assert!(parse_kind("fixed128x18", &json!({})).is_err());
+        assert!(parse_kind("uint256[x]", &json!({})).is_err());
+    }
+
+    #[test]
+    fn indexed_dynamic_event_fields_are_hashes() {
+        let abi = json!([{
+            "type": "event",
+            "name": "Named",
+            "anonymous": false,
+            "inputs": [
+                {"name": "label", "type": "string", "indexed": true},
+                {"name": "owner", "type": "address", "indexed": true},
+                {"name": "data", "type": "bytes", "indexed": false},
+            ],
+        }]);
+        let out = generate(abi.as_array().unwrap()).unwrap();
+        assert!(out.contains("pub label: [u8; 32],"));
+        assert!(out.contains("pub const SIGNATURE: &'static str = \"Named(string,address,bytes)\";"));
+        assert!(out.contains("if topics.len() != 3 {"));
+    }
+
+    #[test]
+    fn abi_without_errors_matches_empty_enum() {
+        let abi = json!([{"type": "function", "name": "owner", "inputs": [], "outputs": [{"name": "", "type": "address"}]}]);
+        let out = generate(abi.as_array().unwrap()).unwrap();
+        assert!(out.contains("pub enum ContractError {\n    }"));
+        assert!(out.contains("match *self {\n            }"));
+    }
+
+    #[test]
+    fn repeated_entries_generated_once() {
+        let entry = json!({"type": "function", "name": "owner", "inputs": [], "outputs": []});
+        let out = generate(&[entry.clone(), entry]).unwrap();
+        assert_eq!(out.matches("pub mod owner {").count(), 1);
+    }
+}
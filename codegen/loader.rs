@@ -0,0 +1,54 @@
+// This file is part of the aoraki-labs library.
+
+// The aoraki-labs library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// The aoraki-labs library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with the aoraki-labs library. If not, see <https://www.gnu.org/licenses/>.
+
+//! reads contract ABI JSON, shared by build.rs (generating the bindings) and bindings::load_abi (checking an abi at startup)
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde_json::Value;
+
+/// the JSON files of an abi path: the file itself, or every JSON file of a directory in name order
+pub fn abi_files(path: &Path) -> Result<Vec<PathBuf>, String> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files = fs::read_dir(path).map_err(|e| format!("read abi dir {}: {}", path.display(), e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|file| file.extension().map(|ext| ext == "json").unwrap_or(false))
+        .collect::<Vec<_>>();
+    files.sort();
+    Ok(files)
+}
+
+/// the ABI entries of the files, each a plain ABI array or a build artifact with an "abi" field
+pub fn load_entries(files: &[PathBuf]) -> Result<Vec<Value>, String> {
+    let mut entries = vec![];
+    for file in files.iter() {
+        let content = fs::read_to_string(file).map_err(|e| format!("read {}: {}", file.display(), e))?;
+        let json: Value = serde_json::from_str(&content).map_err(|e| format!("parse {}: {}", file.display(), e))?;
+        match json {
+            Value::Array(items) => entries.extend(items),
+            Value::Object(mut artifact) => match artifact.remove("abi") {
+                Some(Value::Array(items)) => entries.extend(items),
+                _ => return Err(format!("{} has no abi array", file.display())),
+            },
+            _ => return Err(format!("{} is not an abi", file.display())),
+        }
+    }
+    if entries.is_empty() {
+        return Err("no abi entries found".to_string());
+    }
+    Ok(entries)
+}
@@ -0,0 +1,15 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE small_proofs ALTER COLUMN update_time DROP DEFAULT;
+ALTER TABLE small_proofs ALTER COLUMN create_time DROP DEFAULT;
+ALTER TABLE big_proofs ALTER COLUMN update_time DROP DEFAULT;
+ALTER TABLE big_proofs ALTER COLUMN create_time DROP DEFAULT;
+
+ALTER TABLE small_proofs DROP CONSTRAINT small_proofs_project_id_task_id_fkey;
+ALTER TABLE small_proofs DROP CONSTRAINT small_proofs_project_id_task_id_task_split_id_key;
+ALTER TABLE big_proofs DROP CONSTRAINT big_proofs_project_id_task_id_key;
+
+ALTER TABLE big_proofs ADD CONSTRAINT big_proofs_task_id_key UNIQUE (task_id);
+ALTER TABLE big_proofs ADD CONSTRAINT big_proofs_project_id_key UNIQUE (project_id);
+ALTER TABLE small_proofs ADD CONSTRAINT small_proofs_project_id_fkey FOREIGN KEY (project_id) REFERENCES big_proofs (project_id);
+ALTER TABLE small_proofs ADD CONSTRAINT small_proofs_task_id_fkey FOREIGN KEY (task_id) REFERENCES big_proofs (task_id);
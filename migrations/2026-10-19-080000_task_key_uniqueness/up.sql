@@ -0,0 +1,18 @@
+-- A project has many tasks: make (project_id, task_id) the task key instead of
+-- each column being unique on its own, and point small_proofs at it.
+
+ALTER TABLE small_proofs DROP CONSTRAINT small_proofs_task_id_fkey;
+ALTER TABLE small_proofs DROP CONSTRAINT small_proofs_project_id_fkey;
+ALTER TABLE big_proofs DROP CONSTRAINT big_proofs_project_id_key;
+ALTER TABLE big_proofs DROP CONSTRAINT big_proofs_task_id_key;
+
+ALTER TABLE big_proofs ADD CONSTRAINT big_proofs_project_id_task_id_key UNIQUE (project_id, task_id);
+ALTER TABLE small_proofs ADD CONSTRAINT small_proofs_project_id_task_id_task_split_id_key UNIQUE (project_id, task_id, task_split_id);
+ALTER TABLE small_proofs ADD CONSTRAINT small_proofs_project_id_task_id_fkey
+    FOREIGN KEY (project_id, task_id) REFERENCES big_proofs (project_id, task_id);
+
+-- rows are inserted without timestamps
+ALTER TABLE big_proofs ALTER COLUMN create_time SET DEFAULT CURRENT_TIMESTAMP;
+ALTER TABLE big_proofs ALTER COLUMN update_time SET DEFAULT CURRENT_TIMESTAMP;
+ALTER TABLE small_proofs ALTER COLUMN create_time SET DEFAULT CURRENT_TIMESTAMP;
+ALTER TABLE small_proofs ALTER COLUMN update_time SET DEFAULT CURRENT_TIMESTAMP;
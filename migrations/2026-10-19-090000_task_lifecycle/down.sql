@@ -0,0 +1,27 @@
+-- This file should undo anything in `up.sql`
+
+DROP TRIGGER IF EXISTS task_events_append_only ON task_events;
+DROP FUNCTION IF EXISTS reject_task_events_change();
+DROP TRIGGER IF EXISTS small_proofs_update_time ON small_proofs;
+DROP TRIGGER IF EXISTS big_proofs_update_time ON big_proofs;
+DROP FUNCTION IF EXISTS set_update_time();
+
+DROP TABLE IF EXISTS task_events;
+
+ALTER TABLE small_proofs
+    DROP COLUMN dispatch_time,
+    DROP COLUMN attempts;
+
+ALTER TABLE big_proofs
+    DROP COLUMN attempts,
+    DROP COLUMN prove_tx_hash,
+    DROP COLUMN submit_tx_hash,
+    DROP COLUMN deadline,
+    DROP COLUMN liability_window,
+    DROP COLUMN liability_amount,
+    DROP COLUMN liability_token,
+    DROP COLUMN reward_amount,
+    DROP COLUMN reward_token,
+    DROP COLUMN prover,
+    DROP COLUMN requester,
+    DROP COLUMN instance;
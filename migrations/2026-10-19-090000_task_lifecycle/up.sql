@@ -0,0 +1,59 @@
+-- Task terms as seen in TaskSubmitted, submission bookkeeping, and an
+-- append-only task_events log of every status transition.
+
+ALTER TABLE big_proofs
+    ADD COLUMN instance VARCHAR NOT NULL DEFAULT '',
+    ADD COLUMN requester VARCHAR NOT NULL DEFAULT '',
+    ADD COLUMN prover VARCHAR NOT NULL DEFAULT '',
+    ADD COLUMN reward_token VARCHAR NOT NULL DEFAULT '',
+    ADD COLUMN reward_amount VARCHAR NOT NULL DEFAULT '0', -- uint256 as decimal string
+    ADD COLUMN liability_token VARCHAR NOT NULL DEFAULT '',
+    ADD COLUMN liability_amount VARCHAR NOT NULL DEFAULT '0', -- uint256 as decimal string
+    ADD COLUMN liability_window BIGINT NOT NULL DEFAULT 0, -- seconds
+    ADD COLUMN deadline TIMESTAMP,
+    ADD COLUMN submit_tx_hash VARCHAR, -- the requester's submitTask tx
+    ADD COLUMN prove_tx_hash VARCHAR, -- our proveTask tx
+    ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0; -- proveTask submissions
+
+ALTER TABLE small_proofs
+    ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0, -- dispatches to the scheduler
+    ADD COLUMN dispatch_time TIMESTAMP;
+
+CREATE TABLE task_events (
+    id BIGSERIAL PRIMARY KEY,
+    project_id VARCHAR NOT NULL,
+    task_id VARCHAR NOT NULL,
+    task_split_id VARCHAR, -- NULL for whole task events
+    from_status VARCHAR, -- NULL when the task or segment is created
+    to_status VARCHAR NOT NULL,
+    detail VARCHAR,
+    create_time TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (project_id, task_id) REFERENCES big_proofs (project_id, task_id)
+);
+CREATE INDEX task_events_task_idx ON task_events (project_id, task_id);
+
+CREATE OR REPLACE FUNCTION set_update_time() RETURNS trigger AS $$
+BEGIN
+    IF (
+        NEW IS DISTINCT FROM OLD AND
+        NEW.update_time IS NOT DISTINCT FROM OLD.update_time
+    ) THEN
+        NEW.update_time := current_timestamp;
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER big_proofs_update_time BEFORE UPDATE ON big_proofs
+    FOR EACH ROW EXECUTE PROCEDURE set_update_time();
+CREATE TRIGGER small_proofs_update_time BEFORE UPDATE ON small_proofs
+    FOR EACH ROW EXECUTE PROCEDURE set_update_time();
+
+CREATE OR REPLACE FUNCTION reject_task_events_change() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'task_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER task_events_append_only BEFORE UPDATE OR DELETE ON task_events
+    FOR EACH ROW EXECUTE PROCEDURE reject_task_events_change();
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+
+DROP TRIGGER IF EXISTS quotes_update_time ON quotes;
+DROP TABLE IF EXISTS quotes;
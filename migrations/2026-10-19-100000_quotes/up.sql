@@ -0,0 +1,23 @@
+-- Every quote signed in ReceiveTask, matched against TaskSubmitted.
+
+CREATE TABLE quotes (
+    id BIGSERIAL PRIMARY KEY,
+    quote_hash VARCHAR NOT NULL UNIQUE, -- the signed hash, legacy or EIP-712 digest
+    prover VARCHAR NOT NULL,
+    instance VARCHAR NOT NULL, -- hex of the instance bytes, as in TaskSubmitted
+    reward_token VARCHAR NOT NULL,
+    reward_amount VARCHAR NOT NULL, -- uint256 as decimal string
+    liability_token VARCHAR NOT NULL,
+    liability_amount VARCHAR NOT NULL, -- uint256 as decimal string
+    liability_window BIGINT NOT NULL, -- seconds
+    expiry BIGINT NOT NULL, -- block number
+    signature VARCHAR NOT NULL,
+    status VARCHAR NOT NULL, -- outstanding, matched or expired
+    task_id VARCHAR, -- the task key of the matching TaskSubmitted
+    create_time TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    update_time TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX quotes_status_idx ON quotes (status, expiry);
+
+CREATE TRIGGER quotes_update_time BEFORE UPDATE ON quotes
+    FOR EACH ROW EXECUTE PROCEDURE set_update_time();
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE IF EXISTS small_proofs;
+DROP TABLE IF EXISTS big_proofs;
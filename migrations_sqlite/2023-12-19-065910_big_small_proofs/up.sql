@@ -0,0 +1,24 @@
+-- sqlite version of migrations/2023-12-19-065910_big_small_proofs
+
+CREATE TABLE big_proofs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    project_id TEXT NOT NULL,
+    task_id TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'created',
+    create_time TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    update_time TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (project_id, task_id)
+);
+
+CREATE TABLE small_proofs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    project_id TEXT NOT NULL,
+    task_id TEXT NOT NULL,
+    task_split_id TEXT NOT NULL,
+    task_percentage DOUBLE NOT NULL CHECK (task_percentage >= 0 AND task_percentage <= 1),
+    status TEXT NOT NULL DEFAULT 'created',
+    create_time TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    update_time TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (project_id, task_id, task_split_id),
+    FOREIGN KEY (project_id, task_id) REFERENCES big_proofs (project_id, task_id)
+);
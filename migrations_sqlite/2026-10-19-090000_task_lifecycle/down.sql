@@ -0,0 +1,24 @@
+-- This file should undo anything in `up.sql`
+
+DROP TRIGGER IF EXISTS task_events_no_delete;
+DROP TRIGGER IF EXISTS task_events_no_update;
+DROP TRIGGER IF EXISTS small_proofs_update_time;
+DROP TRIGGER IF EXISTS big_proofs_update_time;
+
+DROP TABLE IF EXISTS task_events;
+
+ALTER TABLE small_proofs DROP COLUMN dispatch_time;
+ALTER TABLE small_proofs DROP COLUMN attempts;
+
+ALTER TABLE big_proofs DROP COLUMN attempts;
+ALTER TABLE big_proofs DROP COLUMN prove_tx_hash;
+ALTER TABLE big_proofs DROP COLUMN submit_tx_hash;
+ALTER TABLE big_proofs DROP COLUMN deadline;
+ALTER TABLE big_proofs DROP COLUMN liability_window;
+ALTER TABLE big_proofs DROP COLUMN liability_amount;
+ALTER TABLE big_proofs DROP COLUMN liability_token;
+ALTER TABLE big_proofs DROP COLUMN reward_amount;
+ALTER TABLE big_proofs DROP COLUMN reward_token;
+ALTER TABLE big_proofs DROP COLUMN prover;
+ALTER TABLE big_proofs DROP COLUMN requester;
+ALTER TABLE big_proofs DROP COLUMN instance;
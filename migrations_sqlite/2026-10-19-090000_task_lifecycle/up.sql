@@ -0,0 +1,52 @@
+-- sqlite version of migrations/2026-10-19-090000_task_lifecycle
+
+ALTER TABLE big_proofs ADD COLUMN instance TEXT NOT NULL DEFAULT '';
+ALTER TABLE big_proofs ADD COLUMN requester TEXT NOT NULL DEFAULT '';
+ALTER TABLE big_proofs ADD COLUMN prover TEXT NOT NULL DEFAULT '';
+ALTER TABLE big_proofs ADD COLUMN reward_token TEXT NOT NULL DEFAULT '';
+ALTER TABLE big_proofs ADD COLUMN reward_amount TEXT NOT NULL DEFAULT '0';
+ALTER TABLE big_proofs ADD COLUMN liability_token TEXT NOT NULL DEFAULT '';
+ALTER TABLE big_proofs ADD COLUMN liability_amount TEXT NOT NULL DEFAULT '0';
+ALTER TABLE big_proofs ADD COLUMN liability_window BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE big_proofs ADD COLUMN deadline TIMESTAMP;
+ALTER TABLE big_proofs ADD COLUMN submit_tx_hash TEXT;
+ALTER TABLE big_proofs ADD COLUMN prove_tx_hash TEXT;
+ALTER TABLE big_proofs ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0;
+
+ALTER TABLE small_proofs ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE small_proofs ADD COLUMN dispatch_time TIMESTAMP;
+
+CREATE TABLE task_events (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    project_id TEXT NOT NULL,
+    task_id TEXT NOT NULL,
+    task_split_id TEXT,
+    from_status TEXT,
+    to_status TEXT NOT NULL,
+    detail TEXT,
+    create_time TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (project_id, task_id) REFERENCES big_proofs (project_id, task_id)
+);
+CREATE INDEX task_events_task_idx ON task_events (project_id, task_id);
+
+CREATE TRIGGER big_proofs_update_time AFTER UPDATE ON big_proofs
+    FOR EACH ROW WHEN NEW.update_time IS OLD.update_time
+BEGIN
+    UPDATE big_proofs SET update_time = CURRENT_TIMESTAMP WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER small_proofs_update_time AFTER UPDATE ON small_proofs
+    FOR EACH ROW WHEN NEW.update_time IS OLD.update_time
+BEGIN
+    UPDATE small_proofs SET update_time = CURRENT_TIMESTAMP WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER task_events_no_update BEFORE UPDATE ON task_events
+BEGIN
+    SELECT RAISE(ABORT, 'task_events is append-only');
+END;
+
+CREATE TRIGGER task_events_no_delete BEFORE DELETE ON task_events
+BEGIN
+    SELECT RAISE(ABORT, 'task_events is append-only');
+END;
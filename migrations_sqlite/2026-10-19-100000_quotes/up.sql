@@ -0,0 +1,26 @@
+-- sqlite version of migrations/2026-10-19-100000_quotes
+
+CREATE TABLE quotes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    quote_hash TEXT NOT NULL UNIQUE,
+    prover TEXT NOT NULL,
+    instance TEXT NOT NULL,
+    reward_token TEXT NOT NULL,
+    reward_amount TEXT NOT NULL,
+    liability_token TEXT NOT NULL,
+    liability_amount TEXT NOT NULL,
+    liability_window BIGINT NOT NULL,
+    expiry BIGINT NOT NULL,
+    signature TEXT NOT NULL,
+    status TEXT NOT NULL,
+    task_id TEXT,
+    create_time TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    update_time TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+CREATE INDEX quotes_status_idx ON quotes (status, expiry);
+
+CREATE TRIGGER quotes_update_time AFTER UPDATE ON quotes
+    FOR EACH ROW WHEN NEW.update_time IS OLD.update_time
+BEGIN
+    UPDATE quotes SET update_time = CURRENT_TIMESTAMP WHERE id = NEW.id;
+END;
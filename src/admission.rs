@@ -0,0 +1,243 @@
+// This file is part of the aoraki-labs library.
+
+// The aoraki-labs library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// The aoraki-labs library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with the aoraki-labs library. If not, see <https://www.gnu.org/licenses/>.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use lazy_static::lazy_static;
+use log::*;
+
+use crate::chain::{SEG_NUM, in_flight_tasks, in_flight_segments};
+use crate::scheduler::total_capacity;
+
+pub const DEFAULT_RETRY_AFTER: u64 = 60;      //seconds, until a segment time has been observed
+pub const CAPACITY_CACHE_SECS: u64 = 5;       //an older answer is still used while a fresh one is asked for
+pub const CAPACITY_TIMEOUT_SECS: u64 = 2;     //only the first query is waited for
+const SEGMENT_TIME_WEIGHT: f64 = 0.2;         //weight of the newest segment in the moving average
+
+lazy_static! {
+    pub static ref ADMISSION: tokio::sync::Mutex<AdmissionConfig> = {
+        tokio::sync::Mutex::new(AdmissionConfig::default())
+    };
+    pub static ref SEGMENT_PROVE_SECS: tokio::sync::Mutex<Option<f64>> = {   //moving average dispatch to proven
+        tokio::sync::Mutex::new(None)
+    };
+    pub static ref SCHEDULER_CAPACITY: tokio::sync::Mutex<Option<(Instant, Option<u64>)>> = {   //last capacity answer
+        tokio::sync::Mutex::new(None)
+    };
+}
+
+static CAPACITY_REFRESHING: AtomicBool = AtomicBool::new(false);   //a capacity query runs in the background
+
+/// limits on the work the relayer takes on, all off by default
+#[derive(Debug, Clone, Default)]
+pub struct AdmissionConfig {
+    pub max_tasks: Option<usize>,
+    pub max_segments: Option<usize>,
+    /// scheduler JSON-RPC method answering the number of free segment slots
+    pub capacity_method: Option<String>,
+}
+
+/// a refusal, with the seconds after which asking again may succeed
+#[derive(Debug)]
+pub struct Refusal {
+    pub reason: String,
+    pub retry_after: u64,
+}
+
+pub async fn set_admission(config: AdmissionConfig) {
+    info!("admission control {:?}", config);
+    let mut current = ADMISSION.lock().await;
+    *current = config;
+}
+
+/// feed the time a segment took from dispatch to proven into the moving average
+pub async fn record_segment_time(secs: f64) {
+    let mut average = SEGMENT_PROVE_SECS.lock().await;
+    *average = Some(match *average {
+        Some(avg) => avg * (1.0 - SEGMENT_TIME_WEIGHT) + secs * SEGMENT_TIME_WEIGHT,
+        None => secs,
+    });
+}
+
+async fn retry_after() -> u64 {
+    SEGMENT_PROVE_SECS.lock().await
+        .map(|secs| secs.ceil() as u64)
+        .unwrap_or(DEFAULT_RETRY_AFTER)
+        .max(1)
+}
+
+/// free segment slots reported by the scheduler, None when not configured or unreachable.
+/// only the first query is waited for, after that the last answer is used and refreshed in the background once stale
+pub async fn scheduler_capacity() -> Option<u64> {
+    let method = ADMISSION.lock().await.capacity_method.clone()?;
+    let cached = *SCHEDULER_CAPACITY.lock().await;
+    match cached {
+        Some((at, capacity)) => {
+            if at.elapsed().as_secs() >= CAPACITY_CACHE_SECS && !CAPACITY_REFRESHING.swap(true, Ordering::SeqCst) {
+                tokio::spawn(async move {
+                    refresh_capacity(&method).await;
+                    CAPACITY_REFRESHING.store(false, Ordering::SeqCst);
+                });
+            }
+            capacity
+        },
+        None => refresh_capacity(&method).await,
+    }
+}
+
+async fn refresh_capacity(method: &str) -> Option<u64> {
+    let query = tokio::time::timeout(Duration::from_secs(CAPACITY_TIMEOUT_SECS), total_capacity("demo", method));
+    let capacity = match query.await {
+        Ok(Ok(r)) => Some(r),
+        Ok(Err(e)) => {
+            error!("query scheduler capacity: {}", e);
+            None
+        },
+        Err(_) => {
+            error!("query scheduler capacity: no answer in {}s", CAPACITY_TIMEOUT_SECS);
+            None
+        },
+    };
+    *SCHEDULER_CAPACITY.lock().await = Some((Instant::now(), capacity));
+    capacity
+}
+
+/// whether the scheduler can take one more task's segments now
+pub async fn admit_dispatch() -> Result<(), Refusal> {
+    match scheduler_capacity().await {
+        Some(free) if free < SEG_NUM as u64 => Err(Refusal {
+            reason: format!("scheduler has {} free segment slots", free),
+            retry_after: retry_after().await,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// what admission looks at when a quote comes in
+#[derive(Debug, Clone, Copy, Default)]
+struct Load {
+    tasks: usize,
+    segments: usize,
+    /// free scheduler slots, None when not configured or unreachable
+    free: Option<u64>,
+    /// moving average of a segment's proving time, None until one was proven
+    segment_secs: Option<f64>,
+}
+
+/// whether a new quote with this liability window can be honoured: under the task and segment
+/// limits, the scheduler has room, and the queued segments are expected to finish in the window
+pub async fn admit_quote(liability_window: u64) -> Result<(), Refusal> {
+    let config = ADMISSION.lock().await.clone();
+    let load = Load {
+        tasks: in_flight_tasks().await,
+        segments: in_flight_segments().await,
+        free: scheduler_capacity().await,
+        segment_secs: *SEGMENT_PROVE_SECS.lock().await,
+    };
+    admit(&config, load, liability_window, retry_after().await)
+}
+
+fn admit(config: &AdmissionConfig, load: Load, liability_window: u64, retry_after: u64) -> Result<(), Refusal> {
+    let Load { tasks, segments, free, segment_secs } = load;
+    let refuse = |reason: String, retry_after: u64| Err(Refusal { reason, retry_after });
+
+    if let Some(max_tasks) = config.max_tasks {
+        if tasks >= max_tasks {
+            return refuse(format!("{} tasks in flight, limit {}", tasks, max_tasks), retry_after);
+        }
+    }
+    if let Some(max_segments) = config.max_segments {
+        if segments + SEG_NUM as usize > max_segments {
+            return refuse(format!("{} segments in flight, limit {}", segments, max_segments), retry_after);
+        }
+    }
+    if let Some(free) = free {
+        if free < SEG_NUM as u64 {
+            return refuse(format!("scheduler has {} free segment slots", free), retry_after);
+        }
+    }
+
+    //segments run in rounds of the parallel slots, each round taking about one segment time
+    let parallel = match (free, config.max_segments) {
+        (Some(free), _) => free as usize + segments,
+        (None, Some(max_segments)) => max_segments,
+        (None, None) => return Ok(()),
+    };
+    if let Some(segment_secs) = segment_secs {
+        let rounds = (segments + SEG_NUM as usize).div_ceil(parallel.max(1));
+        let estimate = (rounds as f64 * segment_secs).ceil() as u64;
+        if estimate > liability_window {
+            return refuse(
+                format!("expected completion in {}s exceeds liability window {}s", estimate, liability_window),
+                segment_secs.ceil() as u64,
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reason(result: Result<(), Refusal>) -> String {
+        result.map(|_| String::new()).unwrap_or_else(|refusal| refusal.reason)
+    }
+
+    #[test]
+    fn task_and_segment_limits() {
+        let config = AdmissionConfig { max_tasks: Some(2), max_segments: Some(8), capacity_method: None };
+        let load = Load { tasks: 1, segments: 4, ..Default::default() };
+        assert!(admit(&config, load, 600, 60).is_ok());
+        assert_eq!(reason(admit(&config, Load { tasks: 2, ..load }, 600, 60)), "2 tasks in flight, limit 2");
+        let refusal = admit(&config, Load { segments: 5, ..load }, 600, 60).unwrap_err();
+        assert_eq!((refusal.reason.as_str(), refusal.retry_after), ("5 segments in flight, limit 8", 60));
+        //no limits, no refusal
+        assert!(admit(&AdmissionConfig::default(), Load { tasks: 1000, segments: 4000, ..load }, 1, 60).is_ok());
+    }
+
+    #[test]
+    fn scheduler_room_and_liability_window() {
+        let config = AdmissionConfig::default();
+        let load = Load { segments: 8, free: Some(SEG_NUM as u64), segment_secs: Some(99.5), ..Default::default() };
+        assert_eq!(reason(admit(&config, Load { free: Some(3), ..load }, 600, 60)), "scheduler has 3 free segment slots");
+        //the queued segments and the task's run side by side in the free slots, one segment time
+        assert!(admit(&config, load, 100, 60).is_ok());
+        let refusal = admit(&config, load, 99, 60).unwrap_err();
+        assert_eq!((refusal.reason.as_str(), refusal.retry_after), ("expected completion in 100s exceeds liability window 99s", 100));
+        //without timings or any limit the window is not checked
+        assert!(admit(&config, Load { segment_secs: None, ..load }, 1, 60).is_ok());
+        assert!(admit(&config, Load { free: None, ..load }, 1, 60).is_ok());
+    }
+
+    #[tokio::test]
+    async fn stale_capacity_answers_without_waiting() {
+        set_admission(AdmissionConfig { capacity_method: Some("capacity".to_string()), ..Default::default() }).await;
+        let stale = Instant::now() - Duration::from_secs(CAPACITY_CACHE_SECS + 1);
+        *SCHEDULER_CAPACITY.lock().await = Some((stale, Some(12)));
+        let started = Instant::now();
+        assert_eq!(scheduler_capacity().await, Some(12));
+        assert!(started.elapsed() < Duration::from_secs(1));
+        //refreshed in the background, with no scheduler configured the answer is unknown
+        for _ in 0..50 {
+            if SCHEDULER_CAPACITY.lock().await.map(|(at, _)| at > stale).unwrap_or(false) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(scheduler_capacity().await, None);
+        set_admission(AdmissionConfig::default()).await;
+    }
+}
@@ -0,0 +1,167 @@
+// This file is part of the aoraki-labs library.
+
+// The aoraki-labs library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// The aoraki-labs library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with the aoraki-labs library. If not, see <https://www.gnu.org/licenses/>.
+
+//! typed bindings of the pool contract, generated by build.rs from abi/ (or ZKPOOL_ABI at build time)
+
+use std::path::Path;
+use serde_json::Value;
+use web3::ethabi;
+
+#[allow(dead_code, unused_imports, unused_mut, unused_variables, clippy::too_many_arguments)]
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/zkpool_bindings.rs"));
+}
+pub use generated::*;
+
+#[path = "../codegen/loader.rs"]
+mod loader;
+#[cfg(test)]
+#[path = "../codegen/generate.rs"]
+mod generate;
+
+/// read an ABI from a JSON file, a plain ABI array or a build artifact with an "abi" field,
+/// or from every JSON file of a directory
+pub fn load_abi(path: &str) -> Result<ethabi::Contract, String> {
+    let entries = loader::abi_files(Path::new(path)).and_then(|files| loader::load_entries(&files))?;
+    serde_json::from_value(Value::Array(entries)).map_err(|e| format!("abi {}: {}", path, e))
+}
+
+fn signature(name: &str, inputs: &[ethabi::ParamType]) -> String {
+    format!("{}({})", name, inputs.iter().map(|kind| kind.to_string()).collect::<Vec<_>>().join(","))
+}
+
+/// check the contract still has the event and function the relayer decodes and sends, as compiled in
+pub fn check_abi(contract: &ethabi::Contract) -> Result<(), String> {
+    let event_found = contract.events_by_name(events::TaskSubmitted::NAME).map(|found| found.iter().any(|event| {
+        signature(&event.name, &event.inputs.iter().map(|p| p.kind.clone()).collect::<Vec<_>>()) == events::TaskSubmitted::SIGNATURE
+    })).unwrap_or(false);
+    if !event_found {
+        return Err(format!("abi has no event {}", events::TaskSubmitted::SIGNATURE));
+    }
+    let function_found = contract.functions_by_name(functions::prove_task::NAME).map(|found| found.iter().any(|function| {
+        signature(&function.name, &function.inputs.iter().map(|p| p.kind.clone()).collect::<Vec<_>>()) == functions::prove_task::SIGNATURE
+    })).unwrap_or(false);
+    if !function_found {
+        return Err(format!("abi has no function {}", functions::prove_task::SIGNATURE));
+    }
+    Ok(())
+}
+
+/// name the contract's custom error in a reverted call's RPC error, whose data holds the revert bytes
+pub fn explain_revert(e: web3::Error) -> web3::Error {
+    match e {
+        web3::Error::Rpc(mut err) => {
+            let revert = err.data.as_ref().and_then(|data| match data {
+                Value::String(hex_data) => hex::decode(hex_data.trim_start_matches("0x")).ok(),
+                Value::Object(object) => object.get("data").and_then(|d| d.as_str())
+                    .and_then(|hex_data| hex::decode(hex_data.trim_start_matches("0x")).ok()),
+                _ => None,
+            });
+            if let Some(error) = revert.and_then(|revert| errors::ContractError::decode(&revert)) {
+                err.message = format!("{}: {}", err.message, error);
+            }
+            web3::Error::Rpc(err)
+        },
+        e => e,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use web3::ethabi::Token;
+    use web3::types::{Address, H256, U256};
+
+    fn contract() -> ethabi::Contract {
+        load_abi("abi").unwrap()
+    }
+
+    #[test]
+    fn prove_task_matches_ethabi() {
+        let task_key = [7u8; 32];
+        let proof = vec![1, 2, 3, 4, 5];
+        let expected = contract().function("proveTask").unwrap()
+            .encode_input(&[Token::FixedBytes(task_key.to_vec()), Token::Bytes(proof.clone())]).unwrap();
+        assert_eq!(functions::prove_task::encode_input(task_key, proof), expected);
+        assert_eq!(functions::prove_task::selector().to_vec(), expected[..4].to_vec());
+    }
+
+    #[test]
+    fn task_submitted_round_trip() {
+        let event = events::TaskSubmitted {
+            requester: Address::repeat_byte(1),
+            prover: Address::repeat_byte(2),
+            instance: vec![1, 2, 3],
+            task_key: [7u8; 32],
+            reward_token: Address::repeat_byte(3),
+            reward_amount: U256::from(1000),
+            liability_window: 3600,
+            liability_token: Address::repeat_byte(4),
+            liability_amount: U256::exp10(20),
+        };
+        let topics = vec![
+            events::TaskSubmitted::topic(),
+            H256::from(event.requester),
+            H256::from(event.prover),
+        ];
+        let data = ethabi::encode(&[
+            Token::Bytes(event.instance.clone()),
+            Token::FixedBytes(event.task_key.to_vec()),
+            Token::Address(event.reward_token),
+            Token::Uint(event.reward_amount),
+            Token::Uint(event.liability_window.into()),
+            Token::Address(event.liability_token),
+            Token::Uint(event.liability_amount),
+        ]);
+        assert_eq!(contract().event("TaskSubmitted").unwrap().signature(), events::TaskSubmitted::topic());
+        assert_eq!(events::TaskSubmitted::decode(&topics, &data).unwrap(), event);
+        assert!(events::TaskSubmitted::decode(&topics[..2], &data).is_err());
+        assert!(events::TaskSubmitted::decode(&[H256::zero(), topics[1], topics[2]], &data).is_err());
+    }
+
+    #[test]
+    fn custom_errors_decode() {
+        let account = Address::repeat_byte(9);
+        let mut revert = errors::OwnableUnauthorizedAccount::selector().to_vec();
+        revert.extend(ethabi::encode(&[Token::Address(account)]));
+        assert_eq!(errors::ContractError::decode(&revert),
+            Some(errors::ContractError::OwnableUnauthorizedAccount(errors::OwnableUnauthorizedAccount { account })));
+        assert_eq!(errors::ContractError::decode(&errors::TaskNoneExist::selector()).unwrap().to_string(), "TaskNoneExist");
+        assert_eq!(errors::ContractError::decode(&[0, 1, 2, 3]), None);
+        assert_eq!(errors::ContractError::decode(&[0]), None);
+    }
+
+    #[test]
+    fn check_abi_finds_compiled_entries() {
+        assert!(check_abi(&contract()).is_ok());
+        let empty: ethabi::Contract = serde_json::from_str("[]").unwrap();
+        assert!(check_abi(&empty).is_err());
+    }
+
+    #[test]
+    fn load_abi_reads_artifacts_and_directories() {
+        let dir = std::env::temp_dir().join(format!("zkpool-abi-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let abi: Value = serde_json::from_str(&std::fs::read_to_string("abi/ZKPool.json").unwrap()).unwrap();
+        let entries = abi.get("abi").cloned().unwrap_or(abi);
+        std::fs::write(dir.join("a.json"), serde_json::json!({"abi": entries}).to_string()).unwrap();
+        std::fs::write(dir.join("notes.txt"), "ignored").unwrap();
+        let from_dir = load_abi(dir.to_str().unwrap()).unwrap();
+        assert!(from_dir.function("proveTask").is_ok());
+        std::fs::write(dir.join("b.json"), "{}").unwrap();
+        assert!(load_abi(dir.to_str().unwrap()).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
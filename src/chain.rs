@@ -14,38 +14,38 @@
 // along with the aoraki-labs library. If not, see <https://www.gnu.org/licenses/>.
 
 use std::str::FromStr;
-use ethereum_private_key_to_address::PrivateKey;
 use log::*;
 use web3::ethabi::FixedBytes;
-use std::thread;
-use tokio::time::{self, Duration};
-use core::str;
-use rand::seq::SliceRandom;
+use tokio::time::{self, Duration, Instant};
 use serde_derive::{Deserialize,Serialize};
-use reqwest::{Client, Url};
-use http_req::request::{Request, Method};
 use web3::types::BlockNumber::Pending;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use tokio::sync::Mutex;
-use http_req::uri::Uri;
 use lazy_static::lazy_static;
 use std::collections::VecDeque;
+use chrono::{DateTime, Utc};
 use web3::{
   Web3,
-  ethabi,
-  transports::Http,
+  transports::WebSocket,
   types,
-  ethabi::{ethereum_types::U256,Function, ParamType, Param, StateMutability, Token},
-  types::{Address,Bytes, TransactionParameters},
+  ethabi::ethereum_types::U256,
+  types::{Address,Bytes, TransactionParameters, H256},
 };
-use diesel::pg::PgConnection;
-use diesel::prelude::*;
-use dotenvy::dotenv;
-use std::env;
+#[cfg(feature = "DB")]
 use crate::db::*;
+#[cfg(feature = "local")]
 use crate::models::*;
+#[cfg(feature = "local")]
 use crate::server::ProofResponse;
+use crate::bindings::{events, functions, structs, explain_revert};
+use crate::provider::{current_head, with_provider, send_raw_transaction, best_web3, ws_provider_urls, CALL_TIMEOUT_SECS};
+use crate::quote::{chain_id, parse_address};
+use futures::StreamExt;
+use crate::signer::{next_gas_signer, prover_signer};
+use crate::quote_registry::{match_task_submitted, expire_quotes_before};
+use crate::admission::{admit_dispatch, record_segment_time};
+use crate::scheduler::{deliver, redeliver, overdue_segments, segment_attempts, segment_done, release_task, Segment};
 
 lazy_static! {
     pub static ref PROOF_MSG_QUEUE: Arc<tokio::sync::Mutex<VecDeque<ProofMessage>>> = {
@@ -54,24 +54,30 @@ lazy_static! {
     pub static ref TASK_MSG_QUEUE: Arc<tokio::sync::Mutex<VecDeque<ProvenTaskMessage>>> = {
         Arc::new(tokio::sync::Mutex::new(VecDeque::new()))
     };
+    pub static ref DISPATCHING: tokio::sync::Mutex<HashSet<String>> = {   //task ids taken off the queue and being delivered
+      tokio::sync::Mutex::new(HashSet::new())
+    };
     pub static ref OPEN_TASK_MSG_QUEUE: Arc<tokio::sync::Mutex<VecDeque<ProvenTaskMessage>>> = {
         Arc::new(tokio::sync::Mutex::new(VecDeque::new()))
     };
-    pub static ref PRIV_KEY: tokio::sync::Mutex<String> = {      //priv_key
-        tokio::sync::Mutex::new(String::from(""))
-    };
     pub static ref CONTRACT: tokio::sync::Mutex<String> = {      //contract
       tokio::sync::Mutex::new(String::from(""))
     };
-    pub static ref SCHEDULER_URL: tokio::sync::Mutex<String> = {   //scheduler rpc url
-        tokio::sync::Mutex::new(String::from(""))
-    };
     pub static ref TASK_KEY_CACHE: Arc<Mutex<HashMap<String, String>>> = {
       Arc::new(Mutex::new(HashMap::default()))
     };
     pub static ref TASK_INFO: Arc<tokio::sync::Mutex<HashMap<String, TaskInfo>>> = {
       Arc::new(Mutex::new(HashMap::new()))
     };
+    pub static ref BLOCK_TIME_CACHE: tokio::sync::Mutex<Option<(Instant, f64)>> = {   //observed seconds per block
+      tokio::sync::Mutex::new(None)
+    };
+    pub static ref TASK_STATE: Arc<tokio::sync::Mutex<HashMap<String, TaskState>>> = {   //whole task status, key project-task
+      Arc::new(Mutex::new(HashMap::new()))
+    };
+    pub static ref LOG_RANGE: tokio::sync::Mutex<LogRange> = {   //adaptive eth_getLogs range
+      tokio::sync::Mutex::new(LogRange::new(LOG_RANGE_MIN_BLOCKS, LOG_RANGE_MAX_BLOCKS).expect("built-in log range"))
+    };
 }
 
 pub const SEG_NUM: i32 = 4;
@@ -83,75 +89,173 @@ pub struct ProofMessage {
     pub degree:  String,
 }
 
-#[derive(Serialize, Default, Debug, Clone)]
-pub struct EmitProvenTaskMessage {
-    pub requester: String,
-    pub prover: String,
-    pub instance: String,
-    pub task_key: String,
-    pub reward_token: String,
-    pub reward_amount: String,
-    pub liability_window: String,
-    pub liability_token: String,
-    pub liability_amount: String,
-   
+/// a mined TaskSubmitted log, decoded, with where it was emitted
+#[derive(Serialize, Default, Debug, Clone, PartialEq)]
+pub struct TaskSubmittedEvent {
+    pub requester: Address,
+    pub prover: Address,
+    pub instance: Bytes,
+    pub task_key: H256,
+    pub reward_token: Address,
+    pub reward_amount: U256,
+    pub liability_window: u64,
+    pub liability_token: Address,
+    pub liability_amount: U256,
+    pub block_number: u64,
+    pub tx_hash: H256,
+    pub log_index: U256,
 }
 
-#[derive(Serialize, Default, Debug, Clone)]
-pub struct ProvenTaskMessage {
-    pub instance: String,
-    pub task_key: String,
-}
+impl TaskSubmittedEvent {
+    /// an error when the log is not mined yet or does not fit the contract ABI the relayer was built with
+    pub fn from_log(log: &types::Log) -> Result<TaskSubmittedEvent, String> {
+        let (block_number, tx_hash, log_index) = match (log.block_number, log.transaction_hash, log.log_index) {
+            (Some(block_number), Some(tx_hash), Some(log_index)) => (block_number.as_u64(), tx_hash, log_index),
+            _ => return Err(format!("TaskSubmitted log of tx {:?} has no block, tx hash or log index", log.transaction_hash)),
+        };
+        let event = events::TaskSubmitted::decode(&log.topics, &log.data.0)
+            .map_err(|e| format!("TaskSubmitted log {} of tx {:?} does not match the contract abi: {}", log_index, tx_hash, e))?;
+        Ok(TaskSubmittedEvent {
+            requester: event.requester,
+            prover: event.prover,
+            instance: Bytes(event.instance),
+            task_key: H256(event.task_key),
+            reward_token: event.reward_token,
+            reward_amount: event.reward_amount,
+            liability_window: event.liability_window,
+            liability_token: event.liability_token,
+            liability_amount: event.liability_amount,
+            block_number,
+            tx_hash,
+            log_index,
+        })
+    }
 
+    /// the task key as tasks are stored, hex without 0x
+    pub fn task_id(&self) -> String {
+        hex::encode(self.task_key.as_bytes())
+    }
+}
 
-#[derive(Debug, Serialize, Deserialize)]
-struct RpcRequest {
-    jsonrpc: String,
-    method: String,
-    params: Vec<String>,
-    id: String,
+#[derive(Serialize, Default, Debug, Clone)]
+pub struct ProvenTaskMessage {
+    pub event: TaskSubmittedEvent,
+    pub received_at: DateTime<Utc>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct RpcResponse {
-    jsonrpc: String,
-    result: String,
-    id: u64,
+impl ProvenTaskMessage {
+    pub fn task_id(&self) -> String {
+        self.event.task_id()
+    }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+/// lifecycle of a task and of each of its segments, stored and reported as the lowercase name.
+/// segments stop at Proven, the task goes on through Aggregated and Submitted to Confirmed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TaskStatus {
+    Created,
+    Dispatched,
     Proving,
     Proven,
+    Aggregated,
+    Submitted,
+    Confirmed,
+    Failed,
+    Expired,
 }
 
 impl TaskStatus {
-  pub fn as_str(&self) -> &str {
+  pub fn as_str(&self) -> &'static str {
       match self {
+          TaskStatus::Created => "created",
+          TaskStatus::Dispatched => "dispatched",
           TaskStatus::Proving => "proving",
           TaskStatus::Proven => "proven",
+          TaskStatus::Aggregated => "aggregated",
+          TaskStatus::Submitted => "submitted",
+          TaskStatus::Confirmed => "confirmed",
+          TaskStatus::Failed => "failed",
+          TaskStatus::Expired => "expired",
       }
   }
 
+  #[cfg(feature = "DB")]
   pub fn from_str(s: &str) -> Option<Self> {
       match s {
+          "created" => Some(TaskStatus::Created),
+          "dispatched" => Some(TaskStatus::Dispatched),
           "proving" => Some(TaskStatus::Proving),
           "proven" => Some(TaskStatus::Proven),
+          "aggregated" => Some(TaskStatus::Aggregated),
+          "submitted" => Some(TaskStatus::Submitted),
+          "confirmed" => Some(TaskStatus::Confirmed),
+          "failed" => Some(TaskStatus::Failed),
+          "expired" => Some(TaskStatus::Expired),
           _ => None,
       }
   }
+
+  pub fn is_terminal(&self) -> bool {
+      matches!(self, TaskStatus::Confirmed | TaskStatus::Failed | TaskStatus::Expired)
+  }
+
+  /// setting the current status again is a no-op and always allowed;
+  /// Dispatched and Proving can go back to Dispatched when a segment is re-sent
+  pub fn can_transition_to(&self, next: TaskStatus) -> bool {
+      if *self == next {
+          return true;
+      }
+      if self.is_terminal() {
+          return false;
+      }
+      if matches!(next, TaskStatus::Failed | TaskStatus::Expired) {
+          return true;
+      }
+      matches!((self, next),
+          (TaskStatus::Created, TaskStatus::Dispatched)
+          | (TaskStatus::Dispatched, TaskStatus::Proving)
+          | (TaskStatus::Proving, TaskStatus::Dispatched)
+          | (TaskStatus::Proving, TaskStatus::Proven)
+          | (TaskStatus::Proven, TaskStatus::Aggregated)
+          | (TaskStatus::Proven, TaskStatus::Submitted)
+          | (TaskStatus::Aggregated, TaskStatus::Submitted)
+          | (TaskStatus::Submitted, TaskStatus::Confirmed))
+  }
+
+  pub fn check_transition(&self, next: TaskStatus) -> Result<(), String> {
+      if self.can_transition_to(next) {
+          Ok(())
+      } else {
+          Err(format!("invalid status transition {} -> {}", self, next))
+      }
+  }
 }
 
+impl std::fmt::Display for TaskStatus {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+      f.write_str(self.as_str())
+  }
+}
+
+/// local mode state of a whole task
 #[derive(Clone, Debug)]
-pub struct TaskInfo {
+pub struct TaskState {
     pub project_id: String,
     pub task_id: String,
-    pub split_id: String,
     pub status: TaskStatus,
+    pub deadline: Option<DateTime<Utc>>,
+}
+
+/// local mode state of a segment, key project-task-split
+#[derive(Clone, Debug)]
+pub struct TaskInfo {
+    pub status: TaskStatus,
+    pub dispatched_at: Option<DateTime<Utc>>,
 }
 
 use crate::ok_or_continue;
 
+/// default rpc providers, replaced by --rpc
 pub const SEPOLIA_CHAIN_URLS: [&str; 1] = [
     "https://eth-sepolia.g.alchemy.com/v2/kMO8lL7g44IJOGR-Om-kc7DAlmHaXFb7",
 ];
@@ -159,675 +263,393 @@ pub const SEPOLIA_CHAIN_URLS: [&str; 1] = [
 ///TBD
 // pub const  ZKPOOL_CONTRACT_ADDR :&str = "c20F6905A21c26B106c7A30E77e4711390cffBA8";
 pub const  GAS_UPPER : &str = "1000000";
-const BLOCK_NUM_BODY: &[u8] = br#"{"jsonrpc":"2.0","method":"eth_blockNumber","params":[],"id":83}"#;
+pub const RECEIPT_POLL_INTERVAL: u64 = 5;   //seconds
+pub const RECEIPT_POLL_TIMES: u64 = 120;
+pub const LOG_RANGE_MIN_BLOCKS: u64 = 10;
+pub const LOG_RANGE_MAX_BLOCKS: u64 = 5000;
+pub const LOG_RANGE_GROW_AFTER: u32 = 10;  //answers in a row before a halved range doubles again
+pub const WS_CONNECT_TIMEOUT_SECS: u64 = 10;
+pub const WS_RETRY_SECS: u64 = 30;         //polling after a subscription failure before trying again
+pub const WS_HEAD_LAG: u64 = 2;            //blocks behind the subscribed head counted as handled
+pub const BLOCK_TIME_SAMPLE: u64 = 100;       //blocks
+pub const BLOCK_TIME_CACHE_SECS: u64 = 600;
+pub const DEFAULT_BLOCK_TIME_SECS: f64 = 12.0;
+pub const SEGMENT_TIMEOUT_MIN_SECS: u64 = 60;
+
+/// local map segment status, created on first use; with the DB feature the stored status was checked already and the map only mirrors it
+pub async fn update_task_status(project_id: &str, task_id: &str, split_id: &str, new_status: TaskStatus) -> Result<(), String> {
+    let mut task_info_map = TASK_INFO.lock().await;
+    let key = format!("{}-{}-{}", project_id, task_id, split_id);
+    let task_info = task_info_map.entry(key)
+        .or_insert_with(|| TaskInfo {
+            status: TaskStatus::Created,
+            dispatched_at: None,
+        });
+    #[cfg(not(feature = "DB"))]
+    task_info.status.check_transition(new_status)?;
+    if new_status == TaskStatus::Dispatched {
+        task_info.dispatched_at = Some(Utc::now());
+    }
+    if new_status == TaskStatus::Proven && task_info.status != TaskStatus::Proven {
+        if let Some(dispatched_at) = task_info.dispatched_at {
+            record_segment_time((Utc::now() - dispatched_at).num_milliseconds() as f64 / 1000.0).await;
+        }
+    }
+    task_info.status = new_status;
+    Ok(())
+}
 
+/// local map whole task status, created on first use; mirrors the stored status with the DB feature like update_task_status
+pub async fn update_big_task_status(project_id: &str, task_id: &str, new_status: TaskStatus) -> Result<(), String> {
+    let mut task_state_map = TASK_STATE.lock().await;
+    let key = format!("{}-{}", project_id, task_id);
+    let state = task_state_map.entry(key)
+        .or_insert_with(|| TaskState {
+            project_id: project_id.to_string(),
+            task_id: task_id.to_string(),
+            status: TaskStatus::Created,
+            deadline: None,
+        });
+    #[cfg(not(feature = "DB"))]
+    state.status.check_transition(new_status)?;
+    state.status = new_status;
+    Ok(())
+}
 
-const MATIC_CONTRACT_ABI:&[u8] = r#"[
-    {
-      "inputs": [
-        {
-          "internalType": "address",
-          "name": "_owner",
-          "type": "address"
-        },
-        {
-          "internalType": "address",
-          "name": "_bondToken",
-          "type": "address"
-        },
-        {
-          "internalType": "uint256",
-          "name": "_bondAmount",
-          "type": "uint256"
-        },
-        {
-          "internalType": "address",
-          "name": "_verifierAddress",
-          "type": "address"
-        },
-        {
-          "internalType": "uint32",
-          "name": "_proofWindow",
-          "type": "uint32"
-        },
-        {
-          "internalType": "uint8",
-          "name": "_instanceLength",
-          "type": "uint8"
-        }
-      ],
-      "stateMutability": "nonpayable",
-      "type": "constructor"
-    },
-    {
-      "inputs": [],
-      "name": "INVALID_ASSIGNMENT",
-      "type": "error"
-    },
-    {
-      "inputs": [],
-      "name": "INVALID_PROOF",
-      "type": "error"
-    },
-    {
-      "inputs": [],
-      "name": "INVALID_PROVER",
-      "type": "error"
-    },
-    {
-      "inputs": [],
-      "name": "INVALID_PROVER_SIG",
-      "type": "error"
-    },
-    {
-      "inputs": [
-        {
-          "internalType": "address",
-          "name": "owner",
-          "type": "address"
-        }
-      ],
-      "name": "OwnableInvalidOwner",
-      "type": "error"
-    },
-    {
-      "inputs": [
-        {
-          "internalType": "address",
-          "name": "account",
-          "type": "address"
-        }
-      ],
-      "name": "OwnableUnauthorizedAccount",
-      "type": "error"
-    },
-    {
-      "inputs": [],
-      "name": "ReentrancyGuardReentrantCall",
-      "type": "error"
-    },
-    {
-      "inputs": [],
-      "name": "TASK_ALREADY_OPEN",
-      "type": "error"
-    },
-    {
-      "inputs": [],
-      "name": "TASK_ALREADY_PROVEN",
-      "type": "error"
-    },
-    {
-      "inputs": [],
-      "name": "TASK_ALREADY_SUBMITTED",
-      "type": "error"
-    },
-    {
-      "inputs": [],
-      "name": "TASK_NONE_EXIST",
-      "type": "error"
-    },
-    {
-      "inputs": [],
-      "name": "TASK_NOT_OPEN",
-      "type": "error"
-    },
-    {
-      "inputs": [],
-      "name": "TASK_NOT_THE_SAME",
-      "type": "error"
-    },
-    {
-      "anonymous": false,
-      "inputs": [
-        {
-          "indexed": true,
-          "internalType": "address",
-          "name": "from",
-          "type": "address"
-        },
-        {
-          "indexed": false,
-          "internalType": "bytes32",
-          "name": "taskKey",
-          "type": "bytes32"
-        },
-        {
-          "indexed": false,
-          "internalType": "uint256",
-          "name": "amount",
-          "type": "uint256"
-        }
-      ],
-      "name": "BondDeposited",
-      "type": "event"
-    },
-    {
-      "anonymous": false,
-      "inputs": [
-        {
-          "indexed": true,
-          "internalType": "address",
-          "name": "to",
-          "type": "address"
-        },
-        {
-          "indexed": false,
-          "internalType": "bytes32",
-          "name": "taskKey",
-          "type": "bytes32"
-        },
-        {
-          "indexed": false,
-          "internalType": "uint256",
-          "name": "amount",
-          "type": "uint256"
-        }
-      ],
-      "name": "BondReleased",
-      "type": "event"
-    },
-    {
-      "anonymous": false,
-      "inputs": [
-        {
-          "indexed": true,
-          "internalType": "address",
-          "name": "previousOwner",
-          "type": "address"
-        },
-        {
-          "indexed": true,
-          "internalType": "address",
-          "name": "newOwner",
-          "type": "address"
-        }
-      ],
-      "name": "OwnershipTransferred",
-      "type": "event"
-    },
-    {
-      "anonymous": false,
-      "inputs": [
-        {
-          "indexed": true,
-          "internalType": "address",
-          "name": "prover",
-          "type": "address"
-        },
-        {
-          "indexed": false,
-          "internalType": "bytes32",
-          "name": "taskKey",
-          "type": "bytes32"
-        }
-      ],
-      "name": "TaskProven",
-      "type": "event"
-    },
-    {
-      "anonymous": false,
-      "inputs": [
-        {
-          "indexed": true,
-          "internalType": "address",
-          "name": "requester",
-          "type": "address"
-        },
-        {
-          "indexed": true,
-          "internalType": "address",
-          "name": "prover",
-          "type": "address"
-        },
-        {
-          "indexed": false,
-          "internalType": "bytes",
-          "name": "instance",
-          "type": "bytes"
-        },
-        {
-          "indexed": false,
-          "internalType": "bytes32",
-          "name": "taskKey",
-          "type": "bytes32"
-        },
-        {
-          "indexed": false,
-          "internalType": "address",
-          "name": "rewardToken",
-          "type": "address"
-        },
-        {
-          "indexed": false,
-          "internalType": "uint256",
-          "name": "rewardAmount",
-          "type": "uint256"
-        },
-        {
-          "indexed": false,
-          "internalType": "uint64",
-          "name": "liabilityWindow",
-          "type": "uint64"
-        },
-        {
-          "indexed": false,
-          "internalType": "address",
-          "name": "liabilityToken",
-          "type": "address"
-        },
-        {
-          "indexed": false,
-          "internalType": "uint256",
-          "name": "liabilityAmount",
-          "type": "uint256"
-        }
-      ],
-      "name": "TaskSubmitted",
-      "type": "event"
-    },
-    {
-      "anonymous": false,
-      "inputs": [
-        {
-          "indexed": true,
-          "internalType": "address",
-          "name": "from",
-          "type": "address"
-        },
-        {
-          "indexed": true,
-          "internalType": "address",
-          "name": "to",
-          "type": "address"
-        },
-        {
-          "indexed": false,
-          "internalType": "uint256",
-          "name": "value",
-          "type": "uint256"
+pub async fn set_task_deadline(project_id: &str, task_id: &str, deadline: DateTime<Utc>) {
+    let mut task_state_map = TASK_STATE.lock().await;
+    if let Some(state) = task_state_map.get_mut(&format!("{}-{}", project_id, task_id)) {
+        state.deadline = Some(deadline);
+    }
+}
+
+/// fill the local maps with the open tasks and proving segments of the database, so a restarted relayer counts,
+/// expires and moves them on like the ones it took itself
+#[cfg(feature = "DB")]
+pub async fn restore_task_state() -> Result<(usize, usize), String> {
+    let (tasks, segments) = load_open_proofs().await?;
+    let mut task_state_map = TASK_STATE.lock().await;
+    for task in &tasks {
+        let status = TaskStatus::from_str(&task.status)
+            .ok_or_else(|| format!("task {} has unknown stored status {}", task.task_id, task.status))?;
+        task_state_map.insert(format!("{}-{}", task.project_id, task.task_id), TaskState {
+            project_id: task.project_id.clone(),
+            task_id: task.task_id.clone(),
+            status,
+            deadline: task.deadline.map(|deadline| deadline.and_utc()),
+        });
+    }
+    drop(task_state_map);
+    let mut task_info_map = TASK_INFO.lock().await;
+    for segment in &segments {
+        let status = TaskStatus::from_str(&segment.status)
+            .ok_or_else(|| format!("segment {}@{} has unknown stored status {}", segment.task_id, segment.task_split_id, segment.status))?;
+        task_info_map.insert(format!("{}-{}-{}", segment.project_id, segment.task_id, segment.task_split_id), TaskInfo {
+            status,
+            dispatched_at: segment.dispatch_time.map(|dispatched_at| dispatched_at.and_utc()),
+        });
+    }
+    Ok((tasks.len(), segments.len()))
+}
+
+/// tasks accepted and not yet confirmed, failed or expired
+pub async fn in_flight_tasks() -> usize {
+    TASK_STATE.lock().await.values()
+        .filter(|state| !state.status.is_terminal())
+        .count()
+}
+
+/// segments handed to the scheduler and not yet proven
+pub async fn in_flight_segments() -> usize {
+    TASK_INFO.lock().await.values()
+        .filter(|info| matches!(info.status, TaskStatus::Dispatched | TaskStatus::Proving))
+        .count()
+}
+
+/// tasks still unsubmitted after their liability window become Expired
+pub async fn expire_overdue_tasks() {
+    let now = Utc::now();
+    let overdue: Vec<TaskState> = TASK_STATE.lock().await.values()
+        .filter(|state| !state.status.is_terminal() && state.status != TaskStatus::Submitted)
+        .filter(|state| state.deadline.map(|deadline| deadline < now).unwrap_or(false))
+        .cloned()
+        .collect();
+    for state in overdue {
+        info!("task {} missed its deadline {:?}", state.task_id, state.deadline);
+        if let Err(e) = set_task_status(&state.project_id, &state.task_id, TaskStatus::Expired).await {
+            error!("expire task {}: {}", state.task_id, e);
         }
-      ],
-      "name": "Transfer",
-      "type": "event"
-    },
-    {
-      "inputs": [],
-      "name": "owner",
-      "outputs": [
-        {
-          "internalType": "address",
-          "name": "",
-          "type": "address"
+        release_task(&state.task_id).await;
+    }
+}
+
+/// how long a segment's proof may take before it is sent again: the task's segments share its liability window
+pub fn segment_timeout(liability_window: u64) -> Duration {
+    Duration::from_secs((liability_window / SEG_NUM as u64).max(SEGMENT_TIMEOUT_MIN_SECS))
+}
+
+/// fail a task because of one of its segments, forgetting the segments the schedulers hold
+async fn fail_segment(project_id: &str, task_id: &str, split_id: &str) {
+    release_task(task_id).await;
+    if let Err(e) = set_segment_status(project_id, task_id, split_id, TaskStatus::Failed).await {
+        error!("{}", e);
+    }
+    if let Err(e) = set_task_status(project_id, task_id, TaskStatus::Failed).await {
+        error!("{}", e);
+    }
+}
+
+/// send the segments whose proof is overdue again, failing their task once a segment used all its attempts
+pub async fn retry_overdue_segments() {
+    let max_attempts = segment_attempts().await;
+    for assignment in overdue_segments().await {
+        let segment = &assignment.segment;
+        let (task_id, split_id) = match segment.task_id.split_once('@') {
+            Some(r) => r,
+            None => continue,
+        };
+        //failed or expired by another segment of this pass
+        if task_status(&segment.project_id, task_id).await.map(|status| status.is_terminal()).unwrap_or(false) {
+            continue;
         }
-      ],
-      "stateMutability": "view",
-      "type": "function"
-    },
-    {
-      "inputs": [
-        {
-          "internalType": "bytes32",
-          "name": "taskKey",
-          "type": "bytes32"
-        },
-        {
-          "internalType": "bytes",
-          "name": "proof",
-          "type": "bytes"
+        if assignment.attempts >= max_attempts {
+            error!("segment {} not proven after {} attempts of {}s, task {} failed",
+                segment.task_id, assignment.attempts, assignment.timeout.as_secs(), task_id);
+            fail_segment(&segment.project_id, task_id, split_id).await;
+            continue;
         }
-      ],
-      "name": "proveTask",
-      "outputs": [],
-      "stateMutability": "nonpayable",
-      "type": "function"
-    },
-    {
-      "inputs": [
-        {
-          "internalType": "bytes32",
-          "name": "taskKey",
-          "type": "bytes32"
+        //a proof coming back meanwhile makes the segment Proven, which cannot go back to Dispatched
+        if let Err(e) = set_segment_status(&segment.project_id, task_id, split_id, TaskStatus::Dispatched).await {
+            info!("segment {} not sent again: {}", segment.task_id, e);
+            continue;
         }
-      ],
-      "name": "readProofStatus",
-      "outputs": [
-        {
-          "components": [
-            {
-              "internalType": "bytes",
-              "name": "instance",
-              "type": "bytes"
-            },
-            {
-              "internalType": "address",
-              "name": "prover",
-              "type": "address"
+        warn!("segment {} not proven within {}s by {}, sending it again (attempt {} of {})",
+            segment.task_id, assignment.timeout.as_secs(), assignment.url, assignment.attempts + 1, max_attempts);
+        match redeliver(&assignment).await {
+            Ok(url) => {
+                info!("segment {} sent again to scheduler {}", segment.task_id, url);
+                if let Err(e) = set_segment_status(&segment.project_id, task_id, split_id, TaskStatus::Proving).await {
+                    error!("{}", e);
+                }
             },
-            {
-              "internalType": "uint64",
-              "name": "submittedAt",
-              "type": "uint64"
+            Err(e) => {
+                error!("send segment {} again: {}, task {} failed", segment.task_id, e, task_id);
+                fail_segment(&segment.project_id, task_id, split_id).await;
             },
-            {
-              "internalType": "bool",
-              "name": "proven",
-              "type": "bool"
-            }
-          ],
-          "internalType": "struct TaskStatus",
-          "name": "taskStatus",
-          "type": "tuple"
         }
-      ],
-      "stateMutability": "view",
-      "type": "function"
-    },
-    {
-      "inputs": [],
-      "name": "renounceOwnership",
-      "outputs": [],
-      "stateMutability": "nonpayable",
-      "type": "function"
-    },
+    }
+}
+
+/// move a segment to new_status in the database, with the DB feature, and then in the local map
+pub async fn set_segment_status(project_id: &str, task_id: &str, split_id: &str, new_status: TaskStatus) -> Result<(), String> {
+    #[cfg(feature = "DB")]
     {
-      "inputs": [
-        {
-          "internalType": "bytes",
-          "name": "instance",
-          "type": "bytes"
-        },
-        {
-          "internalType": "address",
-          "name": "prover",
-          "type": "address"
-        },
-        {
-          "internalType": "address",
-          "name": "rewardToken",
-          "type": "address"
-        },
-        {
-          "internalType": "uint256",
-          "name": "rewardAmount",
-          "type": "uint256"
-        },
-        {
-          "internalType": "uint64",
-          "name": "liabilityWindow",
-          "type": "uint64"
-        },
-        {
-          "internalType": "address",
-          "name": "liabilityToken",
-          "type": "address"
-        },
-        {
-          "internalType": "uint256",
-          "name": "liabilityAmount",
-          "type": "uint256"
-        },
-        {
-          "internalType": "uint64",
-          "name": "expiry",
-          "type": "uint64"
-        },
-        {
-          "internalType": "bytes",
-          "name": "signature",
-          "type": "bytes"
+        if new_status == TaskStatus::Dispatched {
+            set_small_proof_dispatched(project_id, task_id, split_id, 1.0/SEG_NUM as f64).await?;
+        } else {
+            set_small_proof_status_and_percentage(project_id, task_id, split_id, new_status, 1.0/SEG_NUM as f64).await?;
         }
-      ],
-      "name": "submitTask",
-      "outputs": [
-        {
-          "internalType": "bytes32",
-          "name": "taskKey",
-          "type": "bytes32"
-        }
-      ],
-      "stateMutability": "nonpayable",
-      "type": "function"
-    },
+    }
+    update_task_status(project_id, task_id, split_id, new_status).await
+}
+
+/// whether the task was taken on before, in the local map or, with the DB feature, the database
+pub async fn task_known(project_id: &str, task_id: &str) -> Result<bool, String> {
+    if TASK_STATE.lock().await.contains_key(&format!("{}-{}", project_id, task_id)) {
+        return Ok(true);
+    }
+    #[cfg(feature = "DB")]
     {
-      "inputs": [
-        {
-          "internalType": "bytes32",
-          "name": "",
-          "type": "bytes32"
-        }
-      ],
-      "name": "taskStatusMap",
-      "outputs": [
-        {
-          "internalType": "bytes",
-          "name": "instance",
-          "type": "bytes"
-        },
-        {
-          "internalType": "address",
-          "name": "prover",
-          "type": "address"
-        },
-        {
-          "internalType": "uint64",
-          "name": "submittedAt",
-          "type": "uint64"
-        },
-        {
-          "internalType": "bool",
-          "name": "proven",
-          "type": "bool"
-        }
-      ],
-      "stateMutability": "view",
-      "type": "function"
-    },
+        big_proof_exists(project_id, task_id).await
+    }
+    #[cfg(not(feature = "DB"))]
+    Ok(false)
+}
+
+/// the whole task status from the local map, or the store for tasks taken before a restart
+async fn task_status(project_id: &str, task_id: &str) -> Option<TaskStatus> {
+    if let Some(state) = TASK_STATE.lock().await.get(&format!("{}-{}", project_id, task_id)) {
+        return Some(state.status);
+    }
+    #[cfg(feature = "DB")]
     {
-      "inputs": [
-        {
-          "internalType": "address",
-          "name": "newOwner",
-          "type": "address"
-        }
-      ],
-      "name": "transferOwnership",
-      "outputs": [],
-      "stateMutability": "nonpayable",
-      "type": "function"
-    },
+        return get_big_proof_status(project_id, task_id).await.ok().and_then(|s| TaskStatus::from_str(&s));
+    }
+    #[cfg(not(feature = "DB"))]
+    None
+}
+
+/// the segment status from the local map, or the store for tasks taken before a restart
+async fn segment_status(project_id: &str, task_id: &str, split_id: &str) -> Option<TaskStatus> {
+    if let Some(info) = TASK_INFO.lock().await.get(&format!("{}-{}-{}", project_id, task_id, split_id)) {
+        return Some(info.status);
+    }
+    #[cfg(feature = "DB")]
     {
-      "inputs": [
-        {
-          "internalType": "address",
-          "name": "_bondToken",
-          "type": "address"
-        },
-        {
-          "internalType": "uint256",
-          "name": "_bondAmount",
-          "type": "uint256"
-        },
-        {
-          "internalType": "address",
-          "name": "_verifierAddress",
-          "type": "address"
-        },
-        {
-          "internalType": "uint32",
-          "name": "_proofWindow",
-          "type": "uint32"
-        },
-        {
-          "internalType": "uint8",
-          "name": "_instanceLength",
-          "type": "uint8"
-        }
-      ],
-      "name": "updateConfig",
-      "outputs": [],
-      "stateMutability": "nonpayable",
-      "type": "function"
+        return get_small_proof_status_and_percentage(project_id, task_id, split_id).await.ok()
+            .and_then(|(s, _)| TaskStatus::from_str(&s));
     }
-  ]"#.as_bytes();
+    #[cfg(not(feature = "DB"))]
+    None
+}
 
-  pub async fn update_task_status(project_id: &str, task_id: &str, split_id: &str, new_status: &str) -> Result<(), String> {
-    let mut task_info_map = TASK_INFO.lock().await;
-    let key = format!("{}-{}-{}", project_id, task_id, split_id);
-    match TaskStatus::from_str(new_status) {
-        Some(status_enum) => {
-            let task_info = task_info_map.entry(key)
-                .or_insert_with(|| TaskInfo {
-                    project_id: project_id.to_string(),
-                    task_id: task_id.to_string(),
-                    split_id: split_id.to_string(),
-                    status: TaskStatus::Proving,
-                });
-            task_info.status = status_enum;
-            Ok(())
-        },
-        None => Err("Invalid status".to_string()),
+/// whether the proof for task_id, "<task key>" or "<task key>@<split id>", was taken already
+pub async fn proof_received(task_id: &str) -> bool {
+    match task_id.split_once('@') {
+        Some((task_key, split_id)) => segment_status("demo", task_key, split_id).await == Some(TaskStatus::Proven),
+        None => matches!(task_status("demo", task_id).await,
+            Some(TaskStatus::Proven | TaskStatus::Aggregated | TaskStatus::Submitted | TaskStatus::Confirmed)),
     }
 }
 
-pub async fn get_task_status(project_id: &str, task_id: &str, split_id: &str) -> Option<String> {
-    let task_info_map = TASK_INFO.lock().await;
-    let key = format!("{}-{}-{}", project_id, task_id, split_id);
-    task_info_map.get(&key).map(|task_info| task_info.status.as_str().to_string())
+/// move a whole task to new_status in the database, with the DB feature, and then in the local map
+pub async fn set_task_status(project_id: &str, task_id: &str, new_status: TaskStatus) -> Result<(), String> {
+    #[cfg(feature = "DB")]
+    {
+        set_big_proof_status(project_id, task_id, new_status).await?;
+    }
+    update_big_task_status(project_id, task_id, new_status).await
+}
+
+/// record a proveTask attempt, a successful one moves the task to Submitted and waits for its receipt
+pub async fn set_task_submission(project_id: &str, task_id: &str, result: &Result<String, String>) -> Result<(), String> {
+    #[cfg(feature = "DB")]
+    {
+        record_proof_submission(project_id, task_id, result).await?;
+    }
+    if result.is_ok() {
+        update_big_task_status(project_id, task_id, TaskStatus::Submitted).await?;
+    }
+    if let Ok(tx_hash) = result {
+        tokio::spawn(confirm_submission(project_id.to_string(), task_id.to_string(), tx_hash.clone()));
+    }
+    Ok(())
+}
+
+/// poll the proveTask receipt, Confirmed once mined successfully, Failed if it reverted
+pub async fn confirm_submission(project_id: String, task_id: String, tx_hash: String) {
+    let hash = match H256::from_str(&tx_hash) {
+        Ok(r) => r,
+        Err(e) => {
+            error!("invalid proveTask tx hash {}: {}", tx_hash, e);
+            return;
+        }
+    };
+    for _ in 0..RECEIPT_POLL_TIMES {
+        time::sleep(Duration::from_secs(RECEIPT_POLL_INTERVAL)).await;
+        let receipt = match with_provider("get receipt", |web3| async move { web3.eth().transaction_receipt(hash).await }).await {
+            Ok(Some(r)) => r,
+            Ok(None) => continue,
+            Err(e) => {
+                error!("{}", e);
+                continue
+            },
+        };
+        let status = if receipt.status == Some(1.into()) { TaskStatus::Confirmed } else { TaskStatus::Failed };
+        info!("proveTask tx {} of task {} is {}", tx_hash, task_id, status);
+        if let Err(e) = set_task_status(&project_id, &task_id, status).await {
+            error!("{}", e);
+        }
+        return;
+    }
+    error!("no receipt for proveTask tx {} of task {}, left as submitted", tx_hash, task_id);
 }
 
+#[cfg(feature = "local")]
 pub async fn update_proof_response(project_id: &str, task_id: &str) -> Result<ProofResponse, String> {
+  let task_state_map = TASK_STATE.lock().await;
+  let status = match task_state_map.get(&format!("{}-{}", project_id, task_id)) {
+      Some(state) => state.status,
+      None => return Ok(ProofResponse::default()),
+  };
+  drop(task_state_map);
+
   let task_info_map = TASK_INFO.lock().await;
   let mut small_proofs = Vec::new();
-  let mut all_proven = true;
-  let mut found = false;
-
   for split_id in 0..SEG_NUM {
       let key = format!("{}-{}-{}", project_id, task_id, split_id);
       if let Some(task_info) = task_info_map.get(&key) {
-          found = true;
-          if task_info.status != TaskStatus::Proven {
-              all_proven = false;
-          }
-          let status = format!("{:?}", task_info.status);
-          let task_percentage = 1.0 / SEG_NUM as f64;
-
           small_proofs.push(NewSmallProof {
               project_id: project_id.to_string(),
               task_id: task_id.to_string(),
               task_split_id: split_id.to_string(),
-              task_percentage,
-              status,
+              task_percentage: 1.0 / SEG_NUM as f64,
+              status: task_info.status.as_str().to_string(),
           });
       }
   }
 
-  if !found {
-      return Ok(ProofResponse::default());
-  }
-
-  let overall_status = if all_proven {
-      "proven".to_string()
-  } else if small_proofs.iter().any(|sp| sp.status != "created") {
-      "proving".to_string()
-  } else {
-      "created".to_string()
-  };
-
   Ok(ProofResponse {
       task_id: task_id.to_string(),
       project_id: project_id.to_string(),
-      status: overall_status,
+      status: status.as_str().to_string(),
       small_proofs,
+      on_chain: None,
   })
 }
 
-pub fn get_current_block_num() -> Option<u64>{
-    let mut writer1 = Vec::new(); 
-    let mut blocknums = vec![];
-    loop{
-
-        let url_str = match SEPOLIA_CHAIN_URLS.choose(&mut rand::thread_rng()){
-            Some(url) => url.to_string(),
-            None => continue
-          };
-      
-        writer1.clear();
-        let uri: Uri = Uri::try_from(url_str.as_str()).unwrap();
-        let res= match Request::new(&uri)
-            .method(Method::POST)
-            .header("Content-Type", "application/json")
-            .header("Content-Length", &BLOCK_NUM_BODY.len())
-            .body(BLOCK_NUM_BODY)
-            .send(&mut writer1){
-                Ok(r) => { r },
-                Err(e) => {
-                    error!("get block num :{}", e);
-                    continue;
-                },
-            };
-            if !res.status_code().is_success() {
-                continue;
-            }
-        let content = str::from_utf8(&writer1).unwrap();
-        let v: RpcResponse = match serde_json::from_str(content){
-            Ok(r) => { r },
-            Err(e) => {
-                error!("get block num :{}", e);
-                continue;
-            },
-        };
-        blocknums.push(u64::from_str_radix(v.result.trim_start_matches("0x"), 16).unwrap());
-        if blocknums.len() >= 4 {
-            return Some(*blocknums.iter().min().unwrap());
-        }  
+/// the quorum head kept by the provider set
+pub async fn get_current_block_num() -> Result<u64, String> {
+    current_head().await
+}
+
+/// average seconds per block over the last BLOCK_TIME_SAMPLE blocks, cached for BLOCK_TIME_CACHE_SECS,
+/// DEFAULT_BLOCK_TIME_SECS until it could be observed
+pub async fn block_time() -> f64 {
+    let mut cached = BLOCK_TIME_CACHE.lock().await;
+    if let Some((at, secs)) = *cached {
+        if at.elapsed() < Duration::from_secs(BLOCK_TIME_CACHE_SECS) {
+            return secs;
+        }
+    }
+    let observed = match get_current_block_num().await {
+        Ok(head) if head > BLOCK_TIME_SAMPLE => observe_block_time(head).await,
+        Ok(_) => Err("chain too short to observe block time".to_string()),
+        Err(e) => Err(e),
+    };
+    match observed {
+        Ok(secs) => {
+            *cached = Some((Instant::now(), secs));
+            secs
+        },
+        Err(e) => {
+            error!("observe block time: {}", e);
+            cached.map(|(_, secs)| secs).unwrap_or(DEFAULT_BLOCK_TIME_SECS)
+        },
     }
 }
 
-/// get the account nonce value
-pub async fn get_nonce(address:String) -> U256{
-    loop {
-        for url in SEPOLIA_CHAIN_URLS.iter() {
-            let transport = match web3::transports::Http::new(&url){
-                Ok(r)=>{r},
-                Err(_e) => {
-                    continue;
-                },
-            };
-            let web3 = web3::Web3::new(transport);
-            let addr_addr:Address=Address::from_str(address.as_str()).unwrap(); 
-            let nonce= match web3.eth().transaction_count(addr_addr,Some(Pending)).await{
-                Ok(r)=>{r},
-                Err(_e) => {
-                    continue;
-                },  
-            };
-            debug!("nonce value is {:?}",nonce.clone());
-            return nonce
+async fn observe_block_time(head: u64) -> Result<f64, String> {
+    let timestamp = |num: u64| async move {
+        with_provider("get block", |web3| async move {
+            web3.eth().block(types::BlockId::Number(types::BlockNumber::Number(num.into()))).await
+        }).await?
+        .map(|block| block.timestamp.as_u64())
+        .ok_or(format!("block {} not found", num))
+    };
+    let newest = timestamp(head).await?;
+    let oldest = timestamp(head - BLOCK_TIME_SAMPLE).await?;
+    if newest <= oldest {
+        return Err(format!("block timestamps {} and {} do not advance", oldest, newest));
     }
- }
+    Ok((newest - oldest) as f64 / BLOCK_TIME_SAMPLE as f64)
 }
 
-/// 1.2 multiple of the network gas
-pub async fn gas_price() -> U256{
-    loop {
-        for url in SEPOLIA_CHAIN_URLS.iter() {
-            let transport = match web3::transports::Http::new(&url){
-                Ok(r)=>{r},
-                Err(_e) => {
-                    continue;
-                },
-            };
-            let web3 = web3::Web3::new(transport);
-            let gas_price= match web3.eth().gas_price().await{
-                Ok(r) => r,
-                Err(_) => continue,
-            };
+/// get the account nonce value
+pub async fn get_nonce(address: Address) -> Result<U256, String> {
+    let nonce = with_provider("get nonce", |web3| async move {
+        web3.eth().transaction_count(address, Some(Pending)).await
+    }).await?;
+    debug!("nonce value is {:?}",nonce.clone());
+    Ok(nonce)
+}
 
-            let upper_gas = (gas_price.as_u64())*(100)/(50);
-            info!("gas price value is {:?}",upper_gas.clone());
-            return U256::from_dec_str(&upper_gas.to_string()).unwrap()      
-    }
- }
+/// 2 multiple of the network gas
+pub async fn gas_price() -> Result<U256, String> {
+    let gas_price = with_provider("get gas price", |web3| async move { web3.eth().gas_price().await }).await?;
+    let upper_gas = gas_price.saturating_mul(100.into()) / 50;
+    info!("gas price value is {:?}",upper_gas.clone());
+    Ok(upper_gas)
 }
 
 /// submit proof data to sepolia chain
@@ -836,208 +658,454 @@ pub async fn submit_proof(
     proof:Bytes,
 ) -> Result<String, String> { 
 
-    let url_str = SEPOLIA_CHAIN_URLS.choose(&mut rand::thread_rng()).unwrap();
-    let transport = web3::transports::Http::new(url_str).unwrap();
-    let web3 = web3::Web3::new(transport);
-
     let ctr = CONTRACT.lock().await;
     let ctr_addr = (*ctr).clone();
     let contract_address = Address::from_str(ctr_addr.as_str()).unwrap();
     
-    let func = Function {
-        name: "proveTask".to_owned(),
-        inputs: vec![
-            Param { name: "taskKey".to_owned(), kind: ParamType::FixedBytes(32), internal_type: None },
-            Param { name: "proof ".to_owned(), kind: ParamType::Bytes, internal_type: None },
-        ],
-        outputs: vec![],
-        constant: Some(false),
-        state_mutability: StateMutability::Payable,
-    };
-
-      //enocde send tx input parameters
-    let mut data_vec_input:Vec<Token>=Vec::new();
-    data_vec_input.push(Token::FixedBytes(task_key));
-    data_vec_input.push(Token::Bytes(proof.0));
-
-    let tx_data = match func.encode_input(&data_vec_input){
-        Ok(r) => r,
-        Err(e) => {
-          info!("encode input error:{:?}",e);
-          return Err("err".to_string())
-        },
-    };
-    let priv_key = PRIV_KEY.lock().await;
-    let key = (*priv_key).clone();
-    let prvk = web3::signing::SecretKey::from_str(key.as_str()).unwrap();
-    let private_key = PrivateKey::from_str(key.as_str()).unwrap();
+    let task_key = <[u8; 32]>::try_from(task_key.as_slice()).map_err(|_| format!("task key of {} bytes", task_key.len()))?;
+    let tx_data = functions::prove_task::encode_input(task_key, proof.0);
+    let signer = next_gas_signer().await?;
 
     let tx_object = TransactionParameters {
         to: Some(contract_address),
-        gas_price:Some(gas_price().await), 
+        gas_price:Some(gas_price().await?),
         gas:U256::from_dec_str(GAS_UPPER).unwrap(),
-        nonce:Some(get_nonce(private_key.address()).await),
+        nonce:Some(get_nonce(signer.address()).await?),
         data:Bytes(tx_data),
+        chain_id:Some(chain_id().await?),
         ..Default::default()
     };
         //send tx to network
-    loop {
-        let signed = match web3.accounts().sign_transaction(tx_object.clone(), &prvk).await {
-            Ok(r) => {
-                r
-            },
-            Err(_) => {
+    let raw_transaction = signer.sign_transaction(&best_web3().await?, tx_object).await?;
+    let result = send_raw_transaction("send proveTask", raw_transaction).await?;
+
+    debug!("invoke a tx hash is : {:?}",result);
+    Ok(hex::encode(result.as_bytes()))
+}
+
+/// a task as the contract stores it, zero fields when it was never submitted
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct OnChainTask {
+    pub task_key: String,
+    pub exists: bool,
+    pub instance: String,
+    /// 0x address, like chain/Owner
+    pub prover: String,
+    pub submitted_at: u64,
+    pub proven: bool,
+}
+
+impl OnChainTask {
+    fn new(task_key: [u8; 32], status: structs::TaskStatus) -> OnChainTask {
+        OnChainTask {
+            task_key: hex::encode(task_key),
+            exists: status.submitted_at != 0,
+            instance: hex::encode(status.instance),
+            prover: format!("{:?}", status.prover),
+            submitted_at: status.submitted_at,
+            proven: status.proven,
+        }
+    }
+}
+
+/// parse a hex task key, with or without 0x
+pub fn parse_task_key(task_key: &str) -> Result<[u8; 32], String> {
+    let bytes = hex::decode(task_key.trim_start_matches("0x")).map_err(|e| format!("task key {}: {}", task_key, e))?;
+    <[u8; 32]>::try_from(bytes.as_slice()).map_err(|_| format!("task key of {} bytes", bytes.len()))
+}
+
+/// split a proof id, "<task key>" or "<task key>@<segment id>", checking the task key is the 64 hex digits of a task id
+/// and the segment one of the SEG_NUM a task is cut into
+pub fn parse_proof_id(proof_id: &str) -> Result<(&str, Option<&str>), String> {
+    let (task_id, split_id) = match proof_id.split_once('@') {
+        Some((task_id, split_id)) => (task_id, Some(split_id)),
+        None => (proof_id, None),
+    };
+    if task_id.len() != 64 || !task_id.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b)) {
+        return Err(format!("proof id {:?} is not a task key of 64 lowercase hex digits", proof_id));
+    }
+    if let Some(split_id) = split_id {
+        match split_id.parse::<i32>() {
+            Ok(r) if (0..SEG_NUM).contains(&r) && r.to_string() == split_id => (),
+            _ => return Err(format!("proof id {:?} has no segment below {}", proof_id, SEG_NUM)),
+        }
+    }
+    Ok((task_id, split_id))
+}
+
+/// a proof as sent back by the schedulers and provers, hex with an optional 0x
+pub fn check_proof(proof: &str) -> Result<(), String> {
+    let digits = proof.strip_prefix("0x").unwrap_or(proof);
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err("proof is not a hex string".to_string());
+    }
+    Ok(())
+}
+
+/// eth_call a view function of the contract at the latest block
+async fn call_contract(call: &str, data: Vec<u8>) -> Result<Vec<u8>, String> {
+    let contract_address = parse_address(&CONTRACT.lock().await.clone())?;
+    let request = types::CallRequest {
+        to: Some(contract_address),
+        data: Some(Bytes(data)),
+        ..Default::default()
+    };
+    let result = with_provider(call, |web3| {
+        let request = request.clone();
+        async move { web3.eth().call(request, None).await.map_err(explain_revert) }
+    }).await?;
+    Ok(result.0)
+}
+
+/// the task's proof status from readProofStatus
+pub async fn read_proof_status(task_key: [u8; 32]) -> Result<OnChainTask, String> {
+    let output = call_contract("call readProofStatus", functions::read_proof_status::encode_input(task_key)).await?;
+    Ok(OnChainTask::new(task_key, functions::read_proof_status::decode_output(&output)?))
+}
+
+/// the task's entry in the taskStatusMap storage
+pub async fn read_task_status(task_key: [u8; 32]) -> Result<OnChainTask, String> {
+    let output = call_contract("call taskStatusMap", functions::task_status_map::encode_input(task_key)).await?;
+    let (instance, prover, submitted_at, proven) = functions::task_status_map::decode_output(&output)?;
+    Ok(OnChainTask::new(task_key, structs::TaskStatus { instance, prover, submitted_at, proven }))
+}
+
+/// the contract owner
+pub async fn read_owner() -> Result<Address, String> {
+    let output = call_contract("call owner", functions::owner::encode_input()).await?;
+    functions::owner::decode_output(&output)
+}
+
+/// seen logs by tx hash and log index, with their block, so overlapping scans hand each log over once
+type SeenLogs = HashMap<(H256, U256), u64>;
+
+/// blocks asked for in one eth_getLogs, doubled after each answer up to max and halved down to min
+/// when the provider finds the range too big; once halved it only doubles after a run of answers
+#[derive(Debug, Clone)]
+pub struct LogRange {
+    pub min: u64,
+    pub max: u64,
+    pub size: u64,
+    successes: u32,
+    shrunk: bool,
+}
+
+impl LogRange {
+    pub fn new(min: u64, max: u64) -> Result<LogRange, String> {
+        if min == 0 || max < min {
+            return Err(format!("log range {}..{} needs 1 <= min <= max", min, max));
+        }
+        Ok(LogRange { min, max, size: min, successes: 0, shrunk: false })
+    }
+
+    /// the last block of the next range after handled, not beyond head
+    fn end(&self, handled: u64, head: u64) -> u64 {
+        head.min(handled + self.size)
+    }
+
+    /// the last block of the next range starting at first, not beyond head
+    fn last(&self, first: u64, head: u64) -> u64 {
+        head.min(first.saturating_add(self.size - 1))
+    }
+
+    fn grow(&mut self) {
+        self.successes += 1;
+        if !self.shrunk || self.successes >= LOG_RANGE_GROW_AFTER {
+            self.size = (self.size * 2).min(self.max);
+            self.successes = 0;
+        }
+    }
+
+    /// halve the range when the provider says it was too big, false when it is another error
+    /// (a rate limit among them) or the range is at its minimum already
+    fn shrink(&mut self, error: &str) -> bool {
+        let lowercase = error.to_lowercase();
+        let too_big = ["query returned more than", "block range", "too many results"]
+            .iter()
+            .any(|hint| lowercase.contains(hint));
+        too_big && self.halve(error)
+    }
+
+    /// halve the range, false when it is at its minimum already
+    fn halve(&mut self, reason: &str) -> bool {
+        if self.size == self.min {
+            return false;
+        }
+        self.size = (self.size / 2).max(self.min);
+        self.successes = 0;
+        self.shrunk = true;
+        warn!("eth_getLogs range halved to {} blocks: {}", self.size, reason);
+        true
+    }
+}
+
+pub async fn set_log_range(range: LogRange) {
+    info!("eth_getLogs range {:?}", range);
+    *LOG_RANGE.lock().await = range;
+}
+
+/// the TaskSubmitted topic and the contract emitting it
+async fn task_submitted_source() -> Result<(H256, Address), String> {
+    let ctr_addr = CONTRACT.lock().await.clone();
+    Ok((events::TaskSubmitted::topic(), parse_address(&ctr_addr)?))
+}
+
+fn log_filter(address: Address, topic: H256, range: Option<(u64, u64)>) -> types::Filter {
+    let mut builder = types::FilterBuilder::default()
+        .address(vec![address])
+        .topics(Some(vec![topic]), None, None, None);
+    if let Some((start_num, end_num)) = range {
+        builder = builder.from_block(types::BlockNumber::Number(start_num.into()))
+            .to_block(types::BlockNumber::Number(end_num.into()));
+    }
+    builder.build()
+}
+
+/// hand the TaskSubmitted logs not seen yet to receive_task, which drops the tasks it knows already;
+/// a late subscription log from a block handled already is not lost that way
+async fn handle_logs(topic: H256, logs: &[types::Log], handled: u64, seen: &mut SeenLogs) {
+    for log in logs.iter() {
+        if log.removed == Some(true) {
+            warn!("TaskSubmitted log {:?} of tx {:?} removed by a reorg", log.log_index, log.transaction_hash);
+            continue;
+        }
+        if log.topics.first() != Some(&topic) {
+            continue;
+        }
+        let id = (log.transaction_hash.unwrap_or_default(), log.log_index.unwrap_or_default());
+        if seen.contains_key(&id) {
+            continue;
+        }
+        let block_num = log.block_number.unwrap_or_default().as_u64();
+        if block_num <= handled {
+            warn!("late TaskSubmitted log {:?} of tx {:?} from block {}, handled up to {}", log.log_index, log.transaction_hash, block_num, handled);
+        }
+        //a log the relayer cannot read is reported and passed over instead of stalling the monitor
+        match TaskSubmittedEvent::from_log(log) {
+            Ok(event) => add_proof_info(event).await,
+            Err(e) => error!("{}", e),
+        }
+        seen.insert(id, block_num);
+    }
+}
+
+/// everything up to handled is done, logs at or before it need not be remembered
+async fn mark_handled(handled: &mut u64, block_num: u64, seen: &mut SeenLogs) {
+    if block_num > *handled {
+        *handled = block_num;
+        seen.retain(|_, log_block| *log_block > block_num);
+        expire_quotes_before(block_num).await;
+    }
+}
+
+/// poll eth_getLogs in windows of the adaptive LOG_RANGE up to the chain head, until the deadline if any
+async fn poll_events(handled: &mut u64, seen: &mut SeenLogs, until: Option<Instant>) {
+    while until.map(|until| Instant::now() < until).unwrap_or(true) {
+        let world_num = ok_or_continue!(get_current_block_num().await, "get current block num", {
+            time::sleep(Duration::from_secs(1)).await;
+            continue
+        });
+        if *handled >= world_num {
+            time::sleep(Duration::from_secs(1)).await;
+            continue;
+        }
+        let start_num = *handled + 1;
+        let end_num = LOG_RANGE.lock().await.end(*handled, world_num);
+        let (topic, address) = ok_or_continue!(task_submitted_source().await, "TaskSubmitted source", {
+            time::sleep(Duration::from_secs(1)).await;
+            continue
+        });
+        let filter = log_filter(address, topic, Some((start_num, end_num)));
+        let logs = match with_provider("get logs", |web3| {
+            let filter = filter.clone();
+            async move { web3.eth().logs(filter).await }
+        }).await {
+            Ok(r) => r,
+            Err(e) => {
+                //a range too big is retried smaller right away
+                if !LOG_RANGE.lock().await.shrink(&e) {
+                    error!("get logs failed: {}", e);
+                    time::sleep(Duration::from_secs(1)).await;
+                }
                 continue
-            }
+            },
         };
+        LOG_RANGE.lock().await.grow();
+        info!("current process from {} to {}", start_num, end_num);
+        handle_logs(topic, &logs, *handled, seen).await;
+        //update the handled block num
+        mark_handled(handled, end_num, seen).await;
+    }
+}
 
-        let result = match web3.eth().send_raw_transaction(signed.raw_transaction).await{
-            Ok(r )=> r,
-            Err(e) => {
-                return Err(e.to_string())
+/// follow TaskSubmitted over an eth_subscribe logs subscription, after backfilling the blocks since
+/// the last handled one from the same node; returns when the subscription fails
+async fn subscribe_events(url: &str, handled: &mut u64, seen: &mut SeenLogs) -> Result<(), String> {
+    let transport = time::timeout(Duration::from_secs(WS_CONNECT_TIMEOUT_SECS), WebSocket::new(url)).await
+        .map_err(|_| "connect timeout".to_string())?
+        .map_err(|e| format!("connect: {}", e))?;
+    let web3 = Web3::new(transport);
+    let (topic, address) = task_submitted_source().await?;
+    let mut logs = web3.eth_subscribe().subscribe_logs(log_filter(address, topic, None)).await
+        .map_err(|e| format!("subscribe logs: {}", e))?;
+    let mut heads = web3.eth_subscribe().subscribe_new_heads().await
+        .map_err(|e| format!("subscribe new heads: {}", e))?;
+
+    //logs pushed from here on wait in the stream while the gap is filled, receive_task drops the ones it covers
+    let head = web3.eth().block_number().await.map_err(|e| format!("get block num: {}", e))?.as_u64();
+    info!("subscribed to TaskSubmitted on {}, backfilling {} to {}", url, *handled + 1, head);
+    while *handled < head {
+        let end_num = LOG_RANGE.lock().await.end(*handled, head);
+        let filter = log_filter(address, topic, Some((*handled + 1, end_num)));
+        let found = match time::timeout(Duration::from_secs(CALL_TIMEOUT_SECS), web3.eth().logs(filter)).await {
+            Ok(Ok(r)) => r,
+            Ok(Err(e)) => {
+                let e = format!("get logs: {}", e);
+                if LOG_RANGE.lock().await.shrink(&e) {
+                    continue;
+                }
+                return Err(e);
+            },
+            Err(_) => {
+                if LOG_RANGE.lock().await.halve("get logs timeout") {
+                    continue;
+                }
+                return Err("get logs timeout".to_string());
             },
         };
-            
-        debug!("invoke a tx hash is : {:?}",result);
-        return Ok(hex::encode(result.as_bytes()))
+        LOG_RANGE.lock().await.grow();
+        info!("backfill from {} to {}", *handled + 1, end_num);
+        handle_logs(topic, &found, *handled, seen).await;
+        mark_handled(handled, end_num, seen).await;
+    }
+
+    loop {
+        //logs already received go before any head that would count their block as handled
+        tokio::select! {
+            biased;
+            log = logs.next() => {
+                let log = log.ok_or("log subscription closed")?.map_err(|e| format!("log subscription: {}", e))?;
+                handle_logs(topic, &[log], *handled, seen).await;
+            },
+            header = heads.next() => {
+                let header = header.ok_or("head subscription closed")?.map_err(|e| format!("head subscription: {}", e))?;
+                //the logs of the newest blocks may still be on their way
+                if let Some(number) = header.number {
+                    mark_handled(handled, number.as_u64().saturating_sub(WS_HEAD_LAG), seen).await;
+                }
+            },
+        }
     }
 }
 
-macro_rules! ok_or_return{
-    ($exec: expr, $content: literal)=>{
-        match $exec {
-            Ok(value) => value,
-            Err(e) => {
-                error!("{} failed: {}", $content, e);
-                return Err(web3::error::Error::Internal);
-            }
+/// a TaskSubmitted found by replay_events
+#[derive(Serialize, Debug)]
+pub struct ReplayedTask {
+    pub event: TaskSubmittedEvent,
+    pub known: bool,
+    pub enqueued: bool,
+}
+
+/// the TaskSubmitted events of the logs, enqueueing the tasks not known, queued or being dispatched unless dry_run
+async fn replay_logs(topic: H256, logs: &[types::Log], dry_run: bool) -> Result<Vec<ReplayedTask>, String> {
+    let mut replayed = vec![];
+    for log in logs.iter() {
+        if log.removed == Some(true) || log.topics.first() != Some(&topic) {
+            continue;
         }
-    };
-  }
+        let event = TaskSubmittedEvent::from_log(log)?;
+        let known = task_known("demo", &event.task_id()).await?;
+        let enqueued = !known && !dry_run && receive_task(event.clone()).await;
+        replayed.push(ReplayedTask { event, known, enqueued });
+    }
+    Ok(replayed)
+}
+
+/// re-read the TaskSubmitted events of blocks from..=to into the live relayer's queue, see replay_logs;
+/// the monitor's handled block is left alone
+pub async fn replay_events(from: u64, to: u64, dry_run: bool) -> Result<Vec<ReplayedTask>, String> {
+    if from > to {
+        return Err(format!("replay range {}..{} is empty", from, to));
+    }
+    let (topic, address) = task_submitted_source().await?;
+    let mut replayed = vec![];
+    let mut next = from;
+    loop {
+        let end_num = LOG_RANGE.lock().await.last(next, to);
+        let filter = log_filter(address, topic, Some((next, end_num)));
+        let logs = match with_provider("get logs", |web3| {
+            let filter = filter.clone();
+            async move { web3.eth().logs(filter).await }
+        }).await {
+            Ok(r) => r,
+            Err(e) if LOG_RANGE.lock().await.shrink(&e) => continue,
+            Err(e) => return Err(e),
+        };
+        LOG_RANGE.lock().await.grow();
+        info!("replay from {} to {}, {} logs", next, end_num, logs.len());
+        replayed.extend(replay_logs(topic, &logs, dry_run).await?);
+        if end_num >= to {
+            break;
+        }
+        next = end_num + 1;
+    }
+    Ok(replayed)
+}
 
 ///monitor the log emitted by zkpool contract and save it to msg queue
 pub async fn monitor_event(start_block_num:u64) { 
     info!("loop scan block event progrom begin start block:{}",start_block_num);
     let mut handle_block_num =start_block_num;
-    'outer: loop{
-        let url_str = match SEPOLIA_CHAIN_URLS.choose(&mut rand::thread_rng()){
-            Some(url) => url,
-            None => continue
-          };
-
-        let mut builder = Client::builder();
-        builder = builder.user_agent(headers::HeaderValue::from_static("web3.rs"));
-        builder = builder.timeout(std::time::Duration::from_secs(30));
-        let client = ok_or_continue!(builder.build(), "build web3 client");
-        let url: Url = ok_or_continue!(url_str.parse(), format!("url.parse() {}", url_str));
-        let web3 = Web3::new(Http::with_client(client, url.clone()));
-
-        let world_num = ok_or_continue!(web3.eth().block_number().await, "world_num web3.eth().block_number()").as_u64();
-        let mut start_num = 0;
-        let mut end_num = start_num;
-        if handle_block_num < world_num {
-          start_num = handle_block_num + 1;
-          end_num = if world_num - handle_block_num > 10 { handle_block_num + 10 } else { world_num };
-        }else {
-            time::sleep(Duration::from_secs(1)).await;
+    let mut seen = SeenLogs::new();
+    let mut ws_index = 0;
+    loop {
+        let ws_urls = ws_provider_urls().await;
+        if ws_urls.is_empty() {
+            poll_events(&mut handle_block_num, &mut seen, None).await;
             continue;
         }
-
-        let matic_contract = ok_or_continue!(ethabi::Contract::load(MATIC_CONTRACT_ABI), "ethabi::Contract::load MATIC_CONTRACT_ABI");
-
-        let event_insert = ok_or_continue!(matic_contract.event("TaskSubmitted"), "matic_contract.event Insert");
-        let topic_insert_filter = ok_or_continue!(event_insert.filter(ethabi::RawTopicFilter {
-            topic0: ethabi::Topic::Any,
-            topic1: ethabi::Topic::Any,
-            topic2: ethabi::Topic::Any,
-        }), "event_insert.filter");
-
-        let ctr = CONTRACT.lock().await;
-        let ctr_addr = (*ctr).clone();
-
-        let contract_addr_hex = ok_or_continue!(hex::decode(ctr_addr.as_str()), "hex::decode(ZKPOOL_CONTRACT_ADDR)");
-        if let ethabi::Topic::This(topic1) = topic_insert_filter.topic0 {
-        let filter = types::FilterBuilder::default()
-            .address(vec![ethabi::ethereum_types::H160::from_slice(&contract_addr_hex)])
-            .topics(
-                Some(vec![topic1]),
-                None,
-                None,
-                None,
-            ).from_block(types::BlockNumber::Number(start_num.into()))
-            .to_block(types::BlockNumber::Number(end_num.into()))
-            .build();
-        let logs = ok_or_continue!(web3.eth().logs(filter).await, "web3.eth().logs(filter)");
-        info!("current process from {} to {}", start_num, end_num);
-        for log in logs.iter(){    
-            let mut temp = EmitProvenTaskMessage::default(); 
-    
-            if log.topics.get(0).eq(&Some(&topic1)){
-              ok_or_continue!(add_proof_info(event_insert, &mut temp, log).await, "add_info insert", continue 'outer);
-            }
-          }
+        let url = &ws_urls[ws_index % ws_urls.len()];
+        ws_index += 1;
+        if let Err(e) = subscribe_events(url, &mut handle_block_num, &mut seen).await {
+            error!("log subscription on {} failed: {}, polling for {}s", url, e, WS_RETRY_SECS);
         }
-        //update the handled block num
-        handle_block_num=end_num;
+        poll_events(&mut handle_block_num, &mut seen, Some(Instant::now() + Duration::from_secs(WS_RETRY_SECS))).await;
     }
 }
 
-pub async fn add_proof_info(event: &ethabi::Event, temp: &mut EmitProvenTaskMessage, log: &types::Log) -> web3::Result{ 
-    let info = ok_or_return!(event.parse_log(ethabi::RawLog{
-      topics: log.topics.clone(),
-      data: log.data.0.clone()
-    }), "event.parse_log");
-  
-    for item in info.params.iter(){
-      match &item.value{
-        ethabi::Token::Address(content) => {
-          if item.name.eq("requester") {
-            temp.requester = hex::encode(content.as_bytes());
-          }else if item.name.eq("prover"){
-            temp.prover = hex::encode(content.as_bytes());
-          }else if item.name.eq("rewardToken"){
-            temp.reward_token = hex::encode(content.as_bytes());
-          }else if item.name.eq("liabilityToken"){
-            temp.liability_token = hex::encode(content.as_bytes());
-          }
-        },
-        ethabi::Token::Uint(content) => {
-            if item.name.eq("rewardAmount"){
-              temp.reward_amount = content.to_string();
-            }else if item.name.eq("liabilityWindow"){
-              temp.liability_window = content.to_string();
-            }
-            else if item.name.eq("liabilityAmount"){
-                temp.liability_amount = content.to_string();
-              }
-          },
-          ethabi::Token::Bytes(content) => {
-            temp.instance = hex::encode(content);
-          },
-          ethabi::Token::FixedBytes(content) => {
-            temp.task_key=hex::encode(content);
-        },
-        _ => {
-          continue;
-        }
-      }
-    }
-    info!("receive the proof info need to be proven :{:?}",temp);
-    receive_task(temp.instance.clone(), temp.task_key.clone()).await;
-    Ok(())
+pub async fn add_proof_info(event: TaskSubmittedEvent) {
+    info!("receive the proof info need to be proven :{:?}",event);
+    receive_task(event).await;
 }
 ///no need to verify onchain
 pub async fn process_proof_data(msg: &ProofMessage){  
-  let tasks: Vec<&str> = msg.task_id.split("@").collect();
-  if tasks.len() == 1 {
-      #[cfg(feature = "DB")]
-      {
-          // whole proof
-          set_big_proof_status("demo", &msg.task_id, "proven").await.unwrap();
+  let (task_id, split_id) = match parse_proof_id(&msg.task_id) {
+      Ok(r) => r,
+      Err(e) => {
+          error!("proof dropped: {}", e);
+          return;
+      }
+  };
+  let task_key = match parse_task_key(task_id) {
+      Ok(r) => r,
+      Err(e) => {
+          error!("proof dropped: {}", e);
+          return;
+      }
+  };
+  match split_id {
+    None => {
+      // whole proof
+      if let Err(e) = set_task_status("demo", task_id, TaskStatus::Proven).await {
+          error!("task {} proof not accepted: {}", task_id, e);
+          return;
       }
 
-      let task_id = tasks[0];
-      match submit_proof(hex::decode(task_id).unwrap(), Bytes::from(msg.proof.clone())).await{
+      let result = submit_proof(task_key.to_vec(), Bytes::from(msg.proof.clone())).await;
+      if let Err(e) = set_task_submission("demo", task_id, &result).await {
+          error!("record task {} submission: {}", task_id, e);
+      }
+      match result {
         Ok(r) => {
             info!("****** sbumit task_key:{} proof tx success,tx hash is: {}",task_id,r)
         },
@@ -1045,60 +1113,43 @@ pub async fn process_proof_data(msg: &ProofMessage){
             error!("sbumit proof tx failed")
         },
       };
-      #[cfg(feature = "DB")]
-      {
-          set_big_proof_status("demo", &msg.task_id, "submitted").await.unwrap();
-      }
-    } else if tasks.len() == 2 {
+    },
+    Some(split_id) => {
       // segement proof
-
-      let task_id = tasks[0];
-      let split_id = tasks[1];
-      let task_info_map = TASK_INFO.lock().await;
-      let key = format!("{}-{}-{}", "demo", task_id, split_id);
-      match task_info_map.get(&key) {
-        Some(task_info) => {
-          if task_info.status == TaskStatus::Proven {
-              return;
-          }
-        }
+      match segment_status("demo", task_id, split_id).await {
+        Some(TaskStatus::Proven) => return,
+        Some(_) => (),
         None => {
          info!("error, no such task");
+         return;
         }
       }
 
-      let task_info = task_info_map.get(&key).unwrap().clone();
-      if task_info.status == TaskStatus::Proven {
+      if let Err(e) = set_segment_status("demo", task_id, split_id, TaskStatus::Proven).await {
+          error!("segment {} proof not accepted: {}", msg.task_id, e);
           return;
       }
-      drop(task_info_map);
-
-      #[cfg(feature = "DB")]
-      {
-          set_small_proof_status_and_percentage("demo", &task_info.task_id, &task_info.split_id, "proven", 1.0/SEG_NUM as f64).await.unwrap();
-      }
-      update_task_status(&task_info.project_id, &task_info.task_id, &task_info.split_id, "proven").await.unwrap();
-      let task_info_map = TASK_INFO.lock().await;
+      segment_done(&msg.task_id).await;
       let mut all_proven = true;
       for i in 0..SEG_NUM {
-          let key = format!("{}-{}-{}", task_info.project_id, task_info.task_id, i);
-          let task_info = task_info_map.get(&key).unwrap();
-          if task_info.status != TaskStatus::Proven {
+          if segment_status("demo", task_id, &i.to_string()).await != Some(TaskStatus::Proven) {
               all_proven = false;
               break;
           }
       }
       if all_proven {
-        #[cfg(feature = "DB")]
-        {
-           set_big_proof_status("demo", &task_info.task_id, "proven").await.unwrap();
+        for status in [TaskStatus::Proven, TaskStatus::Aggregated] {
+            if let Err(e) = set_task_status("demo", task_id, status).await {
+                error!("task {} not submitted: {}", task_id, e);
+                return;
+            }
+        }
+        let result = submit_proof(task_key.to_vec(), Bytes::from(msg.proof.clone())).await;
+        if let Err(e) = set_task_submission("demo", task_id, &result).await {
+            error!("record task {} submission: {}", task_id, e);
         }
-        match submit_proof(hex::decode(task_id).unwrap(), Bytes::from(msg.proof.clone())).await{
+        match result {
             Ok(r) => {
-                #[cfg(feature = "DB")]
-                {
-                    set_big_proof_status("demo", &task_info.task_id, "submitted").await.unwrap();
-                }
                 info!("****** sbumit task_key:{} proof tx success,tx hash is: {}",task_id,r)
             },
             Err(_) => {
@@ -1106,27 +1157,59 @@ pub async fn process_proof_data(msg: &ProofMessage){
             },
         };
       }
-
-    } else {
-      // assert here
-      panic!("block format error");
-    }
+    },
+  }
 }
 
-pub async fn receive_task(instance:String,task_key:String){
-    info!("receive onchain task info data: {}-{},add to queue",instance,task_key);
-    let msg:ProvenTaskMessage=ProvenTaskMessage { instance,task_key };
+/// queue a TaskSubmitted, false when the task is queued or taken already and the event is a duplicate
+pub async fn receive_task(event:TaskSubmittedEvent) -> bool {
+    //the dispatcher moves a task from the queue to DISPATCHING under the queue lock, so the check and the push see a consistent state
     let mut queue = TASK_MSG_QUEUE.lock().await;
+    let task_id = event.task_id();
+    let known = task_known("demo", &task_id).await.unwrap_or_else(|e| {
+        error!("check task {}: {}", task_id, e);
+        false
+    });
+    let dispatching = DISPATCHING.lock().await.contains(&task_id);
+    if known || dispatching || queue.iter().any(|msg| msg.event.task_key == event.task_key) {
+        info!("duplicate TaskSubmitted for task {} in tx {:?}, ignored", task_id, event.tx_hash);
+        return false;
+    }
+    info!("receive onchain task info data: {}-{},add to queue",hex::encode(&event.instance.0),task_id);
+    let our_prover = prover_signer().map(|signer| format!("{:?}", signer.address())).unwrap_or_default();
+    match_task_submitted(&event, &our_prover).await;
+    let msg:ProvenTaskMessage=ProvenTaskMessage { event, received_at: Utc::now() };
     queue.push_back(msg);
+    true
 }
 
 pub async fn loop_task_data() -> web3::Result<()> {
-    let mut queue = TASK_MSG_QUEUE.lock().await;
-    while queue.len() > 0 {
-        info!(" start to process the task data queue len :{}",queue.len());
-        let item = queue.pop_front().unwrap();
+    loop {
+        let waiting = TASK_MSG_QUEUE.lock().await.len();
+        if waiting == 0 {
+            break;
+        }
+        //the liability clock runs from TaskSubmitted, hold tasks while the scheduler is full
+        if let Err(refusal) = admit_dispatch().await {
+            info!("hold {} tasks, {}, retry in {}s", waiting, refusal.reason, refusal.retry_after);
+            break;
+        }
+        info!(" start to process the task data queue len :{}",waiting);
+        //deliver without the queue lock, receive_task sees the task in DISPATCHING meanwhile
+        let item = {
+            let mut queue = TASK_MSG_QUEUE.lock().await;
+            let item = match queue.pop_front() {
+                Some(r) => r,
+                None => break,
+            };
+            DISPATCHING.lock().await.insert(item.task_id());
+            item
+        };
         process_task_data(&item).await;
+        DISPATCHING.lock().await.remove(&item.task_id());
     }
+    expire_overdue_tasks().await;
+    retry_overdue_segments().await;
     Ok(())
 }
 
@@ -1147,71 +1230,314 @@ pub async fn  process_task_data(msg: &ProvenTaskMessage)-> bool{
     //cache the task_key according to its task_id
     // let task_key_temp = TASK_KEY_CACHE.clone();
     // let mut task_key_map = task_key_temp.lock().await;
-    // task_key_map.insert((task_id%10000).to_string(), msg.task_key.clone());
+    // task_key_map.insert((task_id%10000).to_string(), task_id);
+    let task_id = msg.task_id();
+    let instance = hex::encode(&msg.event.instance.0);
+    //a task is dispatched once, whichever way it came in again
     #[cfg(feature = "DB")]
-    {
-        add_big_proof("demo", &msg.task_key).await.unwrap();
+    let fresh = add_big_proof("demo", msg).await;
+    #[cfg(not(feature = "DB"))]
+    let fresh = task_known("demo", &task_id).await.map(|known| !known);
+    match fresh {
+        Ok(true) => (),
+        Ok(false) => {
+            info!("task {} taken already, not dispatched again", task_id);
+            return true
+        },
+        Err(e) => {
+            error!("store task {}: {}", task_id, e);
+            return false
+        },
     }
+    update_big_task_status("demo", &task_id, TaskStatus::Created).await.unwrap();
+    let liability_window = msg.event.liability_window as i64;
+    set_task_deadline("demo", &task_id, msg.received_at + chrono::Duration::seconds(liability_window)).await;
+    let timeout = segment_timeout(msg.event.liability_window);
+    set_task_status("demo", &task_id, TaskStatus::Dispatched).await.unwrap();
 
     for split_id in 0..SEG_NUM {
       #[cfg(feature = "DB")]
       {
-          add_small_proof("demo", &msg.task_key, &split_id.to_string()).await.unwrap();
+          add_small_proof("demo", &task_id, &split_id.to_string()).await.unwrap();
       }
+      set_segment_status("demo", &task_id, &split_id.to_string(), TaskStatus::Dispatched).await.unwrap();
 
       // Concat msg.task_key and split_id string with # charater, and get a new msg.task_key
-      let new_task_key = format!("{}@{}", msg.task_key, split_id.to_string());
+      let new_task_key = format!("{}@{}", task_id, split_id);
 
-      let request = RpcRequest {
-          jsonrpc: "2.0".to_string(),
-          method: "DelieveTask".to_string(),
-          params: vec!["demo".to_string(),new_task_key,msg.instance.clone(),"1".to_string()],
-          id: "1".to_string(),
+      let segment = Segment {
+          project_id: "demo".to_string(),
+          task_id: new_task_key,
+          instance: instance.clone(),
+          degree: "1".to_string(),
       };
+      info!("try to send task key:{:?},proof task:{}, split id:{:?} to a scheduler",task_id,instance,split_id);
+      match deliver(&segment, timeout).await {
+          Ok(url) => info!("segment {} sent to scheduler {}",segment.task_id,url),
+          Err(e) => {
+              error!("send segment {} to scheduler: {}", segment.task_id, e);
+              fail_segment("demo", &task_id, &split_id.to_string()).await;
+              return false
+          },
+      }
+      // the scheduler took the segment
+      set_segment_status("demo", task_id.as_str(), split_id.to_string().as_str(), TaskStatus::Proving).await.unwrap();
+    }
+    set_task_status("demo", task_id.as_str(), TaskStatus::Proving).await.unwrap();
+  true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use web3::ethabi::Token;
+
+    const ALL_STATUSES: [TaskStatus; 9] = [
+        TaskStatus::Created, TaskStatus::Dispatched, TaskStatus::Proving, TaskStatus::Proven, TaskStatus::Aggregated,
+        TaskStatus::Submitted, TaskStatus::Confirmed, TaskStatus::Failed, TaskStatus::Expired,
+    ];
+
+    #[test]
+    fn task_status_happy_path() {
+        let path = [TaskStatus::Created, TaskStatus::Dispatched, TaskStatus::Proving, TaskStatus::Proven,
+            TaskStatus::Aggregated, TaskStatus::Submitted, TaskStatus::Confirmed];
+        for pair in path.windows(2) {
+            assert!(pair[0].can_transition_to(pair[1]), "{} -> {}", pair[0], pair[1]);
+            //only a re-sent segment goes back
+            if pair[1] != TaskStatus::Proving {
+                assert!(!pair[1].can_transition_to(pair[0]), "{} -> {}", pair[1], pair[0]);
+            }
+        }
+        assert!(TaskStatus::Proven.can_transition_to(TaskStatus::Submitted));
+        assert!(TaskStatus::Proving.can_transition_to(TaskStatus::Dispatched));
+        assert!(!TaskStatus::Created.can_transition_to(TaskStatus::Proven));
+        assert!(!TaskStatus::Dispatched.can_transition_to(TaskStatus::Submitted));
+    }
 
-      let mut writer_buffer = Vec::new();
-      let mut retry = 0 ;
+    #[test]
+    fn task_status_terminal_and_failure() {
+        for status in ALL_STATUSES {
+            assert!(status.can_transition_to(status), "{} -> itself", status);
+            for next in ALL_STATUSES.into_iter().filter(|&next| next != status) {
+                if status.is_terminal() {
+                    assert!(!status.can_transition_to(next), "{} -> {}", status, next);
+                    assert!(status.check_transition(next).is_err());
+                } else if matches!(next, TaskStatus::Failed | TaskStatus::Expired) {
+                    assert!(status.can_transition_to(next), "{} -> {}", status, next);
+                }
+            }
+        }
+    }
 
-      let scheduler_url = SCHEDULER_URL.lock().await;
-      let scheduler_endpoint = (*scheduler_url).clone();
-      info!("try to send task key:{:?},proof task:{}, split id:{:?} to scheduler service:{:?}",msg.task_key.clone(),msg.instance,split_id,scheduler_endpoint.clone());
-      loop{
-          if retry==1 {
-              error!("send task to scheduler error of msg :{:?}", msg.clone());
-              return false
-          }
-          writer_buffer.clear();
-
-          let parameter_string=serde_json::to_string(&request).unwrap();
-
-          let uri: Uri = Uri::try_from(scheduler_endpoint.as_str()).unwrap();
-          let _res= match Request::new(&uri)
-              .method(Method::POST)
-              .header("Content-Type", "application/json")
-              .header("Content-Length", &parameter_string.as_bytes().len())
-              .body(parameter_string.as_bytes())
-              .send(&mut writer_buffer){
-                  Ok(r) => { r },
-                  Err(_) => {
-                      retry=retry+1;
-                      continue;
-                  },
-              };
-          let content = str::from_utf8(&writer_buffer).unwrap();
-          info!("send result is {:?}",content);
-          break;
+    fn task_submitted_log(task_key: [u8; 32], log_index: u64) -> types::Log {
+        let data = web3::ethabi::encode(&[
+            Token::Bytes(vec![1, 2, 3]),
+            Token::FixedBytes(task_key.to_vec()),
+            Token::Address(Address::repeat_byte(3)),
+            Token::Uint(1000.into()),
+            Token::Uint(3600.into()),
+            Token::Address(Address::repeat_byte(4)),
+            Token::Uint(10.into()),
+        ]);
+        types::Log {
+            address: Address::repeat_byte(5),
+            topics: vec![events::TaskSubmitted::topic(), H256::from(Address::repeat_byte(1)), H256::from(Address::repeat_byte(2))],
+            data: Bytes(data),
+            block_number: Some(95.into()),
+            transaction_hash: Some(H256::repeat_byte(9)),
+            log_index: Some(log_index.into()),
+            block_hash: None,
+            transaction_index: None,
+            transaction_log_index: None,
+            log_type: None,
+            removed: None,
+        }
+    }
+
+    #[test]
+    fn task_submitted_from_log() {
+        let log = task_submitted_log([0xa6; 32], 4);
+        let event = TaskSubmittedEvent::from_log(&log).unwrap();
+        assert_eq!((event.requester, event.prover), (Address::repeat_byte(1), Address::repeat_byte(2)));
+        assert_eq!((event.instance.0.clone(), event.task_id()), (vec![1, 2, 3], "a6".repeat(32)));
+        assert_eq!((event.reward_token, event.reward_amount, event.liability_window), (Address::repeat_byte(3), 1000.into(), 3600));
+        assert_eq!((event.liability_token, event.liability_amount), (Address::repeat_byte(4), 10.into()));
+        assert_eq!((event.block_number, event.tx_hash, event.log_index), (95, H256::repeat_byte(9), 4.into()));
+        //carried whole to the proof message, the queue and the RPC answers
+        let json = serde_json::to_value(ProvenTaskMessage { event, received_at: Utc::now() }).unwrap();
+        assert_eq!(json["event"]["block_number"], 95);
+
+        let pending = types::Log { block_number: None, ..log.clone() };
+        assert!(TaskSubmittedEvent::from_log(&pending).unwrap_err().contains("no block"));
+        let other_event = types::Log { topics: vec![H256::repeat_byte(7), log.topics[1], log.topics[2]], ..log.clone() };
+        assert!(TaskSubmittedEvent::from_log(&other_event).unwrap_err().contains("does not match the contract abi"));
+        let short_data = types::Log { data: Bytes(log.data.0[..64].to_vec()), ..log.clone() };
+        assert!(TaskSubmittedEvent::from_log(&short_data).is_err());
+        let missing_topic = types::Log { topics: log.topics[..2].to_vec(), ..log };
+        assert!(TaskSubmittedEvent::from_log(&missing_topic).is_err());
+    }
+
+    #[tokio::test]
+    async fn replay_skips_known_tasks() {
+        let topic = events::TaskSubmitted::topic();
+        let logs = [task_submitted_log([0xa1; 32], 0), task_submitted_log([0xa2; 32], 1)];
+        let taken = hex::encode([0xa1u8; 32]);
+        //taken by the relayer before, from the monitor
+        TASK_STATE.lock().await.insert(format!("demo-{}", taken), TaskState {
+            project_id: "demo".to_string(), task_id: taken.clone(), status: TaskStatus::Dispatched, deadline: None,
+        });
+        let queued = |id: String| async move { TASK_MSG_QUEUE.lock().await.iter().filter(|msg| msg.task_id() == id).count() };
+
+        let replayed = replay_logs(topic, &logs[..1], false).await.unwrap();
+        assert_eq!(replayed.iter().map(|task| (task.known, task.enqueued)).collect::<Vec<_>>(), vec![(true, false)]);
+        assert_eq!(queued(taken.clone()).await, 0);
+
+        //with the DB feature an unknown task is looked up in the database
+        #[cfg(not(feature = "DB"))]
+        {
+            let missed = hex::encode([0xa2u8; 32]);
+            let dry = replay_logs(topic, &logs, true).await.unwrap();
+            assert_eq!(dry.iter().map(|task| (task.known, task.enqueued)).collect::<Vec<_>>(), vec![(true, false), (false, false)]);
+            assert_eq!(queued(missed.clone()).await, 0);
+            let first = replay_logs(topic, &logs, false).await.unwrap();
+            assert_eq!(first.iter().map(|task| (task.known, task.enqueued)).collect::<Vec<_>>(), vec![(true, false), (false, true)]);
+            //the same range again, the missed task is queued already
+            let again = replay_logs(topic, &logs, false).await.unwrap();
+            assert_eq!(again.iter().map(|task| (task.known, task.enqueued)).collect::<Vec<_>>(), vec![(true, false), (false, false)]);
+            assert_eq!((queued(taken).await, queued(missed).await), (0, 1));
         }
-      // call update_task_status
-      update_task_status("demo", msg.task_key.as_str(), split_id.to_string().as_str(), "proving").await.unwrap();
-      #[cfg(feature = "DB")]
-      {
-        // call set_small_proof_status_and_percentage and set status as proving and percentage is 1/SEG_NUM
-        set_small_proof_status_and_percentage("demo", msg.task_key.as_str(), split_id.to_string().as_str(), "proving", 1.0/SEG_NUM as f64).await.unwrap();
-      }
     }
+
     #[cfg(feature = "DB")]
-    { 
-        set_big_proof_status("demo", msg.task_key.as_str(), "proving").await.unwrap();
+    #[tokio::test]
+    async fn restart_restores_open_tasks_from_the_store() {
+        init_test_pool();
+        let mut msg = ProvenTaskMessage { received_at: Utc::now(), ..Default::default() };
+        msg.event.task_key = H256::repeat_byte(0xb1);
+        msg.event.liability_window = 3600;
+        let task_id = msg.task_id();
+        let (task, segment) = (format!("restore-{}", task_id), format!("restore-{}-0", task_id));
+        assert!(add_big_proof("restore", &msg).await.unwrap());
+        assert!(add_small_proof("restore", &task_id, "0").await.unwrap());
+        set_task_status("restore", &task_id, TaskStatus::Dispatched).await.unwrap();
+        set_segment_status("restore", &task_id, "0", TaskStatus::Dispatched).await.unwrap();
+        set_segment_status("restore", &task_id, "0", TaskStatus::Proving).await.unwrap();
+
+        //a restart forgets the local maps, the stored status still decides
+        TASK_STATE.lock().await.remove(&task);
+        TASK_INFO.lock().await.remove(&segment);
+        assert!(set_task_status("restore", &task_id, TaskStatus::Created).await.is_err());
+        assert!(!TASK_STATE.lock().await.contains_key(&task));
+
+        restore_task_state().await.unwrap();
+        let state = TASK_STATE.lock().await.get(&task).cloned().unwrap();
+        assert_eq!(state.status, TaskStatus::Dispatched);
+        assert!(state.deadline.is_some());
+        let info = TASK_INFO.lock().await.get(&segment).cloned().unwrap();
+        assert_eq!(info.status, TaskStatus::Proving);
+        assert!(info.dispatched_at.is_some());
+        set_task_status("restore", &task_id, TaskStatus::Proving).await.unwrap();
+        assert_eq!(get_big_proof_status("restore", &task_id).await.unwrap(), "proving");
+    }
+
+    #[tokio::test]
+    async fn logs_handled_once_across_subscription_and_backfill() {
+        #[cfg(feature = "DB")]
+        init_test_pool();
+        let topic = events::TaskSubmitted::topic();
+        let at_block = |task_key: [u8; 32], log_index: u64, block: u64| {
+            let mut log = task_submitted_log(task_key, log_index);
+            log.block_number = Some(block.into());
+            log
+        };
+        let queued = |task_key: [u8; 32]| async move {
+            TASK_MSG_QUEUE.lock().await.iter().filter(|msg| msg.event.task_key == H256::from(task_key)).count()
+        };
+        let mut seen = SeenLogs::new();
+        let mut handled = 100;
+
+        //pushed by the subscription, then found again by the backfill of its block
+        let pushed = at_block([0x40; 32], 0, 101);
+        handle_logs(topic, std::slice::from_ref(&pushed), handled, &mut seen).await;
+        handle_logs(topic, &[pushed, at_block([0x41; 32], 1, 102)], handled, &mut seen).await;
+        assert_eq!((queued([0x40; 32]).await, queued([0x41; 32]).await), (1, 1));
+
+        //a reorged log, another event of the contract and a late log from a handled block
+        let mut removed = at_block([0x42; 32], 2, 102);
+        removed.removed = Some(true);
+        let mut other = at_block([0x43; 32], 3, 102);
+        other.topics[0] = H256::repeat_byte(0xee);
+        handle_logs(topic, &[removed, other, at_block([0x44; 32], 4, 99)], handled, &mut seen).await;
+        assert_eq!((queued([0x42; 32]).await, queued([0x43; 32]).await, queued([0x44; 32]).await), (0, 0, 1));
+        assert_eq!(seen.len(), 3);
+
+        //handled blocks forget their logs, the handled block never moves back
+        mark_handled(&mut handled, 101, &mut seen).await;
+        assert_eq!((handled, seen.len()), (101, 1));
+        mark_handled(&mut handled, 90, &mut seen).await;
+        assert_eq!((handled, seen.len()), (101, 1));
+        TASK_MSG_QUEUE.lock().await.retain(|msg| msg.event.task_key.as_bytes()[0] & 0xf0 != 0x40);
+    }
+
+    #[test]
+    fn on_chain_task_from_the_contract() {
+        let task_key = [0xc4u8; 32];
+        let output = web3::ethabi::encode(&[Token::Bytes(vec![1, 2, 3]), Token::Address(Address::repeat_byte(2)),
+            Token::Uint(1_700_000_000u64.into()), Token::Bool(true)]);
+        let (instance, prover, submitted_at, proven) = functions::task_status_map::decode_output(&output).unwrap();
+        let task = OnChainTask::new(task_key, structs::TaskStatus { instance, prover, submitted_at, proven });
+        let json = serde_json::to_value(&task).unwrap();
+        assert_eq!(json["task_key"], "c4".repeat(32));
+        assert_eq!(json["instance"], "010203");
+        assert_eq!(json["prover"], format!("0x{}", "02".repeat(20)));
+        assert_eq!((json["exists"].clone(), json["submitted_at"].clone(), json["proven"].clone()), (true.into(), 1_700_000_000u64.into(), true.into()));
+        assert_eq!(task.prover, format!("{:?}", functions::owner::decode_output(&web3::ethabi::encode(&[Token::Address(Address::repeat_byte(2))])).unwrap()));
+
+        //an unknown task is all zero, and a short answer is an error rather than a default
+        let unknown = functions::read_proof_status::decode_output(&web3::ethabi::encode(&[Token::Tuple(vec![Token::Bytes(vec![]),
+            Token::Address(Address::zero()), Token::Uint(0u64.into()), Token::Bool(false)])])).unwrap();
+        let task = OnChainTask::new(task_key, unknown);
+        assert!(!task.exists && !task.proven && task.instance.is_empty());
+        assert!(functions::task_status_map::decode_output(&output[..64]).is_err());
+    }
+
+    #[test]
+    fn proof_ids_and_proofs() {
+        let task_key = "0a".repeat(32);
+        assert_eq!(parse_proof_id(&task_key), Ok((task_key.as_str(), None)));
+        assert_eq!(parse_proof_id(&format!("{}@3", task_key)), Ok((task_key.as_str(), Some("3"))));
+        for proof_id in [format!("{}@4", task_key), format!("{}@03", task_key), format!("{}@", task_key),
+            task_key.to_uppercase(), format!("0x{}", &task_key[2..]), task_key[1..].to_string(), "@0".to_string()] {
+            assert!(parse_proof_id(&proof_id).is_err(), "{}", proof_id);
+        }
+        assert!(check_proof("0x00ff").is_ok());
+        assert!(check_proof("AbC1").is_ok());
+        for proof in ["", "0x", "proof", "0x 1"] {
+            assert!(check_proof(proof).is_err(), "{}", proof);
+        }
+    }
+
+    #[test]
+    fn log_range_from_block_zero() {
+        let range = LogRange::new(10, 100).unwrap();
+        assert_eq!(range.last(0, 1000), 9);
+        assert_eq!(range.last(0, 3), 3);
+        assert_eq!(range.last(u64::MAX - 2, u64::MAX), u64::MAX);
+        assert_eq!(range.end(0, 1000), 10);
+    }
+
+    #[test]
+    fn log_range_shrinks_on_range_errors_only() {
+        let mut range = LogRange::new(10, 100).unwrap();
+        range.size = 80;
+        assert!(range.shrink("get logs: Query returned more than 10000 results"));
+        assert!(range.shrink("get logs: eth_getLogs block range is too wide"));
+        assert!(range.shrink("get logs: too many results, try a smaller range"));
+        assert_eq!(range.size, 10);
+        assert!(!range.shrink("get logs: too many results"));
+        range.size = 80;
+        assert!(!range.shrink("get logs: 429 rate limit exceeded"));
+        assert!(!range.shrink("get logs: daily request limit reached"));
+        assert_eq!(range.size, 80);
     }
-  return true
 }
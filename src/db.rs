@@ -1,125 +1,612 @@
-
 use diesel::pg::PgConnection;
+use diesel::sqlite::SqliteConnection;
 use diesel::prelude::*;
+use diesel::r2d2::{self, ManageConnection, R2D2Connection};
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 use dotenvy::dotenv;
 use std::env;
-use parking_lot::Mutex;
-use std::sync::Arc;
-use crate::models::{BigProof, SmallProof,NewBigProof, NewSmallProof};
-use crate::schema::{big_proofs,small_proofs};
-use diesel::sql_types::{Varchar, Float8};
-
-use lazy_static::lazy_static;
-
-#[derive(QueryableByName)]
-struct BigProofStatus {
-    #[sql_type = "Varchar"]
-    status: String,
+use std::sync::OnceLock;
+use std::time::Duration;
+use log::info;
+use chrono::Utc;
+use crate::models::{BigProof, SmallProof,NewBigProof, NewSmallProof, TaskEvent, NewTaskEvent, NewQuote, Quote};
+use crate::schema::{big_proofs,small_proofs,task_events,quotes};
+use crate::chain::{SEG_NUM, ProvenTaskMessage, TaskStatus};
+use crate::quote_registry::QuoteStatus;
+use crate::server::ProofResponse;
+
+pub const PG_MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+pub const SQLITE_MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations_sqlite");
+
+pub const DEFAULT_POOL_SIZE: u32 = 10;
+pub const DEFAULT_POOL_TIMEOUT_SECS: u64 = 10;
+
+/// one connection type for both backends, picked by the DATABASE_URL scheme.
+/// single row inserts are not supported by the multi backend, so they match on the variant
+#[derive(diesel::MultiConnection)]
+pub enum DbConnection {
+    Postgresql(PgConnection),
+    Sqlite(SqliteConnection),
 }
 
-#[derive(QueryableByName)]
-struct SmallProofStatusAndPercentage {
-    #[sql_type = "Varchar"]
-    status: String,
-    #[sql_type = "Float8"]
-    task_percentage: f64,
+pub type DbPool = r2d2::Pool<DbConnectionManager>;
+
+static DB_POOL: OnceLock<DbPool> = OnceLock::new();
+
+/// r2d2 manager dispatching on the DATABASE_URL scheme, diesel's ConnectionManager would try every backend in turn
+#[derive(Debug)]
+pub struct DbConnectionManager {
+    database_url: String,
 }
 
-type DbPool = Arc<tokio::sync::Mutex<PgConnection>>;
+impl ManageConnection for DbConnectionManager {
+    type Connection = DbConnection;
+    type Error = r2d2::Error;
+
+    fn connect(&self) -> Result<DbConnection, r2d2::Error> {
+        establish_connection(&self.database_url)
+            .map_err(|err| r2d2::Error::ConnectionError(ConnectionError::BadConnection(err)))
+    }
+
+    fn is_valid(&self, conn: &mut DbConnection) -> Result<(), r2d2::Error> {
+        conn.ping().map_err(r2d2::Error::QueryError)
+    }
+
+    fn has_broken(&self, conn: &mut DbConnection) -> bool {
+        conn.is_broken()
+    }
+}
 
-lazy_static! {
-    pub static ref DB_POOL: DbPool = {
-        dotenv().ok();
-        let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-        let connection = PgConnection::establish(&database_url)
-            .expect("Error connecting to database");
-        Arc::new(tokio::sync::Mutex::new(connection))
+/// postgres://.. or postgresql://.. use Postgres, sqlite://<path>, sqlite:<path> or file:<path> use a local sqlite file
+pub fn establish_connection(database_url: &str) -> Result<DbConnection, String> {
+    if database_url.starts_with("postgres://") || database_url.starts_with("postgresql://") {
+        let conn = PgConnection::establish(database_url)
+            .map_err(|err| format!("Error connecting to postgres: {}", err))?;
+        return Ok(DbConnection::Postgresql(conn));
+    }
+
+    let path = if let Some(path) = database_url.strip_prefix("sqlite://") {
+        path
+    } else if let Some(path) = database_url.strip_prefix("sqlite:") {
+        path
+    } else if database_url.starts_with("file:") {
+        database_url
+    } else {
+        return Err(format!("Unsupported DATABASE_URL scheme: {}", database_url));
     };
+
+    let mut conn = SqliteConnection::establish(path)
+        .map_err(|err| format!("Error connecting to sqlite: {}", err))?;
+    // several pooled connections share the file, wait on locks instead of failing with SQLITE_BUSY
+    diesel::sql_query("PRAGMA foreign_keys = ON")
+        .execute(&mut conn)
+        .and_then(|_| diesel::sql_query("PRAGMA busy_timeout = 5000").execute(&mut conn))
+        .map_err(|err| format!("Error configuring sqlite connection: {}", err))?;
+    Ok(DbConnection::Sqlite(conn))
 }
 
-pub async fn add_big_proof(project_id: &str, task_id: &str) -> Result<(), String> {
-    let mut conn = DB_POOL.lock().await;
+/// apply the embedded migrations of the connection's backend, returns the versions applied
+pub fn run_migrations(conn: &mut DbConnection) -> Result<Vec<String>, String> {
+    let versions = match conn {
+        DbConnection::Postgresql(conn) => conn.run_pending_migrations(PG_MIGRATIONS),
+        DbConnection::Sqlite(conn) => conn.run_pending_migrations(SQLITE_MIGRATIONS),
+    }.map_err(|err| format!("Error running migrations: {}", err))?;
+    Ok(versions.iter().map(|v| v.to_string()).collect())
+}
 
-    let new_proof = NewBigProof {
-        project_id: project_id.to_owned(),
-        task_id: task_id.to_owned(),
-        status: "created".to_owned(),
+/// the `migrate` subcommand: bring the DATABASE_URL database up to date without starting the relayer
+pub fn migrate() -> Result<Vec<String>, String> {
+    dotenv().ok();
+    let database_url = env::var("DATABASE_URL").map_err(|_| "DATABASE_URL must be set".to_string())?;
+    let mut conn = establish_connection(&database_url)?;
+    run_migrations(&mut conn)
+}
+
+/// build the pool from DATABASE_URL (and optional DATABASE_POOL_SIZE / DATABASE_POOL_TIMEOUT), must be called once at startup.
+/// sqlite files are always migrated, postgres only with auto_migrate
+pub fn init_db_pool(auto_migrate: bool) -> Result<(), String> {
+    dotenv().ok();
+    let database_url = env::var("DATABASE_URL").map_err(|_| "DATABASE_URL must be set".to_string())?;
+    let pool_size = match env::var("DATABASE_POOL_SIZE") {
+        Ok(r) => r.parse::<u32>().map_err(|err| format!("Invalid DATABASE_POOL_SIZE: {}", err))?,
+        Err(_) => DEFAULT_POOL_SIZE,
     };
+    let pool_timeout = match env::var("DATABASE_POOL_TIMEOUT") {
+        Ok(r) => r.parse::<u64>().map_err(|err| format!("Invalid DATABASE_POOL_TIMEOUT: {}", err))?,
+        Err(_) => DEFAULT_POOL_TIMEOUT_SECS,
+    };
+
+    let manager = DbConnectionManager { database_url };
+    // fail fast here rather than on the first task if the database is unreachable
+    manager.connect().map_err(|err| format!("Error connecting to database: {}", err))?;
+
+    let pool = r2d2::Pool::builder()
+        .max_size(pool_size)
+        .connection_timeout(Duration::from_secs(pool_timeout))
+        .test_on_check_out(true)
+        .build(manager)
+        .map_err(|err| format!("Error building database pool: {}", err))?;
+
+    let mut conn = pool.get().map_err(|err| format!("Error getting database connection: {}", err))?;
+    if auto_migrate || matches!(*conn, DbConnection::Sqlite(_)) {
+        for version in run_migrations(&mut conn)? {
+            info!("applied database migration {}", version);
+        }
+    }
+    drop(conn);
+
+    info!("database pool ready, size:{}", pool_size);
+    DB_POOL.set(pool).map_err(|_| "database pool already initialized".to_string())
+}
+
+/// run blocking diesel calls on a pooled connection, off the async executor
+async fn run_db<F, T>(f: F) -> Result<T, String>
+where
+    F: FnOnce(&mut DbConnection) -> Result<T, String> + Send + 'static,
+    T: Send + 'static,
+{
+    let pool = DB_POOL.get().ok_or("database pool is not initialized")?.clone();
+    tokio::task::spawn_blocking(move || {
+        let mut conn = pool.get().map_err(|err| format!("Error getting database connection: {}", err))?;
+        f(&mut conn)
+    })
+    .await
+    .map_err(|err| format!("Database task failed: {}", err))?
+}
+
+/// parse a stored status and check it may move to next, failing the surrounding transaction otherwise
+fn check_transition(old_status: &str, next: TaskStatus) -> QueryResult<TaskStatus> {
+    let old = TaskStatus::from_str(old_status)
+        .ok_or_else(|| diesel::result::Error::QueryBuilderError(format!("unknown stored status {}", old_status).into()))?;
+    old.check_transition(next)
+        .map_err(|err| diesel::result::Error::QueryBuilderError(err.into()))?;
+    Ok(old)
+}
 
-    diesel::insert_into(big_proofs::dsl::big_proofs)
-        .values(&new_proof)
-        .execute(&mut *conn)
-        .map_err(|err| format!("Error adding big proof: {}", err))?;
+fn insert_task_event(conn: &mut DbConnection, event: NewTaskEvent) -> QueryResult<usize> {
+    let query = diesel::insert_into(task_events::dsl::task_events).values(&event);
+    match conn {
+        DbConnection::Postgresql(conn) => query.execute(conn),
+        DbConnection::Sqlite(conn) => query.execute(conn),
+    }
+}
 
-    Ok(())
+fn big_proof_event(project_id: &str, task_id: &str, from_status: Option<TaskStatus>, to_status: TaskStatus, detail: Option<String>) -> NewTaskEvent {
+    NewTaskEvent {
+        project_id: project_id.to_owned(),
+        task_id: task_id.to_owned(),
+        task_split_id: None,
+        from_status: from_status.map(|status| status.as_str().to_owned()),
+        to_status: to_status.as_str().to_owned(),
+        detail,
+    }
+}
+
+fn small_proof_event(project_id: &str, task_id: &str, split_id: &str, from_status: Option<TaskStatus>, to_status: TaskStatus) -> NewTaskEvent {
+    NewTaskEvent {
+        project_id: project_id.to_owned(),
+        task_id: task_id.to_owned(),
+        task_split_id: Some(split_id.to_owned()),
+        from_status: from_status.map(|status| status.as_str().to_owned()),
+        to_status: to_status.as_str().to_owned(),
+        detail: None,
+    }
 }
 
-pub async fn set_big_proof_status(project_id: &str, task_id: &str, status: &str) -> Result<(), String> {
-    let mut conn = DB_POOL.lock().await;
+/// record a task picked up from TaskSubmitted, with its terms; the deadline counts the liability window from when the event was received.
+/// false when the task was stored already, which is left as it is
+pub async fn add_big_proof(project_id: &str, msg: &ProvenTaskMessage) -> Result<bool, String> {
+    let liability_window = msg.event.liability_window as i64;
+    let new_proof = NewBigProof {
+        project_id: project_id.to_owned(),
+        task_id: msg.task_id(),
+        status: TaskStatus::Created.as_str().to_owned(),
+        instance: hex::encode(&msg.event.instance.0),
+        requester: hex::encode(msg.event.requester.as_bytes()),
+        prover: hex::encode(msg.event.prover.as_bytes()),
+        reward_token: hex::encode(msg.event.reward_token.as_bytes()),
+        reward_amount: msg.event.reward_amount.to_string(),
+        liability_token: hex::encode(msg.event.liability_token.as_bytes()),
+        liability_amount: msg.event.liability_amount.to_string(),
+        liability_window,
+        deadline: Some(msg.received_at.naive_utc() + chrono::Duration::seconds(liability_window)),
+        submit_tx_hash: Some(hex::encode(msg.event.tx_hash.as_bytes())),
+    };
 
-    diesel::update(big_proofs::dsl::big_proofs.filter(big_proofs::project_id.eq(project_id).and(big_proofs::task_id.eq(task_id))))
-        .set(big_proofs::status.eq(status))
-        .execute(&mut *conn)
-        .map_err(|err| format!("Error setting big proof status: {}", err))?;
+    run_db(move |conn| {
+        conn.transaction(|conn| {
+            let query = diesel::insert_into(big_proofs::dsl::big_proofs).values(&new_proof).on_conflict_do_nothing();
+            let inserted = match conn {
+                DbConnection::Postgresql(conn) => query.execute(conn),
+                DbConnection::Sqlite(conn) => query.execute(conn),
+            }?;
+            if inserted == 0 {
+                return Ok(false);
+            }
+            insert_task_event(conn, big_proof_event(&new_proof.project_id, &new_proof.task_id, None, TaskStatus::Created, new_proof.submit_tx_hash.clone()))?;
+            Ok(true)
+        }).map_err(|err: diesel::result::Error| format!("Error adding big proof: {}", err))
+    }).await
+}
 
-    Ok(())
+pub async fn set_big_proof_status(project_id: &str, task_id: &str, status: TaskStatus) -> Result<(), String> {
+    let (project_id, task_id) = (project_id.to_owned(), task_id.to_owned());
+
+    run_db(move |conn| {
+        conn.transaction(|conn| {
+            let filter = big_proofs::project_id.eq(&project_id).and(big_proofs::task_id.eq(&task_id));
+            let old_status = big_proofs::dsl::big_proofs.filter(filter).select(big_proofs::status).first::<String>(conn)?;
+            let old_status = check_transition(&old_status, status)?;
+            if old_status == status {
+                return Ok(0);
+            }
+            diesel::update(big_proofs::dsl::big_proofs.filter(filter))
+                .set(big_proofs::status.eq(status.as_str()))
+                .execute(conn)?;
+            insert_task_event(conn, big_proof_event(&project_id, &task_id, Some(old_status), status, None))
+        }).map_err(|err| format!("Error setting big proof status: {}", err))?;
+        Ok(())
+    }).await
 }
 
-pub async fn add_small_proof(project_id: &str, task_id: &str, split_id: &str) -> Result<(), String> {
-    let mut conn = DB_POOL.lock().await;
+/// count a proveTask attempt, a successful one moves the task to Submitted with its tx hash
+pub async fn record_proof_submission(project_id: &str, task_id: &str, result: &Result<String, String>) -> Result<(), String> {
+    let (project_id, task_id, result) = (project_id.to_owned(), task_id.to_owned(), result.clone());
+
+    run_db(move |conn| {
+        conn.transaction(|conn| {
+            let filter = big_proofs::project_id.eq(&project_id).and(big_proofs::task_id.eq(&task_id));
+            let old_status = big_proofs::dsl::big_proofs.filter(filter).select(big_proofs::status).first::<String>(conn)?;
+            let next_status = if result.is_ok() { TaskStatus::Submitted } else { TaskStatus::from_str(&old_status).unwrap_or(TaskStatus::Failed) };
+            let old_status = check_transition(&old_status, next_status)?;
+            diesel::update(big_proofs::dsl::big_proofs.filter(filter))
+                .set(big_proofs::attempts.eq(big_proofs::attempts + 1))
+                .execute(conn)?;
+            match &result {
+                Ok(tx_hash) => {
+                    diesel::update(big_proofs::dsl::big_proofs.filter(filter))
+                        .set((big_proofs::status.eq(next_status.as_str()), big_proofs::prove_tx_hash.eq(tx_hash)))
+                        .execute(conn)?;
+                    insert_task_event(conn, big_proof_event(&project_id, &task_id, Some(old_status), next_status, Some(tx_hash.clone())))
+                },
+                Err(e) => insert_task_event(conn, big_proof_event(&project_id, &task_id, Some(old_status), old_status, Some(format!("proveTask failed: {}", e)))),
+            }
+        }).map_err(|err| format!("Error recording proof submission: {}", err))?;
+        Ok(())
+    }).await
+}
+
+/// false when the segment was stored already, which is left as it is
+pub async fn add_small_proof(project_id: &str, task_id: &str, split_id: &str) -> Result<bool, String> {
     let new_proof = NewSmallProof {
         project_id: project_id.to_owned(),
         task_id: task_id.to_owned(),
         task_split_id: split_id.to_owned(),
         task_percentage: 0.0,
-        status: "created".to_owned(),
+        status: TaskStatus::Created.as_str().to_owned(),
     };
 
-    diesel::insert_into(small_proofs::dsl::small_proofs)
-        .values(&new_proof)
-        .execute(&mut *conn)
-        .map_err(|err| format!("Error adding small proof: {}", err))?;
+    run_db(move |conn| {
+        conn.transaction(|conn| {
+            let query = diesel::insert_into(small_proofs::dsl::small_proofs).values(&new_proof).on_conflict_do_nothing();
+            let inserted = match conn {
+                DbConnection::Postgresql(conn) => query.execute(conn),
+                DbConnection::Sqlite(conn) => query.execute(conn),
+            }?;
+            if inserted == 0 {
+                return Ok(false);
+            }
+            insert_task_event(conn, small_proof_event(&new_proof.project_id, &new_proof.task_id, &new_proof.task_split_id, None, TaskStatus::Created))?;
+            Ok(true)
+        }).map_err(|err: diesel::result::Error| format!("Error adding small proof: {}", err))
+    }).await
+}
+
+pub async fn set_small_proof_status_and_percentage(project_id: &str, task_id: &str, split_id: &str, status: TaskStatus, percentage: f64) -> Result<(), String> {
+    let (project_id, task_id, split_id) = (project_id.to_owned(), task_id.to_owned(), split_id.to_owned());
+
+    run_db(move |conn| {
+        conn.transaction(|conn| {
+            let filter = small_proofs::project_id.eq(&project_id).and(small_proofs::task_id.eq(&task_id)).and(small_proofs::task_split_id.eq(&split_id));
+            let old_status = small_proofs::dsl::small_proofs.filter(filter).select(small_proofs::status).first::<String>(conn)?;
+            let old_status = check_transition(&old_status, status)?;
+            if old_status == status {
+                return Ok(0);
+            }
+            diesel::update(small_proofs::dsl::small_proofs.filter(filter))
+                .set((small_proofs::status.eq(status.as_str()), small_proofs::task_percentage.eq(percentage)))
+                .execute(conn)?;
+            insert_task_event(conn, small_proof_event(&project_id, &task_id, &split_id, Some(old_status), status))
+        }).map_err(|err| format!("Error setting small proof status and percentage: {}", err))?;
+        Ok(())
+    }).await
+}
+
+/// a segment is sent to the scheduler: Dispatched, one more attempt, dispatch time now
+pub async fn set_small_proof_dispatched(project_id: &str, task_id: &str, split_id: &str, percentage: f64) -> Result<(), String> {
+    let (project_id, task_id, split_id) = (project_id.to_owned(), task_id.to_owned(), split_id.to_owned());
+
+    run_db(move |conn| {
+        conn.transaction(|conn| {
+            let filter = small_proofs::project_id.eq(&project_id).and(small_proofs::task_id.eq(&task_id)).and(small_proofs::task_split_id.eq(&split_id));
+            let old_status = small_proofs::dsl::small_proofs.filter(filter).select(small_proofs::status).first::<String>(conn)?;
+            let old_status = check_transition(&old_status, TaskStatus::Dispatched)?;
+            diesel::update(small_proofs::dsl::small_proofs.filter(filter))
+                .set((
+                    small_proofs::status.eq(TaskStatus::Dispatched.as_str()),
+                    small_proofs::task_percentage.eq(percentage),
+                    small_proofs::attempts.eq(small_proofs::attempts + 1),
+                    small_proofs::dispatch_time.eq(Utc::now().naive_utc()),
+                ))
+                .execute(conn)?;
+            insert_task_event(conn, small_proof_event(&project_id, &task_id, &split_id, Some(old_status), TaskStatus::Dispatched))
+        }).map_err(|err| format!("Error setting small proof dispatched: {}", err))?;
+        Ok(())
+    }).await
+}
+
+pub async fn add_quote(quote: NewQuote) -> Result<(), String> {
+    run_db(move |conn| {
+        let query = diesel::insert_into(quotes::dsl::quotes).values(&quote);
+        match conn {
+            DbConnection::Postgresql(conn) => query.execute(conn),
+            DbConnection::Sqlite(conn) => query.execute(conn),
+        }.map_err(|err| format!("Error adding quote: {}", err))?;
+        Ok(())
+    }).await
+}
+
+/// mark the outstanding quote with the same terms, unexpired at block_num, as matched by task_id;
+/// among identical quotes the one expiring first is used
+pub async fn match_quote(terms: NewQuote, block_num: i64, task_id: &str) -> Result<Option<String>, String> {
+    let task_id = task_id.to_owned();
+
+    run_db(move |conn| {
+        conn.transaction(|conn| {
+            let quote_hash = quotes::dsl::quotes
+                .filter(quotes::status.eq(QuoteStatus::Outstanding.as_str()))
+                .filter(quotes::expiry.ge(block_num))
+                .filter(quotes::prover.eq(&terms.prover))
+                .filter(quotes::instance.eq(&terms.instance))
+                .filter(quotes::reward_token.eq(&terms.reward_token))
+                .filter(quotes::reward_amount.eq(&terms.reward_amount))
+                .filter(quotes::liability_token.eq(&terms.liability_token))
+                .filter(quotes::liability_amount.eq(&terms.liability_amount))
+                .filter(quotes::liability_window.eq(terms.liability_window))
+                .order(quotes::expiry.asc())
+                .select(quotes::quote_hash)
+                .first::<String>(conn)
+                .optional()?;
+            if let Some(quote_hash) = &quote_hash {
+                diesel::update(quotes::dsl::quotes.filter(quotes::quote_hash.eq(quote_hash)))
+                    .set((quotes::status.eq(QuoteStatus::Matched.as_str()), quotes::task_id.eq(&task_id)))
+                    .execute(conn)?;
+            }
+            Ok(quote_hash)
+        }).map_err(|err: diesel::result::Error| format!("Error matching quote: {}", err))
+    }).await
+}
+
+/// outstanding quotes whose expiry block is before block_num become expired, returned for reporting
+pub async fn expire_quotes(block_num: i64) -> Result<Vec<Quote>, String> {
+    run_db(move |conn| {
+        conn.transaction(|conn| {
+            let filter = quotes::status.eq(QuoteStatus::Outstanding.as_str()).and(quotes::expiry.lt(block_num));
+            let expired = quotes::dsl::quotes.filter(filter).load::<Quote>(conn)?;
+            diesel::update(quotes::dsl::quotes.filter(filter))
+                .set(quotes::status.eq(QuoteStatus::Expired.as_str()))
+                .execute(conn)?;
+            Ok(expired)
+        }).map_err(|err: diesel::result::Error| format!("Error expiring quotes: {}", err))
+    }).await
+}
 
-    Ok(())
+pub async fn get_quotes(status: QuoteStatus) -> Result<Vec<Quote>, String> {
+    run_db(move |conn| {
+        quotes::dsl::quotes
+            .filter(quotes::status.eq(status.as_str()))
+            .order(quotes::expiry.asc())
+            .load::<Quote>(conn)
+            .map_err(|err| format!("Error getting quotes: {}", err))
+    }).await
 }
 
-pub async fn set_small_proof_status_and_percentage(project_id: &str, task_id: &str, split_id: &str, status: &str, percentage: f64) -> Result<(), String> {
-    let mut conn = DB_POOL.lock().await;
-    diesel::update(small_proofs::dsl::small_proofs.filter(small_proofs::project_id.eq(project_id).and(small_proofs::task_id.eq(task_id)).and(small_proofs::task_split_id.eq(split_id))))
-        .set((small_proofs::status.eq(status), small_proofs::task_percentage.eq(percentage)))
-        .execute(&mut *conn)
-        .map_err(|err| format!("Error setting small proof status and percentage: {}", err))?;
+/// the audit trail of a task and its segments, oldest first
+pub async fn get_task_events(project_id: &str, task_id: &str) -> Result<Vec<TaskEvent>, String> {
+    let (project_id, task_id) = (project_id.to_owned(), task_id.to_owned());
+
+    run_db(move |conn| {
+        task_events::dsl::task_events
+            .filter(task_events::project_id.eq(project_id).and(task_events::task_id.eq(task_id)))
+            .order(task_events::id.asc())
+            .load::<TaskEvent>(conn)
+            .map_err(|err| format!("Error getting task events: {}", err))
+    }).await
+}
 
-    Ok(())
+/// the tasks not yet confirmed, failed or expired and the segments the schedulers were proving, to restore the local maps at startup
+pub async fn load_open_proofs() -> Result<(Vec<BigProof>, Vec<SmallProof>), String> {
+    run_db(move |conn| {
+        let tasks = big_proofs::dsl::big_proofs
+            .filter(big_proofs::status.ne(TaskStatus::Confirmed.as_str())
+                .and(big_proofs::status.ne(TaskStatus::Failed.as_str()))
+                .and(big_proofs::status.ne(TaskStatus::Expired.as_str())))
+            .load::<BigProof>(conn)
+            .map_err(|err| format!("Error loading open big proofs: {}", err))?;
+        let segments = small_proofs::dsl::small_proofs
+            .filter(small_proofs::status.eq(TaskStatus::Dispatched.as_str())
+                .or(small_proofs::status.eq(TaskStatus::Proving.as_str())))
+            .load::<SmallProof>(conn)
+            .map_err(|err| format!("Error loading open small proofs: {}", err))?;
+        Ok((tasks, segments))
+    }).await
 }
 
 pub async fn get_big_proof_status(project_id: &str, task_id: &str) -> Result<String, String> {
-    let mut conn = DB_POOL.lock().await;
+    let (project_id, task_id) = (project_id.to_owned(), task_id.to_owned());
 
-    let query = "SELECT status FROM big_proofs WHERE project_id = $1 AND task_id = $2";
-    let result = diesel::sql_query(query)
-        .bind::<Varchar, _>(project_id)
-        .bind::<Varchar, _>(task_id)
-        .get_result::<BigProofStatus>(&mut *conn)
-        .map_err(|err| format!("Error getting big proof status: {}", err))?;
+    let result = run_db(move |conn| {
+        big_proofs::dsl::big_proofs
+            .filter(big_proofs::project_id.eq(project_id).and(big_proofs::task_id.eq(task_id)))
+            .first::<BigProof>(conn)
+            .map_err(|err| format!("Error getting big proof status: {}", err))
+    }).await?;
 
     Ok(result.status)
 }
 
+pub async fn big_proof_exists(project_id: &str, task_id: &str) -> Result<bool, String> {
+    let (project_id, task_id) = (project_id.to_owned(), task_id.to_owned());
+
+    let result = run_db(move |conn| {
+        big_proofs::dsl::big_proofs
+            .filter(big_proofs::project_id.eq(project_id).and(big_proofs::task_id.eq(task_id)))
+            .first::<BigProof>(conn)
+            .optional()
+            .map_err(|err| format!("Error getting big proof: {}", err))
+    }).await?;
+
+    Ok(result.is_some())
+}
+
 pub async fn get_small_proof_status_and_percentage(project_id: &str, task_id: &str, split_id: &str) -> Result<(String, f64), String> {
-    let mut conn = DB_POOL.lock().await;
+    let (project_id, task_id, split_id) = (project_id.to_owned(), task_id.to_owned(), split_id.to_owned());
 
-    let query = "SELECT status, task_percentage FROM small_proofs WHERE project_id = $1 AND task_id = $2 AND task_split_id = $3";
-    let result = diesel::sql_query(query)
-        .bind::<Varchar, _>(project_id)
-        .bind::<Varchar, _>(task_id)
-        .bind::<Varchar, _>(split_id)
-        .get_result::<SmallProofStatusAndPercentage>(&mut *conn)
-        .map_err(|err| format!("Error getting small proof status and percentage: {}", err))?;
+    let result = run_db(move |conn| {
+        small_proofs::dsl::small_proofs
+            .filter(small_proofs::project_id.eq(project_id).and(small_proofs::task_id.eq(task_id)).and(small_proofs::task_split_id.eq(split_id)))
+            .first::<SmallProof>(conn)
+            .map_err(|err| format!("Error getting small proof status and percentage: {}", err))
+    }).await?;
 
     Ok((result.status, result.task_percentage))
 }
 
+/// the DB mode counterpart of chain::update_proof_response
+pub async fn get_proof_response(project_id: &str, task_id: &str) -> Result<ProofResponse, String> {
+    let status = get_big_proof_status(project_id, task_id).await?;
+
+    let mut small_proofs = Vec::new();
+    for split_id in 0..SEG_NUM {
+        let (status, task_percentage) = get_small_proof_status_and_percentage(project_id, task_id, &split_id.to_string()).await?;
+        small_proofs.push(NewSmallProof {
+            project_id: project_id.to_string(),
+            task_id: task_id.to_string(),
+            task_split_id: split_id.to_string(),
+            task_percentage,
+            status,
+        });
+    }
+
+    Ok(ProofResponse {
+        task_id: task_id.to_string(),
+        project_id: project_id.to_string(),
+        status,
+        small_proofs,
+        on_chain: None,
+    })
+}
+
+/// one sqlite file per test run behind the pool, for the tests of every module
+#[cfg(test)]
+pub fn init_test_pool() {
+    static INIT: std::sync::Once = std::sync::Once::new();
+    INIT.call_once(|| {
+        let path = env::temp_dir().join(format!("relayer-test-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        env::set_var("DATABASE_URL", format!("sqlite://{}", path.display()));
+        init_db_pool(false).unwrap();
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connection_by_scheme() {
+        assert!(matches!(establish_connection("sqlite::memory:"), Ok(DbConnection::Sqlite(_))));
+        assert!(matches!(establish_connection("file::memory:"), Ok(DbConnection::Sqlite(_))));
+        assert!(establish_connection("mysql://localhost/zkpool").err().unwrap().starts_with("Unsupported DATABASE_URL scheme"));
+        let mut conn = establish_connection("sqlite::memory:").unwrap();
+        assert!(!run_migrations(&mut conn).unwrap().is_empty());
+        assert!(run_migrations(&mut conn).unwrap().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn pooled_queries_run_concurrently() {
+        init_test_pool();
+        assert_eq!(init_db_pool(false).unwrap_err(), "database pool already initialized");
+        let added = futures::future::join_all((0..DEFAULT_POOL_SIZE * 2).map(|i| async move {
+            let mut msg = ProvenTaskMessage { received_at: Utc::now(), ..Default::default() };
+            msg.event.task_key = web3::types::H256::from_low_u64_be((i % DEFAULT_POOL_SIZE) as u64);
+            add_big_proof("pool", &msg).await
+        })).await;
+        //every task inserted once, the second insert of each left alone
+        assert!(added.iter().all(|r| r.is_ok()), "{:?}", added);
+        assert_eq!(added.iter().filter(|r| matches!(r, Ok(true))).count(), DEFAULT_POOL_SIZE as usize);
+        assert!(big_proof_exists("pool", &format!("{:064x}", DEFAULT_POOL_SIZE - 1)).await.unwrap());
+        assert!(!big_proof_exists("pool", &format!("{:064x}", DEFAULT_POOL_SIZE)).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn task_history_and_submission_attempts() {
+        init_test_pool();
+        let mut msg = ProvenTaskMessage { received_at: Utc::now(), ..Default::default() };
+        msg.event.task_key = web3::types::H256::repeat_byte(0x29);
+        msg.event.reward_amount = 1000.into();
+        msg.event.liability_window = 600;
+        let task_id = msg.task_id();
+        assert!(add_big_proof("history", &msg).await.unwrap());
+        assert!(add_small_proof("history", &task_id, "0").await.unwrap());
+        set_small_proof_dispatched("history", &task_id, "0", 0.25).await.unwrap();
+        set_small_proof_dispatched("history", &task_id, "0", 0.25).await.unwrap();
+        for status in [TaskStatus::Dispatched, TaskStatus::Proving, TaskStatus::Proven] {
+            set_big_proof_status("history", &task_id, status).await.unwrap();
+        }
+        //setting the same status again is not an event, a transition back is refused
+        set_big_proof_status("history", &task_id, TaskStatus::Proven).await.unwrap();
+        assert!(set_big_proof_status("history", &task_id, TaskStatus::Created).await.is_err());
+        record_proof_submission("history", &task_id, &Err("nonce too low".to_string())).await.unwrap();
+        record_proof_submission("history", &task_id, &Ok("ab".repeat(32))).await.unwrap();
+
+        let events: Vec<(Option<String>, Option<String>, String)> = get_task_events("history", &task_id).await.unwrap().into_iter()
+            .map(|e| (e.task_split_id, e.from_status, e.to_status))
+            .collect();
+        let step = |split: Option<&str>, from: Option<&str>, to: &str| (split.map(String::from), from.map(String::from), to.to_string());
+        assert_eq!(events, vec![
+            step(None, None, "created"),
+            step(Some("0"), None, "created"),
+            step(Some("0"), Some("created"), "dispatched"),
+            step(Some("0"), Some("dispatched"), "dispatched"),
+            step(None, Some("created"), "dispatched"),
+            step(None, Some("dispatched"), "proving"),
+            step(None, Some("proving"), "proven"),
+            step(None, Some("proven"), "proven"),
+            step(None, Some("proven"), "submitted"),
+        ]);
+
+        let (task_id_filter, project) = (task_id.clone(), "history");
+        let stored = run_db(move |conn| {
+            big_proofs::dsl::big_proofs
+                .filter(big_proofs::project_id.eq(project).and(big_proofs::task_id.eq(task_id_filter)))
+                .first::<BigProof>(conn)
+                .map_err(|err| err.to_string())
+        }).await.unwrap();
+        assert_eq!((stored.status.as_str(), stored.attempts, stored.liability_window), ("submitted", 2, 600));
+        assert_eq!(stored.reward_amount, "1000");
+        assert_eq!(stored.prove_tx_hash, Some("ab".repeat(32)));
+        let segment = run_db(move |conn| {
+            small_proofs::dsl::small_proofs
+                .filter(small_proofs::project_id.eq(project))
+                .first::<SmallProof>(conn)
+                .map_err(|err| err.to_string())
+        }).await.unwrap();
+        assert_eq!(segment.attempts, 2);
+        assert!(segment.dispatch_time.is_some());
+    }
+}
+
 // #[cfg(test)]
 // mod tests {
 //     use super::*;
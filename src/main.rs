@@ -13,21 +13,29 @@
 // You should have received a copy of the GNU General Public License
 // along with the aoraki-labs library. If not, see <https://www.gnu.org/licenses/>.
 
-use std::{thread};
 use tokio::time::{self, Duration};
 
 use chain::{monitor_event, loop_task_data};
 use log::*;
 use clap::{load_yaml, App};
 use server::loop_proof_data;
-use web3::ethabi::Bytes;
 
 mod server;
 mod chain;
+#[cfg(feature = "DB")]
 mod db;
 mod models;
 mod schema;
-use crate::{server::start_rpc_server, chain::{PRIV_KEY, SCHEDULER_URL, CONTRACT, get_current_block_num}};
+mod quote;
+mod provider;
+mod bindings;
+mod scheduler;
+mod work;
+mod quote_registry;
+mod pricing;
+mod admission;
+mod signer;
+use crate::{server::start_rpc_server, chain::{CONTRACT, get_current_block_num}};
 
 #[macro_use]
 mod app_marco;
@@ -66,23 +74,188 @@ async fn main() {
     let cli_param_yml = load_yaml!("app.yml");
     let cli_param = App::from_yaml(cli_param_yml).get_matches();
 
-    let key: String = cli_param.value_of("key").unwrap_or("").into();
     let api: String = cli_param.value_of("api").unwrap_or("").into();
-    let scheduler: String = cli_param.value_of("scheduler").unwrap_or("").into();
     let blk_num: String = cli_param.value_of("start_num").unwrap_or("").into();
     let contract_addr: String = cli_param.value_of("contract").unwrap_or("").into();
 
-    {
-        let mut priv_key = PRIV_KEY.lock().await;
-        *priv_key=key.clone();
-    
-        let mut scheduler_url = SCHEDULER_URL.lock().await;
-        *scheduler_url=scheduler.clone();
+    if cli_param.subcommand_matches("migrate").is_some() {
+        #[cfg(feature = "DB")]
+        match db::migrate() {
+            Ok(versions) => {
+                info!("database migrated, {} migrations applied: {:?}", versions.len(), versions);
+                return;
+            },
+            Err(e) => {
+                error!("database migrate failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        #[cfg(not(feature = "DB"))]
+        {
+            error!("migrate needs the relayer built with the DB feature");
+            std::process::exit(1);
+        }
+    }
+
+    #[cfg(feature = "DB")]
+    if let Err(e) = db::init_db_pool(cli_param.is_present("auto_migrate")) {
+        error!("database init failed: {}", e);
+        std::process::exit(1);
+    }
+    #[cfg(feature = "DB")]
+    match chain::restore_task_state().await {
+        Ok((tasks, segments)) => info!("restored {} open tasks and {} proving segments from the database", tasks, segments),
+        Err(e) => {
+            error!("restore task state: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(urls) = cli_param.values_of("rpc") {
+        if let Err(e) = provider::set_providers(urls.map(|url| url.to_string()).collect()).await {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    if let Some(urls) = cli_param.values_of("ws_rpc") {
+        if let Err(e) = provider::set_ws_providers(urls.map(|url| url.to_string()).collect()).await {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    if let Some(path) = cli_param.value_of("abi") {
+        if let Err(e) = bindings::load_abi(path).and_then(|contract| bindings::check_abi(&contract)) {
+            error!("contract abi {} does not match the relayer: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+    let log_range = match (cli_param.value_of("log_range_min").unwrap_or_default().parse::<u64>(),
+        cli_param.value_of("log_range_max").unwrap_or_default().parse::<u64>()) {
+        (Ok(min), Ok(max)) => chain::LogRange::new(min, max),
+        (Err(e), _) | (_, Err(e)) => Err(format!("invalid log range: {}", e)),
+    };
+    match log_range {
+        Ok(r) => chain::set_log_range(r).await,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        },
+    }
+    if let Some(quorum) = cli_param.value_of("head_quorum") {
+        let result = match quorum.parse::<usize>() {
+            Ok(r) => provider::set_head_quorum(r).await,
+            Err(e) => Err(format!("invalid head_quorum {}: {}", quorum, e)),
+        };
+        if let Err(e) = result {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
 
+    let schedulers = cli_param.values_of("scheduler").map(|specs| specs.map(|spec| spec.to_string()).collect()).unwrap_or_default();
+    let placement = cli_param.value_of("scheduler_placement").unwrap_or_default();
+    let result = match scheduler::Placement::from_str(placement) {
+        Some(placement) => scheduler::set_schedulers(schedulers, placement).await,
+        None => Err(format!("invalid scheduler_placement {}", placement)),
+    };
+    if let Err(e) = result {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+    if let Some(attempts) = cli_param.value_of("segment_attempts") {
+        let result = match attempts.parse::<u32>() {
+            Ok(r) => scheduler::set_segment_attempts(r).await,
+            Err(e) => Err(format!("invalid segment_attempts {}: {}", attempts, e)),
+        };
+        if let Err(e) = result {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    if let Some(lease) = cli_param.value_of("work_lease") {
+        let result = match lease.parse::<u64>() {
+            Ok(r) => work::set_work_lease(r).await,
+            Err(e) => Err(format!("invalid work_lease {}: {}", lease, e)),
+        };
+        if let Err(e) = result {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    {
         let mut contract = CONTRACT.lock().await;
         *contract=contract_addr.clone();
 
     }
+
+    let prover_spec = match (cli_param.value_of("prover_signer"), cli_param.value_of("key")) {
+        (Some(spec), _) => spec.to_string(),
+        (None, Some(key)) => key.to_string(),
+        (None, None) => {
+            error!("no prover key, pass --prover_signer keystore:<file>,<password file>, env:<VAR> or remote:<url>");
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = signer::Signer::from_spec(&prover_spec).await.and_then(signer::set_prover_signer) {
+        error!("prover signer init failed: {}", e);
+        std::process::exit(1);
+    }
+    let mut gas_signers = Vec::new();
+    for spec in cli_param.values_of("gas_signer").into_iter().flatten() {
+        match signer::Signer::from_spec(spec).await {
+            Ok(s) => gas_signers.push(s),
+            Err(e) => {
+                error!("gas signer init failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    signer::set_gas_signers(gas_signers).await;
+
+    if let Some(path) = cli_param.value_of("pricing_policy") {
+        match pricing::PricingPolicy::load(path) {
+            Ok(policy) => pricing::set_pricing_policy(policy).await,
+            Err(e) => {
+                error!("pricing policy init failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let mut admission_config = admission::AdmissionConfig {
+        capacity_method: cli_param.value_of("scheduler_capacity").map(|m| m.to_string()),
+        ..Default::default()
+    };
+    for (arg, limit) in [("max_tasks", &mut admission_config.max_tasks), ("max_segments", &mut admission_config.max_segments)] {
+        if let Some(value) = cli_param.value_of(arg) {
+            match value.parse::<usize>() {
+                Ok(r) => *limit = Some(r),
+                Err(e) => {
+                    error!("invalid {} {}: {}", arg, value, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+    admission::set_admission(admission_config).await;
+
+    if let Some(spec) = cli_param.value_of("quote_expiry") {
+        match quote::QuoteExpiry::from_spec(spec) {
+            Ok(expiry) => quote::set_quote_expiry(expiry).await,
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    for spec in cli_param.values_of("quote_signing").into_iter().flatten() {
+        if let Err(e) = quote::set_quote_signing(spec).await {
+            error!("quote signing init failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+
     let myserver = start_rpc_server(api);
 
     let srv_handle = tokio::spawn(async move {
@@ -91,9 +264,14 @@ async fn main() {
 
     let event_loop_handle = tokio::spawn(async move {
         if blk_num.parse::<u64>().unwrap()==0 {
-            let latest_blk = match get_current_block_num(){
-                Some(r) => r,
-                None => 0,
+            let latest_blk = loop {
+                match get_current_block_num().await {
+                    Ok(r) => break r,
+                    Err(e) => {
+                        error!("start block unavailable: {}", e);
+                        time::sleep(Duration::from_secs(5)).await;
+                    }
+                }
             };
             monitor_event(latest_blk).await
 
@@ -102,6 +280,14 @@ async fn main() {
         }
     });
 
+    let head_handle = tokio::spawn(async move {
+        provider::track_head().await
+    });
+
+    let scheduler_handle = tokio::spawn(async move {
+        scheduler::track_schedulers().await
+    });
+
     let process_proof_handle = tokio::spawn(async move {
         process_proof_data().await
     });
@@ -110,16 +296,22 @@ async fn main() {
     });
 
     tokio::select! {
-       _ = async { srv_handle.await } => {
+       _ = srv_handle => {
         info!("Server terminal")
         },
-      _ = async { process_proof_handle.await } => {
+      _ = process_proof_handle => {
         info!("process proof handle terminal")
        },
-       _ = async { process_task_handle.await } => {
+       _ = process_task_handle => {
         info!("process task handle terminal")
        },
-       _ = async { event_loop_handle.await } => {
+       _ = head_handle => {
+        info!("chain head tracker terminal")
+       },
+       _ = scheduler_handle => {
+        info!("scheduler health checker terminal")
+       },
+       _ = event_loop_handle => {
         info!("process event loop handle terminal")
        },
     }
@@ -1,11 +1,16 @@
 use diesel::prelude::*;
-use crate::schema::{big_proofs, small_proofs};
+use crate::schema::{quotes, small_proofs};
+#[cfg(feature = "DB")]
+use crate::schema::{big_proofs, task_events};
+#[cfg(feature = "DB")]
 use chrono::NaiveDateTime;
 use serde::{Serialize, Deserialize};
 
+#[cfg(feature = "DB")]
+#[allow(dead_code)]
 #[derive(Queryable)]
 #[diesel(table_name = big_proofs)]
-#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(check_for_backend(diesel::pg::Pg, diesel::sqlite::Sqlite))]
 pub struct BigProof {
     pub id: i64,
     pub project_id: String,
@@ -13,11 +18,25 @@ pub struct BigProof {
     pub status: String,
     pub create_time: NaiveDateTime,
     pub update_time: NaiveDateTime,
+    pub instance: String,
+    pub requester: String,
+    pub prover: String,
+    pub reward_token: String,
+    pub reward_amount: String,
+    pub liability_token: String,
+    pub liability_amount: String,
+    pub liability_window: i64,
+    pub deadline: Option<NaiveDateTime>,
+    pub submit_tx_hash: Option<String>,
+    pub prove_tx_hash: Option<String>,
+    pub attempts: i32,
 }
 
+#[cfg(feature = "DB")]
+#[allow(dead_code)]
 #[derive(Queryable)]
 #[diesel(table_name = small_proofs)]
-#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(check_for_backend(diesel::pg::Pg, diesel::sqlite::Sqlite))]
 pub struct SmallProof {
     pub id: i64,
     pub project_id: String,
@@ -27,25 +46,107 @@ pub struct SmallProof {
     pub status: String,
     pub create_time: NaiveDateTime,
     pub update_time: NaiveDateTime,
+    pub attempts: i32,
+    pub dispatch_time: Option<NaiveDateTime>,
 }
 
+/// one status transition of a task (task_split_id None) or of one of its segments
+#[cfg(feature = "DB")]
+#[derive(Queryable, Debug, Serialize)]
+#[diesel(table_name = task_events)]
+#[diesel(check_for_backend(diesel::pg::Pg, diesel::sqlite::Sqlite))]
+pub struct TaskEvent {
+    pub id: i64,
+    pub project_id: String,
+    pub task_id: String,
+    pub task_split_id: Option<String>,
+    pub from_status: Option<String>,
+    pub to_status: String,
+    pub detail: Option<String>,
+    pub create_time: NaiveDateTime,
+}
+
+#[cfg(feature = "DB")]
 #[derive(Insertable)]
 #[diesel(table_name = big_proofs)]
-#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(check_for_backend(diesel::pg::Pg, diesel::sqlite::Sqlite))]
 pub struct NewBigProof {
     pub project_id: String,
     pub task_id: String,
     pub status: String,
+    pub instance: String,
+    pub requester: String,
+    pub prover: String,
+    pub reward_token: String,
+    pub reward_amount: String,
+    pub liability_token: String,
+    pub liability_amount: String,
+    pub liability_window: i64,
+    pub deadline: Option<NaiveDateTime>,
+    pub submit_tx_hash: Option<String>,
+}
+
+#[cfg(feature = "DB")]
+#[derive(Insertable)]
+#[diesel(table_name = task_events)]
+#[diesel(check_for_backend(diesel::pg::Pg, diesel::sqlite::Sqlite))]
+pub struct NewTaskEvent {
+    pub project_id: String,
+    pub task_id: String,
+    pub task_split_id: Option<String>,
+    pub from_status: Option<String>,
+    pub to_status: String,
+    pub detail: Option<String>,
 }
 
 
 #[derive(Insertable, Debug, Serialize, Deserialize)]
 #[diesel(table_name = small_proofs)]
-#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(check_for_backend(diesel::pg::Pg, diesel::sqlite::Sqlite))]
 pub struct NewSmallProof {
     pub project_id: String,
     pub task_id: String,
     pub task_split_id: String,
     pub task_percentage: f64,
     pub status: String,
-}
\ No newline at end of file
+}
+/// a quote signed in ReceiveTask, addresses as 0x lowercase hex and the instance as hex of its bytes
+#[derive(Insertable, Debug, Clone, Serialize, Deserialize)]
+#[diesel(table_name = quotes)]
+#[diesel(check_for_backend(diesel::pg::Pg, diesel::sqlite::Sqlite))]
+pub struct NewQuote {
+    pub quote_hash: String,
+    pub prover: String,
+    pub instance: String,
+    pub reward_token: String,
+    pub reward_amount: String,
+    pub liability_token: String,
+    pub liability_amount: String,
+    pub liability_window: i64,
+    pub expiry: i64,
+    pub signature: String,
+    pub status: String,
+    pub task_id: Option<String>,
+}
+
+#[cfg(feature = "DB")]
+#[derive(Queryable, Debug, Serialize)]
+#[diesel(table_name = quotes)]
+#[diesel(check_for_backend(diesel::pg::Pg, diesel::sqlite::Sqlite))]
+pub struct Quote {
+    pub id: i64,
+    pub quote_hash: String,
+    pub prover: String,
+    pub instance: String,
+    pub reward_token: String,
+    pub reward_amount: String,
+    pub liability_token: String,
+    pub liability_amount: String,
+    pub liability_window: i64,
+    pub expiry: i64,
+    pub signature: String,
+    pub status: String,
+    pub task_id: Option<String>,
+    pub create_time: NaiveDateTime,
+    pub update_time: NaiveDateTime,
+}
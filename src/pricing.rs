@@ -0,0 +1,231 @@
+// This file is part of the aoraki-labs library.
+
+// The aoraki-labs library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// The aoraki-labs library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with the aoraki-labs library. If not, see <https://www.gnu.org/licenses/>.
+
+use std::fs;
+use lazy_static::lazy_static;
+use log::*;
+use serde_derive::{Deserialize,Serialize};
+
+use crate::chain::{SEG_NUM, in_flight_tasks};
+use crate::quote::{TaskResponse, parse_address};
+
+lazy_static! {
+    pub static ref PRICING_POLICY: tokio::sync::Mutex<Option<PricingPolicy>> = {   //None accepts any terms
+        tokio::sync::Mutex::new(None)
+    };
+}
+
+/// the terms the relayer is willing to sign, loaded from a JSON file
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct PricingPolicy {
+    /// reward tokens accepted, any token when empty
+    pub reward_tokens: Vec<String>,
+    /// liability tokens accepted, any token when empty
+    pub liability_tokens: Vec<String>,
+    /// minimum reward for each byte of the task instance, as the string it is signed as
+    pub min_reward_per_byte: u64,
+    /// minimum reward for each segment the task is split into
+    pub min_reward_per_segment: u64,
+    pub max_liability: Option<u64>,
+    pub max_liability_window: Option<u64>,
+    /// surcharges on the minimum reward once enough tasks are in flight, the highest reached applies
+    pub load_surcharges: Vec<LoadSurcharge>,
+    /// answer out of policy terms with adjusted terms instead of rejecting them
+    pub counter_quote: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LoadSurcharge {
+    pub in_flight: usize,
+    pub percent: u64,
+}
+
+/// what the policy makes of requested terms
+#[derive(Debug)]
+pub enum PricingDecision {
+    Accept,
+    /// the quote was adjusted to the policy, with the reasons
+    Counter(Vec<String>),
+    Reject(Vec<String>),
+}
+
+impl PricingPolicy {
+    pub fn load(path: &str) -> Result<PricingPolicy, String> {
+        let content = fs::read_to_string(path).map_err(|e| format!("read pricing policy {}: {}", path, e))?;
+        let policy: PricingPolicy = serde_json::from_str(&content)
+            .map_err(|e| format!("parse pricing policy {}: {}", path, e))?;
+        for token in policy.reward_tokens.iter().chain(policy.liability_tokens.iter()) {
+            parse_address(token)?;
+        }
+        Ok(policy)
+    }
+
+    fn surcharge_percent(&self, in_flight: usize) -> u64 {
+        self.load_surcharges.iter()
+            .filter(|surcharge| in_flight >= surcharge.in_flight)
+            .map(|surcharge| surcharge.percent)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// the lowest reward accepted for the instance at the current load. instance_len is the UTF-8 length of the
+    /// instance string as signed, so a hex instance counts two bytes per byte it encodes, plus a 0x prefix if any
+    pub fn min_reward(&self, instance_len: usize, in_flight: usize) -> u64 {
+        let base = std::cmp::max(
+            self.min_reward_per_byte.saturating_mul(instance_len as u64),
+            self.min_reward_per_segment.saturating_mul(SEG_NUM as u64),
+        );
+        base.saturating_mul(100 + self.surcharge_percent(in_flight)) / 100
+    }
+
+    /// check the quote terms, adjusting them in place when counter quoting
+    pub fn evaluate(&self, quote: &mut TaskResponse, in_flight: usize) -> PricingDecision {
+        let mut rejected = vec![];
+        if !accepts_token(&self.reward_tokens, &quote.reward_token) {
+            rejected.push(format!("reward token {} not accepted", quote.reward_token));
+        }
+        if !accepts_token(&self.liability_tokens, &quote.liability_token) {
+            rejected.push(format!("liability token {} not accepted", quote.liability_token));
+        }
+        if !rejected.is_empty() {
+            return PricingDecision::Reject(rejected);
+        }
+
+        let mut countered = vec![];
+        let min_reward = self.min_reward(quote.instance.len(), in_flight);
+        if quote.reward < min_reward {
+            countered.push(format!("reward {} below minimum {}", quote.reward, min_reward));
+            quote.reward = min_reward;
+        }
+        if let Some(max_liability) = self.max_liability {
+            if quote.liability > max_liability {
+                countered.push(format!("liability {} above maximum {}", quote.liability, max_liability));
+                quote.liability = max_liability;
+            }
+        }
+        if let Some(max_window) = self.max_liability_window {
+            if quote.liability_window > max_window {
+                countered.push(format!("liability window {} above maximum {}", quote.liability_window, max_window));
+                quote.liability_window = max_window;
+            }
+        }
+        if countered.is_empty() {
+            PricingDecision::Accept
+        } else if self.counter_quote {
+            PricingDecision::Counter(countered)
+        } else {
+            PricingDecision::Reject(countered)
+        }
+    }
+}
+
+fn accepts_token(accepted: &[String], token: &str) -> bool {
+    let token = match parse_address(token) {
+        Ok(r) => r,
+        Err(_) => return false,
+    };
+    accepted.is_empty() || accepted.iter().any(|t| parse_address(t).map(|t| t == token).unwrap_or(false))
+}
+
+pub async fn set_pricing_policy(policy: PricingPolicy) {
+    info!("pricing policy {:?}", policy);
+    let mut current = PRICING_POLICY.lock().await;
+    *current = Some(policy);
+}
+
+/// apply the configured policy to the quote, without a policy every quote is accepted
+pub async fn price_quote(quote: &mut TaskResponse) -> PricingDecision {
+    let policy = PRICING_POLICY.lock().await.clone();
+    match policy {
+        Some(policy) => policy.evaluate(quote, in_flight_tasks().await),
+        None => PricingDecision::Accept,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOKEN_A: &str = "0x00000000000000000000000000000000000000aa";
+    const TOKEN_B: &str = "0x00000000000000000000000000000000000000bb";
+
+    fn load_policy(name: &str, content: &str) -> Result<PricingPolicy, String> {
+        let path = std::env::temp_dir().join(format!("pricing-{}-{}.json", name, std::process::id()));
+        fs::write(&path, content).unwrap();
+        let policy = PricingPolicy::load(path.to_str().unwrap());
+        let _ = fs::remove_file(&path);
+        policy
+    }
+
+    fn quote(reward: u64, liability: u64) -> TaskResponse {
+        TaskResponse {
+            instance: "ab".repeat(100),
+            reward_token: TOKEN_A.to_string(),
+            liability_token: TOKEN_A.to_string(),
+            reward,
+            liability,
+            liability_window: 600,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn load_checks_tokens() {
+        let policy = load_policy("ok", &format!(r#"{{"reward_tokens": ["{}"], "min_reward_per_byte": 10}}"#, TOKEN_A)).unwrap();
+        assert_eq!(policy.reward_tokens, vec![TOKEN_A.to_string()]);
+        assert_eq!(policy.min_reward_per_byte, 10);
+        assert!(policy.liability_tokens.is_empty() && !policy.counter_quote);
+        assert!(load_policy("bad-token", r#"{"liability_tokens": ["0xnot"]}"#).is_err());
+        assert!(load_policy("bad-json", r#"{"min_reward_per_byte": -1}"#).is_err());
+        assert!(PricingPolicy::load("/nonexistent/pricing.json").is_err());
+    }
+
+    #[test]
+    fn min_reward_by_byte_segment_and_load() {
+        let policy = load_policy("min", r#"{"min_reward_per_byte": 10, "min_reward_per_segment": 1000,
+            "load_surcharges": [{"in_flight": 10, "percent": 20}, {"in_flight": 50, "percent": 100}]}"#).unwrap();
+        //4 segments of 1000 beat 200 bytes of 10
+        assert_eq!(policy.min_reward(200, 0), 4000);
+        assert_eq!(policy.min_reward(500, 0), 5000);
+        assert_eq!(policy.min_reward(500, 10), 6000);
+        assert_eq!(policy.min_reward(500, 60), 10000);
+    }
+
+    #[test]
+    fn accept_reject_and_counter() {
+        let content = format!(r#"{{"reward_tokens": ["{}"], "min_reward_per_byte": 10, "max_liability": 500,
+            "max_liability_window": 300}}"#, TOKEN_A);
+        let policy = load_policy("reject", &content).unwrap();
+        //the 200 character hex instance is 200 bytes as signed
+        assert!(matches!(policy.evaluate(&mut quote(2000, 500), 0), PricingDecision::Reject(_)));
+        let mut q = quote(2000, 500);
+        q.liability_window = 300;
+        assert!(matches!(policy.evaluate(&mut q, 0), PricingDecision::Accept));
+        q.reward_token = TOKEN_B.to_string();
+        assert!(matches!(policy.evaluate(&mut q, 0), PricingDecision::Reject(reasons) if reasons.len() == 1));
+
+        let policy = PricingPolicy { counter_quote: true, ..policy };
+        let mut q = quote(100, 900);
+        match policy.evaluate(&mut q, 0) {
+            PricingDecision::Counter(reasons) => assert_eq!(reasons.len(), 3),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!((q.reward, q.liability, q.liability_window), (2000, 500, 300));
+        //unaccepted tokens are never countered
+        q.liability_token = "0x".to_string();
+        assert!(matches!(policy.evaluate(&mut q, 0), PricingDecision::Reject(_)));
+    }
+}
@@ -0,0 +1,570 @@
+// This file is part of the aoraki-labs library.
+
+// The aoraki-labs library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// The aoraki-labs library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with the aoraki-labs library. If not, see <https://www.gnu.org/licenses/>.
+
+use std::future::Future;
+use lazy_static::lazy_static;
+use log::*;
+use reqwest::{Client, Url};
+use serde_derive::Serialize;
+use tokio::time::{self, Duration, Instant};
+use web3::{Web3, transports::Http, error::{Error, TransportError}, signing::keccak256, types::{Bytes, H256}};
+
+use crate::bindings::explain_revert;
+use crate::chain::SEPOLIA_CHAIN_URLS;
+
+pub const HEAD_POLL_SECS: u64 = 2;
+pub const HEAD_TIMEOUT_SECS: u64 = 5;
+pub const HEAD_MAX_AGE_SECS: u64 = 30;       //older cached heads are refreshed on read
+pub const STALE_BLOCKS: u64 = 5;             //behind the quorum head by more is stale
+pub const STALE_SECS: u64 = 60;              //head unchanged while the quorum head moved is stale
+pub const CALL_TIMEOUT_SECS: u64 = 30;
+pub const BREAKER_FAILURES: u32 = 3;         //consecutive failures that open the breaker
+pub const BREAKER_OPEN_SECS: u64 = 10;       //first open period, doubled on each failed trial
+pub const BREAKER_MAX_OPEN_SECS: u64 = 300;
+pub const RATE_LIMIT_SECS: u64 = 10;         //rest after an HTTP 429
+const HEALTH_WEIGHT: f64 = 0.2;              //weight of the newest answer in the health score
+const LATENCY_WEIGHT: f64 = 0.2;             //weight of the newest call in the latency average
+const SENT_ALREADY: [&str; 3] = ["already known", "known transaction", "nonce too low"];   //a node has the transaction
+
+lazy_static! {
+    pub static ref PROVIDERS: tokio::sync::Mutex<Vec<Provider>> = {
+        tokio::sync::Mutex::new(SEPOLIA_CHAIN_URLS.iter().map(|url| Provider::new(url).expect("built-in provider url")).collect())
+    };
+    pub static ref HEAD_QUORUM: tokio::sync::Mutex<usize> = {   //answers needed for a head
+        tokio::sync::Mutex::new(1)
+    };
+    pub static ref WS_PROVIDERS: tokio::sync::Mutex<Vec<String>> = {   //websocket endpoints for log subscriptions
+        tokio::sync::Mutex::new(vec![])
+    };
+    pub static ref HEAD: tokio::sync::Mutex<Option<(u64, Instant)>> = {   //quorum head and when it was taken
+        tokio::sync::Mutex::new(None)
+    };
+}
+
+/// an RPC endpoint, its client and what has been seen of it
+#[derive(Debug, Clone, Serialize)]
+pub struct Provider {
+    pub url: String,
+    #[serde(skip)]
+    pub web3: Web3<Http>,
+    /// moving average of answers, 1 when every call succeeds
+    pub health: f64,
+    /// moving average of successful call times
+    pub latency_ms: f64,
+    pub head: Option<u64>,
+    #[serde(skip)]
+    pub head_changed_at: Option<Instant>,
+    pub stale: bool,
+    /// consecutive failures, the breaker opens at BREAKER_FAILURES
+    pub failures: u32,
+    /// calls are not sent before this, after it one trial call decides
+    #[serde(skip)]
+    pub open_until: Option<Instant>,
+    /// the trial call of a half-open breaker is out until this
+    #[serde(skip)]
+    pub trial_until: Option<Instant>,
+    pub open_secs: u64,
+    pub rate_limited: u64,
+}
+
+/// how a call went, as far as the provider's health is concerned
+enum Outcome {
+    Ok(Duration),
+    /// the node answered with an error, e.g. a revert; another provider would say the same
+    Answered,
+    RateLimited,
+    Failed,
+}
+
+fn classify(e: &Error) -> Outcome {
+    match e {
+        Error::Transport(TransportError::Code(429)) => Outcome::RateLimited,
+        Error::Transport(_) | Error::Unreachable | Error::InvalidResponse(_) | Error::Io(_) => Outcome::Failed,
+        _ => Outcome::Answered,
+    }
+}
+
+impl Provider {
+    pub fn new(url: &str) -> Result<Provider, String> {
+        let client = Client::builder()
+            .user_agent(headers::HeaderValue::from_static("web3.rs"))
+            .timeout(Duration::from_secs(CALL_TIMEOUT_SECS))
+            .build()
+            .map_err(|e| format!("build client for {}: {}", url, e))?;
+        let parsed: Url = url.parse().map_err(|e| format!("invalid provider url {}: {}", url, e))?;
+        Ok(Provider {
+            url: url.to_string(),
+            web3: Web3::new(Http::with_client(client, parsed)),
+            health: 1.0,
+            latency_ms: 0.0,
+            head: None,
+            head_changed_at: None,
+            stale: false,
+            failures: 0,
+            open_until: None,
+            trial_until: None,
+            open_secs: BREAKER_OPEN_SECS,
+            rate_limited: 0,
+        })
+    }
+
+    /// closed, or open with the period over so a trial call may go
+    fn available(&self, now: Instant) -> bool {
+        self.open_until.map(|until| now >= until).unwrap_or(true)
+    }
+
+    /// take the right to call now: always when closed, and when half-open for the first caller only
+    fn claim(&mut self, now: Instant) -> bool {
+        if !self.available(now) {
+            return false;
+        }
+        if self.open_until.is_none() {
+            return true;
+        }
+        if self.trial_until.map(|until| now < until).unwrap_or(false) {
+            return false;
+        }
+        self.trial_until = Some(now + Duration::from_secs(CALL_TIMEOUT_SECS));
+        true
+    }
+
+    pub fn breaker(&self) -> &'static str {
+        match self.open_until {
+            None => "closed",
+            Some(until) if Instant::now() >= until => "half-open",
+            Some(_) => "open",
+        }
+    }
+
+    fn record(&mut self, outcome: &Outcome) {
+        self.trial_until = None;
+        let ok = !matches!(outcome, Outcome::Failed | Outcome::RateLimited);
+        self.health = self.health * (1.0 - HEALTH_WEIGHT) + if ok { HEALTH_WEIGHT } else { 0.0 };
+        match outcome {
+            Outcome::Ok(_) | Outcome::Answered => {
+                if let Outcome::Ok(latency) = outcome {
+                    let ms = latency.as_secs_f64() * 1000.0;
+                    self.latency_ms = if self.latency_ms == 0.0 { ms } else { self.latency_ms * (1.0 - LATENCY_WEIGHT) + ms * LATENCY_WEIGHT };
+                }
+                if self.open_until.is_some() {
+                    info!("provider {} recovered, breaker closed", self.url);
+                }
+                self.failures = 0;
+                self.open_until = None;
+                self.open_secs = BREAKER_OPEN_SECS;
+            },
+            Outcome::RateLimited => {
+                self.rate_limited += 1;
+                warn!("provider {} rate limited, resting {}s", self.url, RATE_LIMIT_SECS);
+                self.open_until = Some(Instant::now() + Duration::from_secs(RATE_LIMIT_SECS));
+            },
+            Outcome::Failed => {
+                self.failures += 1;
+                if self.failures >= BREAKER_FAILURES {
+                    warn!("provider {} failed {} times, breaker open for {}s", self.url, self.failures, self.open_secs);
+                    self.open_until = Some(Instant::now() + Duration::from_secs(self.open_secs));
+                    self.open_secs = (self.open_secs * 2).min(BREAKER_MAX_OPEN_SECS);
+                }
+            },
+        }
+    }
+}
+
+pub async fn set_providers(urls: Vec<String>) -> Result<(), String> {
+    info!("rpc providers {:?}", urls);
+    let providers = urls.iter().map(|url| Provider::new(url)).collect::<Result<Vec<_>, _>>()?;
+    *PROVIDERS.lock().await = providers;
+    Ok(())
+}
+
+pub async fn set_ws_providers(urls: Vec<String>) -> Result<(), String> {
+    for url in urls.iter() {
+        let parsed: Url = url.parse().map_err(|e| format!("invalid websocket url {}: {}", url, e))?;
+        if !matches!(parsed.scheme(), "ws" | "wss") {
+            return Err(format!("websocket url {} must be ws:// or wss://", url));
+        }
+    }
+    info!("websocket rpc providers {:?}", urls);
+    *WS_PROVIDERS.lock().await = urls;
+    Ok(())
+}
+
+pub async fn ws_provider_urls() -> Vec<String> {
+    WS_PROVIDERS.lock().await.clone()
+}
+
+pub async fn set_head_quorum(quorum: usize) -> Result<(), String> {
+    let providers = PROVIDERS.lock().await.len();
+    if quorum == 0 || quorum > providers {
+        return Err(format!("head quorum {} needs between 1 and {} providers", quorum, providers));
+    }
+    *HEAD_QUORUM.lock().await = quorum;
+    Ok(())
+}
+
+/// providers a call may go to: breaker closed or due a trial, fresh before stale,
+/// then healthiest and fastest first
+async fn candidates() -> Vec<Provider> {
+    let now = Instant::now();
+    let mut providers: Vec<Provider> = PROVIDERS.lock().await.iter()
+        .filter(|p| p.available(now))
+        .cloned()
+        .collect();
+    providers.sort_by(|a, b| a.stale.cmp(&b.stale)
+        .then(b.health.total_cmp(&a.health))
+        .then(a.latency_ms.total_cmp(&b.latency_ms)));
+    providers
+}
+
+async fn claim(url: &str) -> bool {
+    let now = Instant::now();
+    PROVIDERS.lock().await.iter_mut().find(|p| p.url == url).map(|p| p.claim(now)).unwrap_or(false)
+}
+
+async fn record(url: &str, outcome: &Outcome) {
+    if let Some(provider) = PROVIDERS.lock().await.iter_mut().find(|p| p.url == url) {
+        provider.record(outcome);
+    }
+}
+
+/// run a chain call on the best available provider, failing over to the next one on transport
+/// errors, timeouts and rate limits; an error answered by the node is returned as is
+pub async fn with_provider<T, F, Fut>(call: &str, f: F) -> Result<T, String>
+where
+    F: Fn(Web3<Http>) -> Fut,
+    Fut: Future<Output = web3::Result<T>>,
+{
+    let mut last_error = format!("{}: no rpc provider available", call);
+    for provider in candidates().await {
+        if !claim(&provider.url).await {
+            continue;
+        }
+        let started = Instant::now();
+        let result = time::timeout(Duration::from_secs(CALL_TIMEOUT_SECS), f(provider.web3.clone())).await;
+        let outcome = match &result {
+            Ok(Ok(_)) => Outcome::Ok(started.elapsed()),
+            Ok(Err(e)) => classify(e),
+            Err(_) => Outcome::Failed,
+        };
+        record(&provider.url, &outcome).await;
+        match result {
+            Ok(Ok(r)) => return Ok(r),
+            Ok(Err(e)) => {
+                last_error = format!("{} on {}: {}", call, provider.url, e);
+                if let Outcome::Answered = outcome {
+                    return Err(last_error);
+                }
+            },
+            Err(_) => last_error = format!("{} on {}: timeout", call, provider.url),
+        }
+        debug!("{}, failing over", last_error);
+    }
+    Err(last_error)
+}
+
+/// send a signed transaction, failing over on transport errors and rate limits only; after a
+/// timeout the node may have taken it, and a node that knows it or its nonce already counts as sent
+pub async fn send_raw_transaction(call: &str, raw: Bytes) -> Result<H256, String> {
+    let hash = H256::from(keccak256(&raw.0));
+    let mut last_error = format!("{}: no rpc provider available", call);
+    for provider in candidates().await {
+        if !claim(&provider.url).await {
+            continue;
+        }
+        let started = Instant::now();
+        let result = time::timeout(Duration::from_secs(CALL_TIMEOUT_SECS), provider.web3.eth().send_raw_transaction(raw.clone())).await;
+        let outcome = match &result {
+            Ok(Ok(_)) => Outcome::Ok(started.elapsed()),
+            Ok(Err(e)) => classify(e),
+            Err(_) => Outcome::Failed,
+        };
+        record(&provider.url, &outcome).await;
+        match result {
+            Ok(Ok(r)) => return Ok(r),
+            Ok(Err(e)) => {
+                last_error = format!("{} on {}: {}", call, provider.url, explain_revert(e));
+                let lowercase = last_error.to_lowercase();
+                if SENT_ALREADY.iter().any(|known| lowercase.contains(known)) {
+                    warn!("{}, taken as sent with hash {:?}", last_error, hash);
+                    return Ok(hash);
+                }
+                if let Outcome::Answered = outcome {
+                    return Err(last_error);
+                }
+            },
+            Err(_) => return Err(format!("{} on {}: timeout, transaction {:?} may still be mined", call, provider.url, hash)),
+        }
+        debug!("{}, failing over", last_error);
+    }
+    Err(last_error)
+}
+
+/// the client of the best available provider, for calls that need one but do not go over the wire
+pub async fn best_web3() -> Result<Web3<Http>, String> {
+    candidates().await.first().map(|p| p.web3.clone()).ok_or("no rpc provider available".to_string())
+}
+
+pub async fn provider_status() -> Vec<serde_json::Value> {
+    PROVIDERS.lock().await.iter().map(|p| {
+        let mut status = serde_json::to_value(p).unwrap_or_default();
+        status["breaker"] = p.breaker().into();
+        status
+    }).collect()
+}
+
+async fn query_head(provider: &Provider) -> Result<u64, String> {
+    if !claim(&provider.url).await {
+        return Err(format!("breaker {}", provider.breaker()));
+    }
+    let started = Instant::now();
+    let result = time::timeout(Duration::from_secs(HEAD_TIMEOUT_SECS), provider.web3.eth().block_number()).await;
+    let outcome = match &result {
+        Ok(Ok(_)) => Outcome::Ok(started.elapsed()),
+        Ok(Err(e)) => classify(e),
+        Err(_) => Outcome::Failed,
+    };
+    record(&provider.url, &outcome).await;
+    match result {
+        Ok(Ok(r)) => Ok(r.as_u64()),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err("timeout".to_string()),
+    }
+}
+
+/// lower median, so an even split never trusts the provider furthest ahead
+fn median(mut heads: Vec<u64>) -> Option<u64> {
+    heads.sort_unstable();
+    heads.get(heads.len().saturating_sub(1) / 2).copied()
+}
+
+/// ask every provider for its head, update staleness, and take the median of the fresh
+/// answers once the quorum is reached; the head never moves back
+pub async fn refresh_head() -> Result<u64, String> {
+    let snapshot: Vec<Provider> = PROVIDERS.lock().await.clone();
+    let answers = futures::future::join_all(snapshot.iter().map(query_head)).await;
+    let quorum = *HEAD_QUORUM.lock().await;
+    let now = Instant::now();
+
+    let mut providers = PROVIDERS.lock().await;
+    if providers.len() != answers.len() {
+        return Err("providers changed during the head refresh".to_string());
+    }
+    for (provider, answer) in providers.iter_mut().zip(answers.iter()) {
+        match answer {
+            Ok(head) => {
+                if provider.head != Some(*head) {
+                    provider.head_changed_at = Some(now);
+                }
+                provider.head = Some(*head);
+            },
+            Err(e) => error!("get block num from {}: {}", provider.url, e),
+        }
+    }
+
+    let answered: Vec<u64> = answers.iter().filter_map(|a| a.as_ref().ok().copied()).collect();
+    let first_pass = median(answered).ok_or("no provider answered the block number")?;
+    for (provider, answer) in providers.iter_mut().zip(answers.iter()) {
+        let behind = matches!(answer, Ok(head) if head + STALE_BLOCKS < first_pass);
+        let stuck = matches!(answer, Ok(head) if *head < first_pass)
+            && provider.head_changed_at.map(|at| now.duration_since(at).as_secs() > STALE_SECS).unwrap_or(false);
+        let stale = answer.is_err() || behind || stuck;
+        if stale && !provider.stale {
+            warn!("provider {} is stale at {:?}, quorum head {}", provider.url, provider.head, first_pass);
+        } else if !stale && provider.stale {
+            info!("provider {} caught up at {:?}", provider.url, provider.head);
+        }
+        provider.stale = stale;
+    }
+    let fresh: Vec<u64> = providers.iter().zip(answers.iter())
+        .filter(|(provider, _)| !provider.stale)
+        .filter_map(|(_, answer)| answer.as_ref().ok().copied())
+        .collect();
+    drop(providers);
+
+    if fresh.len() < quorum {
+        return Err(format!("{} fresh providers answered, quorum is {}", fresh.len(), quorum));
+    }
+    let head = median(fresh).ok_or("no fresh provider answered the block number")?;
+    let mut cached = HEAD.lock().await;
+    let head = cached.map(|(previous, _)| previous.max(head)).unwrap_or(head);
+    *cached = Some((head, now));
+    Ok(head)
+}
+
+/// the cached quorum head, refreshed here if the tracker has not updated it lately
+pub async fn current_head() -> Result<u64, String> {
+    if let Some((head, at)) = *HEAD.lock().await {
+        if at.elapsed() < Duration::from_secs(HEAD_MAX_AGE_SECS) {
+            return Ok(head);
+        }
+    }
+    refresh_head().await
+}
+
+/// keep the cached head current for every reader
+pub async fn track_head() {
+    loop {
+        if let Err(e) = refresh_head().await {
+            error!("refresh chain head: {}", e);
+        }
+        time::sleep(Duration::from_secs(HEAD_POLL_SECS)).await;
+    }
+}
+
+/// a local HTTP endpoint for the tests of every module: answer gets the request line and the JSON body,
+/// and gives the status and JSON to send back
+#[cfg(test)]
+pub async fn serve_http<F>(answer: F) -> String
+where
+    F: Fn(&str, &serde_json::Value) -> (u16, serde_json::Value) + Send + Sync + 'static,
+{
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let answer = std::sync::Arc::new(answer);
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let answer = answer.clone();
+            tokio::spawn(async move {
+                let mut request = vec![];
+                let mut buf = [0u8; 4096];
+                //one request per connection: the headers, then content-length bytes of body
+                let (head, body) = loop {
+                    let n = stream.read(&mut buf).await.unwrap_or(0);
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                        let length = head.lines()
+                            .filter_map(|line| line.split_once(':'))
+                            .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+                            .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+                            .unwrap_or(0);
+                        if body.len() >= length {
+                            break (head.to_string(), body.to_string());
+                        }
+                    }
+                    if n == 0 {
+                        return;
+                    }
+                };
+                let (status, reply) = answer(head.lines().next().unwrap_or_default(), &serde_json::from_str(&body).unwrap_or_default());
+                let reply = reply.to_string();
+                let response = format!("HTTP/1.1 {} X\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status, reply.len(), reply);
+                let _ = stream.write_all(response.as_bytes()).await;
+            });
+        }
+    });
+    url
+}
+
+/// a JSON-RPC endpoint on serve_http answering each method with result_of(method), an error object when None
+#[cfg(test)]
+pub async fn serve_rpc<F>(result_of: F) -> String
+where
+    F: Fn(&str) -> Option<serde_json::Value> + Send + Sync + 'static,
+{
+    serve_http(move |_, request| {
+        let reply = match result_of(request["method"].as_str().unwrap_or_default()) {
+            Some(result) => serde_json::json!({"jsonrpc": "2.0", "id": request["id"], "result": result}),
+            None => serde_json::json!({"jsonrpc": "2.0", "id": request["id"], "error": {"code": -32000, "message": "execution reverted"}}),
+        };
+        (200, reply)
+    }).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn half_open_lets_one_trial_through() {
+        let mut provider = Provider::new("http://127.0.0.1:8545").unwrap();
+        let now = Instant::now();
+        assert!(provider.claim(now) && provider.claim(now));
+        for _ in 0..BREAKER_FAILURES {
+            provider.record(&Outcome::Failed);
+        }
+        assert!(!provider.claim(Instant::now()));
+        let due = Instant::now() + Duration::from_secs(BREAKER_OPEN_SECS);
+        assert!(provider.claim(due));
+        assert!(!provider.claim(due));
+        provider.record(&Outcome::Failed);
+        assert!(!provider.claim(due));
+        provider.record(&Outcome::Ok(Duration::from_millis(10)));
+        assert!(provider.claim(due) && provider.claim(due));
+    }
+
+    #[test]
+    fn lower_median() {
+        assert_eq!(median(vec![]), None);
+        assert_eq!(median(vec![7]), Some(7));
+        assert_eq!(median(vec![9, 7]), Some(7));
+        assert_eq!(median(vec![10, 3, 7]), Some(7));
+        assert_eq!(median(vec![10, 3, 7, 100]), Some(7));
+    }
+
+    /// the providers, the head and the quorum are process wide, one test drives them all
+    #[tokio::test]
+    async fn failover_quorum_and_stale_providers() {
+        let refused = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let head = |head: u64| {
+            let refused = refused.clone();
+            move |method: &str| match method {
+                "eth_blockNumber" => Some(serde_json::json!(format!("0x{:x}", head))),
+                _ => {
+                    refused.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    None
+                },
+            }
+        };
+        let (low, mid, high) = (serve_rpc(head(100)).await, serve_rpc(head(102)).await, serve_rpc(head(110)).await);
+        let dead = "http://127.0.0.1:9".to_string();
+
+        //a dead provider is failed over, an error answered by the node is not
+        set_providers(vec![dead.clone(), mid.clone(), low.clone()]).await.unwrap();
+        let number = with_provider("get block num", |web3| async move { web3.eth().block_number().await }).await;
+        assert_eq!(number, Ok(102.into()));
+        let answered = with_provider("chain id", |web3| async move { web3.eth().chain_id().await }).await.unwrap_err();
+        assert!(answered.contains("execution reverted"), "{}", answered);
+        assert_eq!(refused.load(std::sync::atomic::Ordering::SeqCst), 1);
+        //the failed provider is tried last from now on
+        with_provider("get block num", |web3| async move { web3.eth().block_number().await }).await.unwrap();
+        let status = provider_status().await;
+        assert_eq!(status[0]["failures"], 1);
+        assert!(status[0]["health"].as_f64().unwrap() < 1.0);
+        assert!(status[1..].iter().all(|p| p["failures"] == 0 && p["health"] == 1.0));
+
+        //the median of the fresh answers once the quorum is reached, the dead and far behind are stale
+        set_providers(vec![dead, low.clone(), mid.clone(), high.clone(), serve_rpc(head(90)).await]).await.unwrap();
+        assert!(set_head_quorum(6).await.is_err());
+        set_head_quorum(3).await.unwrap();
+        *HEAD.lock().await = None;
+        assert_eq!(refresh_head().await, Ok(102));
+        let stale: Vec<bool> = provider_status().await.iter().map(|p| p["stale"].as_bool().unwrap()).collect();
+        assert_eq!(stale, vec![true, false, false, false, true]);
+        assert_eq!(current_head().await, Ok(102));
+
+        //too few fresh answers keep the last head, which never moves back
+        set_head_quorum(1).await.unwrap();
+        set_providers(vec![low.clone(), mid]).await.unwrap();
+        set_head_quorum(2).await.unwrap();
+        assert_eq!(refresh_head().await, Ok(102));
+        set_providers(vec![low]).await.unwrap();
+        assert!(refresh_head().await.is_err());
+        assert_eq!(current_head().await, Ok(102));
+        *HEAD.lock().await = None;
+    }
+}
@@ -0,0 +1,405 @@
+// This file is part of the aoraki-labs library.
+
+// The aoraki-labs library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// The aoraki-labs library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with the aoraki-labs library. If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use lazy_static::lazy_static;
+use log::*;
+use serde_derive::{Deserialize,Serialize};
+use serde_json::json;
+use web3::ethabi::{Token, encode};
+use web3::signing::{self, keccak256};
+use web3::types::Address;
+
+use crate::chain::{get_current_block_num, block_time};
+use crate::provider::with_provider;
+use crate::signer::prover_signer;
+
+pub const EIP712_DOMAIN_NAME: &str = "ZKPool";
+pub const EIP712_DEFAULT_VERSION: &str = "1";
+pub const DEFAULT_QUOTE_EXPIRY_BLOCKS: u64 = 2000;
+const EIP712_DOMAIN_TYPE: &str = "EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)";
+const QUOTE_TYPE: &str = "Quote(bytes instance,address rewardToken,uint256 reward,address liabilityToken,uint256 liability,uint256 expiry,uint256 deadlineWindow)";
+
+lazy_static! {
+    pub static ref QUOTE_SIGNING: tokio::sync::Mutex<HashMap<Address, QuoteSigning>> = {   //signing mode per contract
+        tokio::sync::Mutex::new(HashMap::new())
+    };
+    pub static ref QUOTE_EXPIRY: tokio::sync::Mutex<QuoteExpiry> = {
+        tokio::sync::Mutex::new(QuoteExpiry::Blocks(DEFAULT_QUOTE_EXPIRY_BLOCKS))
+    };
+    pub static ref CHAIN_ID: tokio::sync::Mutex<Option<u64>> = {   //fetched once from the chain
+        tokio::sync::Mutex::new(None)
+    };
+}
+
+/// how quotes for a contract are signed
+#[derive(Debug, Clone, PartialEq)]
+pub enum QuoteSigning {
+    /// eth_sign of keccak256(abi.encode(terms)), no domain
+    Legacy,
+    /// EIP-712 typed data bound to the chain id and the contract
+    Eip712 { version: String },
+}
+
+/// how long a quote stays valid, the contract takes it as a block number
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QuoteExpiry {
+    Blocks(u64),
+    /// converted to blocks with the observed block time
+    Seconds(u64),
+}
+
+impl QuoteExpiry {
+    /// parse `<n>` or `<n>b` for blocks, `<n>s` for seconds
+    pub fn from_spec(spec: &str) -> Result<QuoteExpiry, String> {
+        let parse = |n: &str| n.parse::<u64>().map_err(|e| format!("invalid quote expiry {}: {}", spec, e));
+        if let Some(secs) = spec.strip_suffix('s') {
+            Ok(QuoteExpiry::Seconds(parse(secs)?))
+        } else {
+            Ok(QuoteExpiry::Blocks(parse(spec.strip_suffix('b').unwrap_or(spec))?))
+        }
+    }
+
+    /// the blocks a quote stays valid for at block_time seconds per block, rounded up
+    pub fn blocks(&self, block_time: f64) -> u64 {
+        match *self {
+            QuoteExpiry::Blocks(blocks) => blocks,
+            QuoteExpiry::Seconds(secs) => (secs as f64 / block_time).ceil() as u64,
+        }
+    }
+}
+
+pub async fn set_quote_expiry(expiry: QuoteExpiry) {
+    info!("quotes expire after {:?}", expiry);
+    let mut current = QUOTE_EXPIRY.lock().await;
+    *current = expiry;
+}
+
+/// the expiry block for a quote signed now
+pub async fn quote_expiry_block() -> Result<u64, String> {
+    let expiry = *QUOTE_EXPIRY.lock().await;
+    let blocks = match expiry {
+        QuoteExpiry::Blocks(blocks) => blocks,
+        QuoteExpiry::Seconds(_) => expiry.blocks(block_time().await),
+    };
+    Ok(get_current_block_num().await? + blocks)
+}
+
+/// the EIP-712 domain a quote was signed under
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct QuoteDomain {
+    pub name: String,
+    pub version: String,
+    pub chain_id: u64,
+    pub verifying_contract: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct TaskResponse {
+    pub prover: String,
+    pub instance: String,
+    pub reward_token: String,
+    pub reward: u64,
+    pub liability_window: u64,
+    pub liability_token: String,
+    pub liability: u64,
+    pub expiry: u64,
+    pub signature: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain: Option<QuoteDomain>,
+    /// why the terms differ from the requested ones on a counter quote
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub counter: Vec<String>,
+}
+
+impl QuoteSigning {
+    /// parse `legacy` or `eip712[:<version>]`
+    pub fn from_spec(spec: &str) -> Result<QuoteSigning, String> {
+        match spec.split_once(':') {
+            None if spec == "legacy" => Ok(QuoteSigning::Legacy),
+            None if spec == "eip712" => Ok(QuoteSigning::Eip712 { version: EIP712_DEFAULT_VERSION.to_string() }),
+            Some(("eip712", version)) if !version.is_empty() => Ok(QuoteSigning::Eip712 { version: version.to_string() }),
+            _ => Err(format!("unknown quote signing mode {}, expect legacy or eip712[:<version>]", spec)),
+        }
+    }
+}
+
+/// parse `<contract>=<mode>` and register the mode for that contract
+pub async fn set_quote_signing(spec: &str) -> Result<(), String> {
+    let (contract, mode) = spec.split_once('=')
+        .ok_or(format!("quote signing {} should be <contract>=<mode>", spec))?;
+    let contract = parse_address(contract)?;
+    let mode = QuoteSigning::from_spec(mode)?;
+    info!("quotes for {:?} are signed with {:?}", contract, mode);
+    let mut signing = QUOTE_SIGNING.lock().await;
+    signing.insert(contract, mode);
+    Ok(())
+}
+
+/// contracts without an explicit mode keep the legacy signature
+pub async fn quote_signing(contract: &Address) -> QuoteSigning {
+    let signing = QUOTE_SIGNING.lock().await;
+    signing.get(contract).cloned().unwrap_or(QuoteSigning::Legacy)
+}
+
+pub fn parse_address(addr: &str) -> Result<Address, String> {
+    Address::from_str(addr.trim_start_matches("0x")).map_err(|e| format!("invalid address {}: {}", addr, e))
+}
+
+pub async fn chain_id() -> Result<u64, String> {
+    let mut cached = CHAIN_ID.lock().await;
+    if let Some(id) = *cached {
+        return Ok(id);
+    }
+    let id = with_provider("get chain id", |web3| async move { web3.eth().chain_id().await }).await?.as_u64();
+    *cached = Some(id);
+    Ok(id)
+}
+
+/// the quote terms, in the order both signatures commit to them
+fn quote_tokens(quote: &TaskResponse) -> Result<Vec<Token>, String> {
+    Ok(vec![
+        Token::Bytes(quote.instance.as_bytes().to_vec()),
+        Token::Address(parse_address(&quote.reward_token)?),
+        Token::Uint(quote.reward.into()),
+        Token::Address(parse_address(&quote.liability_token)?),
+        Token::Uint(quote.liability.into()),
+        Token::Uint(quote.expiry.into()),
+        Token::Uint(quote.liability_window.into()),
+    ])
+}
+
+/// keccak256(abi.encode(instance, rewardToken, reward, liabilityToken, liability, expiry, deadlineWindow))
+pub fn legacy_hash(quote: &TaskResponse) -> Result<[u8; 32], String> {
+    let data_vec = quote_tokens(quote)?;
+    debug!("keccak256 the content is {:?}", data_vec);
+    Ok(keccak256(&encode(&data_vec)))
+}
+
+pub fn domain_separator(domain: &QuoteDomain) -> Result<[u8; 32], String> {
+    Ok(keccak256(&encode(&[
+        Token::FixedBytes(keccak256(EIP712_DOMAIN_TYPE.as_bytes()).to_vec()),
+        Token::FixedBytes(keccak256(domain.name.as_bytes()).to_vec()),
+        Token::FixedBytes(keccak256(domain.version.as_bytes()).to_vec()),
+        Token::Uint(domain.chain_id.into()),
+        Token::Address(parse_address(&domain.verifying_contract)?),
+    ])))
+}
+
+/// keccak256(0x1901 || domainSeparator || hashStruct(quote))
+pub fn eip712_digest(quote: &TaskResponse, domain: &QuoteDomain) -> Result<[u8; 32], String> {
+    let mut struct_tokens = vec![Token::FixedBytes(keccak256(QUOTE_TYPE.as_bytes()).to_vec())];
+    for token in quote_tokens(quote)? {
+        match token {
+            Token::Bytes(bytes) => struct_tokens.push(Token::FixedBytes(keccak256(&bytes).to_vec())),
+            other => struct_tokens.push(other),
+        }
+    }
+    let struct_hash = keccak256(&encode(&struct_tokens));
+    let mut message = vec![0x19, 0x01];
+    message.extend_from_slice(&domain_separator(domain)?);
+    message.extend_from_slice(&struct_hash);
+    Ok(keccak256(&message))
+}
+
+/// the eth_signTypedData payload matching eip712_digest
+fn typed_data(quote: &TaskResponse, domain: &QuoteDomain) -> serde_json::Value {
+    json!({
+        "types": {
+            "EIP712Domain": [
+                {"name": "name", "type": "string"},
+                {"name": "version", "type": "string"},
+                {"name": "chainId", "type": "uint256"},
+                {"name": "verifyingContract", "type": "address"},
+            ],
+            "Quote": [
+                {"name": "instance", "type": "bytes"},
+                {"name": "rewardToken", "type": "address"},
+                {"name": "reward", "type": "uint256"},
+                {"name": "liabilityToken", "type": "address"},
+                {"name": "liability", "type": "uint256"},
+                {"name": "expiry", "type": "uint256"},
+                {"name": "deadlineWindow", "type": "uint256"},
+            ],
+        },
+        "primaryType": "Quote",
+        "domain": {
+            "name": domain.name,
+            "version": domain.version,
+            "chainId": domain.chain_id,
+            "verifyingContract": domain.verifying_contract,
+        },
+        "message": {
+            "instance": format!("0x{}", hex::encode(quote.instance.as_bytes())),
+            "rewardToken": quote.reward_token,
+            "reward": quote.reward.to_string(),
+            "liabilityToken": quote.liability_token,
+            "liability": quote.liability.to_string(),
+            "expiry": quote.expiry.to_string(),
+            "deadlineWindow": quote.liability_window.to_string(),
+        },
+    })
+}
+
+/// sign the quote with the prover key in the mode configured for the contract,
+/// filling prover, domain and signature; returns the signed hash
+pub async fn sign_quote(quote: &mut TaskResponse, contract: &Address) -> Result<[u8; 32], String> {
+    let signer = prover_signer()?;
+    quote.prover = format!("{:?}", signer.address());
+    let (hash, signature) = match quote_signing(contract).await {
+        QuoteSigning::Legacy => {
+            quote.domain = None;
+            let hash = legacy_hash(quote)?;
+            let signature = signer.sign_hash(hash).await?;
+            info!("keccak hash is:{},signature is {}", hex::encode(hash), hex::encode(&signature));
+            (hash, signature)
+        },
+        QuoteSigning::Eip712 { version } => {
+            let domain = QuoteDomain {
+                name: EIP712_DOMAIN_NAME.to_string(),
+                version,
+                chain_id: chain_id().await?,
+                verifying_contract: format!("{:?}", contract),
+            };
+            let digest = eip712_digest(quote, &domain)?;
+            let signature = signer.sign_typed_data(digest, typed_data(quote, &domain)).await?;
+            info!("eip712 digest is:{},signature is {}", hex::encode(digest), hex::encode(&signature));
+            quote.domain = Some(domain);
+            (digest, signature)
+        },
+    };
+    quote.signature = hex::encode(signature);
+    Ok(hash)
+}
+
+/// recover the address that signed the quote, legacy or EIP-712 depending on its domain
+pub fn recover_quote_signer(quote: &TaskResponse) -> Result<Address, String> {
+    let signature = hex::decode(quote.signature.trim_start_matches("0x"))
+        .map_err(|e| format!("invalid signature hex: {}", e))?;
+    if signature.len() != 65 {
+        return Err(format!("signature should be 65 bytes, got {}", signature.len()));
+    }
+    let digest: [u8; 32] = match &quote.domain {
+        None => signing::hash_message(legacy_hash(quote)?).into(),
+        Some(domain) => eip712_digest(quote, domain)?,
+    };
+    let recovery_id = match signature[64] {
+        v @ 27..=28 => v - 27,
+        v @ 0..=1 => v,
+        v => return Err(format!("invalid signature v {}", v)),
+    };
+    signing::recover(&digest, &signature[..64], recovery_id as i32)
+        .map_err(|e| format!("recover signer: {}", e))
+}
+
+/// check the quote was signed by its prover, and for EIP-712 quotes that it is bound to
+/// the expected chain and contract
+pub fn verify_quote(quote: &TaskResponse, chain_id: u64, contract: &Address) -> Result<Address, String> {
+    if let Some(domain) = &quote.domain {
+        if domain.chain_id != chain_id {
+            return Err(format!("quote is for chain {}, expected {}", domain.chain_id, chain_id));
+        }
+        if parse_address(&domain.verifying_contract)? != *contract {
+            return Err(format!("quote is for contract {}, expected {:?}", domain.verifying_contract, contract));
+        }
+    }
+    let signer = recover_quote_signer(quote)?;
+    if signer != parse_address(&quote.prover)? {
+        return Err(format!("quote signed by {:?}, prover is {}", signer, quote.prover));
+    }
+    Ok(signer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signer::{set_prover_signer, LocalSigner, Signer};
+    use crate::chain::BLOCK_TIME_CACHE;
+
+    const CHAIN: u64 = 11155111;
+
+    fn quote() -> TaskResponse {
+        TaskResponse {
+            instance: "instance".to_string(),
+            reward_token: format!("{:?}", Address::repeat_byte(0x22)),
+            reward: 100,
+            liability_window: 600,
+            liability_token: format!("{:?}", Address::repeat_byte(0x33)),
+            liability: 50,
+            expiry: 1200,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn expiry_specs() {
+        assert_eq!(QuoteExpiry::from_spec("2000"), Ok(QuoteExpiry::Blocks(2000)));
+        assert_eq!(QuoteExpiry::from_spec("150b"), Ok(QuoteExpiry::Blocks(150)));
+        assert_eq!(QuoteExpiry::from_spec("600s"), Ok(QuoteExpiry::Seconds(600)));
+        for spec in ["", "s", "10m", "-5", "1.5s"] {
+            assert!(QuoteExpiry::from_spec(spec).is_err(), "{}", spec);
+        }
+    }
+
+    #[tokio::test]
+    async fn expiry_seconds_follow_the_block_time() {
+        assert_eq!(QuoteExpiry::Blocks(2000).blocks(12.0), 2000);
+        assert_eq!(QuoteExpiry::Seconds(600).blocks(12.0), 50);
+        //a partial block still counts
+        assert_eq!(QuoteExpiry::Seconds(601).blocks(12.0), 51);
+        assert_eq!(QuoteExpiry::Seconds(10).blocks(0.25), 40);
+        //an observed block time is used until it is stale
+        *BLOCK_TIME_CACHE.lock().await = Some((tokio::time::Instant::now(), 2.0));
+        assert_eq!(QuoteExpiry::Seconds(600).blocks(block_time().await), 300);
+    }
+
+    #[tokio::test]
+    async fn quote_sign_verify_round_trip() {
+        //set once per process, whichever test gets there first
+        set_prover_signer(Signer::Local(LocalSigner::from_hex(&"11".repeat(32)).unwrap())).ok();
+        let prover = prover_signer().unwrap().address();
+        *CHAIN_ID.lock().await = Some(CHAIN);
+        let eip712 = Address::repeat_byte(0x44);
+        let legacy = Address::repeat_byte(0x55);
+        set_quote_signing(&format!("{:?}=eip712:2", eip712)).await.unwrap();
+
+        let mut typed = quote();
+        let digest = sign_quote(&mut typed, &eip712).await.unwrap();
+        let domain = typed.domain.clone().unwrap();
+        assert_eq!((domain.version.as_str(), domain.chain_id), ("2", CHAIN));
+        assert_eq!(digest, eip712_digest(&typed, &domain).unwrap());
+        assert_eq!(verify_quote(&typed, CHAIN, &eip712), Ok(prover));
+        assert!(verify_quote(&typed, 1, &eip712).is_err());
+        assert!(verify_quote(&typed, CHAIN, &legacy).is_err());
+
+        let mut plain = quote();
+        let hash = sign_quote(&mut plain, &legacy).await.unwrap();
+        assert!(plain.domain.is_none());
+        assert_eq!(hash, legacy_hash(&plain).unwrap());
+        assert_eq!(verify_quote(&plain, CHAIN, &legacy), Ok(prover));
+        assert_ne!(hash, digest);
+
+        //any term changed after signing, or another prover claimed, fails the check
+        for signed in [typed, plain] {
+            let mut changed = signed.clone();
+            changed.reward += 1;
+            assert!(verify_quote(&changed, CHAIN, &eip712).is_err() && verify_quote(&changed, CHAIN, &legacy).is_err());
+            let mut claimed = signed.clone();
+            claimed.prover = format!("{:?}", Address::repeat_byte(0x66));
+            assert!(verify_quote(&claimed, CHAIN, &eip712).is_err() && verify_quote(&claimed, CHAIN, &legacy).is_err());
+        }
+    }
+}
@@ -0,0 +1,291 @@
+// This file is part of the aoraki-labs library.
+
+// The aoraki-labs library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// The aoraki-labs library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with the aoraki-labs library. If not, see <https://www.gnu.org/licenses/>.
+
+#[cfg(not(feature = "DB"))]
+use std::collections::{HashMap, VecDeque};
+#[cfg(not(feature = "DB"))]
+use lazy_static::lazy_static;
+use log::*;
+use serde_json::Value;
+
+use crate::chain::TaskSubmittedEvent;
+#[cfg(feature = "DB")]
+use crate::db::*;
+use crate::models::NewQuote;
+use crate::quote::{TaskResponse, parse_address};
+
+#[cfg(not(feature = "DB"))]
+lazy_static! {
+    pub static ref QUOTES: tokio::sync::Mutex<HashMap<String, NewQuote>> = {   //outstanding quotes by quote hash
+        tokio::sync::Mutex::new(HashMap::new())
+    };
+    pub static ref CLOSED_QUOTES: tokio::sync::Mutex<VecDeque<NewQuote>> = {   //matched and expired quotes, newest last
+        tokio::sync::Mutex::new(VecDeque::new())
+    };
+}
+
+#[cfg(not(feature = "DB"))]
+pub const CLOSED_QUOTES_KEPT: usize = 1000;
+
+/// a quote is outstanding until a TaskSubmitted with its terms arrives or its expiry block passes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuoteStatus {
+    Outstanding,
+    Matched,
+    Expired,
+}
+
+impl QuoteStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuoteStatus::Outstanding => "outstanding",
+            QuoteStatus::Matched => "matched",
+            QuoteStatus::Expired => "expired",
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "outstanding" => Some(QuoteStatus::Outstanding),
+            "matched" => Some(QuoteStatus::Matched),
+            "expired" => Some(QuoteStatus::Expired),
+            _ => None,
+        }
+    }
+}
+
+fn normalize_address(addr: &str) -> String {
+    parse_address(addr).map(|a| format!("{:?}", a)).unwrap_or_else(|_| addr.to_lowercase())
+}
+
+/// the terms of an on-chain TaskSubmitted, in the form quotes are stored
+fn event_terms(event: &TaskSubmittedEvent) -> NewQuote {
+    NewQuote {
+        quote_hash: String::new(),
+        prover: format!("{:?}", event.prover),
+        instance: hex::encode(&event.instance.0),
+        reward_token: format!("{:?}", event.reward_token),
+        reward_amount: event.reward_amount.to_string(),
+        liability_token: format!("{:?}", event.liability_token),
+        liability_amount: event.liability_amount.to_string(),
+        liability_window: event.liability_window as i64,
+        expiry: 0,
+        signature: String::new(),
+        status: QuoteStatus::Outstanding.as_str().to_string(),
+        task_id: None,
+    }
+}
+
+/// keep a matched or expired quote for QueryQuotes, forgetting the oldest past CLOSED_QUOTES_KEPT
+#[cfg(not(feature = "DB"))]
+async fn close_quotes(closed: Vec<NewQuote>) {
+    let mut kept = CLOSED_QUOTES.lock().await;
+    kept.extend(closed);
+    while kept.len() > CLOSED_QUOTES_KEPT {
+        kept.pop_front();
+    }
+}
+
+#[cfg(not(feature = "DB"))]
+fn same_terms(a: &NewQuote, b: &NewQuote) -> bool {
+    a.prover == b.prover && a.instance == b.instance
+        && a.reward_token == b.reward_token && a.reward_amount == b.reward_amount
+        && a.liability_token == b.liability_token && a.liability_amount == b.liability_amount
+        && a.liability_window == b.liability_window
+}
+
+/// record a signed quote under the hash its signature commits to
+pub async fn register_quote(quote: &TaskResponse, quote_hash: [u8; 32]) -> Result<(), String> {
+    let issued = NewQuote {
+        quote_hash: hex::encode(quote_hash),
+        prover: normalize_address(&quote.prover),
+        instance: hex::encode(quote.instance.as_bytes()),
+        reward_token: normalize_address(&quote.reward_token),
+        reward_amount: quote.reward.to_string(),
+        liability_token: normalize_address(&quote.liability_token),
+        liability_amount: quote.liability.to_string(),
+        liability_window: quote.liability_window as i64,
+        expiry: quote.expiry as i64,
+        signature: quote.signature.clone(),
+        status: QuoteStatus::Outstanding.as_str().to_string(),
+        task_id: None,
+    };
+    #[cfg(feature = "DB")]
+    {
+        add_quote(issued).await?;
+    }
+    #[cfg(not(feature = "DB"))]
+    {
+        QUOTES.lock().await.insert(issued.quote_hash.clone(), issued);
+    }
+    Ok(())
+}
+
+/// match a TaskSubmitted against the outstanding quotes, reporting submissions for our prover that
+/// carry terms we never signed or signed with an expiry already passed
+pub async fn match_task_submitted(event: &TaskSubmittedEvent, our_prover: &str) -> Option<String> {
+    let terms = event_terms(event);
+    let task_id = event.task_id();
+    let block_num = event.block_number as i64;
+
+    #[cfg(feature = "DB")]
+    let matched = match match_quote(terms.clone(), block_num, &task_id).await {
+        Ok(r) => r,
+        Err(e) => {
+            error!("match quote for task {}: {}", task_id, e);
+            return None
+        },
+    };
+    #[cfg(not(feature = "DB"))]
+    let matched = {
+        let mut quotes = QUOTES.lock().await;
+        let found = quotes.values()
+            .filter(|q| q.expiry >= block_num && same_terms(q, &terms))
+            .min_by_key(|q| q.expiry)
+            .map(|q| q.quote_hash.clone());
+        let closed = found.as_ref().and_then(|quote_hash| quotes.remove(quote_hash));
+        drop(quotes);
+        if let Some(mut q) = closed {
+            q.status = QuoteStatus::Matched.as_str().to_string();
+            q.task_id = Some(task_id.clone());
+            close_quotes(vec![q]).await;
+        }
+        found
+    };
+
+    match &matched {
+        Some(quote_hash) => info!("task {} matches quote {}", task_id, quote_hash),
+        None if terms.prover == normalize_address(our_prover) => {
+            warn!("task {} names our prover but matches no outstanding quote: {:?}", task_id, terms)
+        },
+        None => debug!("task {} is for prover {}, not a quote of ours", task_id, terms.prover),
+    }
+    matched
+}
+
+/// expire the outstanding quotes whose expiry block is before block_num and report them
+pub async fn expire_quotes_before(block_num: u64) {
+    #[cfg(feature = "DB")]
+    let expired: Vec<String> = match expire_quotes(block_num as i64).await {
+        Ok(r) => r.into_iter().map(|q| q.quote_hash).collect(),
+        Err(e) => {
+            error!("expire quotes: {}", e);
+            return
+        },
+    };
+    #[cfg(not(feature = "DB"))]
+    let expired: Vec<String> = {
+        let mut quotes = QUOTES.lock().await;
+        let hashes: Vec<String> = quotes.values()
+            .filter(|q| q.expiry < block_num as i64)
+            .map(|q| q.quote_hash.clone())
+            .collect();
+        let closed: Vec<NewQuote> = hashes.iter()
+            .filter_map(|quote_hash| quotes.remove(quote_hash))
+            .map(|mut q| {
+                q.status = QuoteStatus::Expired.as_str().to_string();
+                q
+            })
+            .collect();
+        drop(quotes);
+        close_quotes(closed).await;
+        hashes
+    };
+    for quote_hash in expired {
+        warn!("quote {} expired without a matching TaskSubmitted", quote_hash);
+    }
+}
+
+/// the quotes in a status as JSON, oldest expiry first
+pub async fn list_quotes(status: QuoteStatus) -> Result<Value, String> {
+    #[cfg(feature = "DB")]
+    {
+        let quotes = get_quotes(status).await?;
+        serde_json::to_value(quotes).map_err(|e| e.to_string())
+    }
+    #[cfg(not(feature = "DB"))]
+    {
+        let mut quotes: Vec<NewQuote> = match status {
+            QuoteStatus::Outstanding => QUOTES.lock().await.values().cloned().collect(),
+            _ => CLOSED_QUOTES.lock().await.iter()
+                .filter(|q| q.status == status.as_str())
+                .cloned()
+                .collect(),
+        };
+        quotes.sort_by_key(|q| q.expiry);
+        serde_json::to_value(quotes).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(all(test, not(feature = "DB")))]
+mod tests {
+    use super::*;
+    use web3::types::{Address, Bytes, U256};
+
+    fn quote(instance: &str, expiry: u64) -> TaskResponse {
+        TaskResponse {
+            prover: "0x00000000000000000000000000000000000000a1".to_string(),
+            instance: instance.to_string(),
+            reward_token: "0x00000000000000000000000000000000000000a2".to_string(),
+            reward: 100,
+            liability_window: 600,
+            liability_token: "0x00000000000000000000000000000000000000a3".to_string(),
+            liability: 50,
+            expiry,
+            ..Default::default()
+        }
+    }
+
+    fn submitted(quote: &TaskResponse, block_number: u64) -> TaskSubmittedEvent {
+        TaskSubmittedEvent {
+            prover: parse_address(&quote.prover).unwrap(),
+            instance: Bytes(quote.instance.as_bytes().to_vec()),
+            reward_token: parse_address(&quote.reward_token).unwrap(),
+            reward_amount: U256::from(quote.reward),
+            liability_window: quote.liability_window,
+            liability_token: parse_address(&quote.liability_token).unwrap(),
+            liability_amount: U256::from(quote.liability),
+            block_number,
+            requester: Address::repeat_byte(0x11),
+            ..Default::default()
+        }
+    }
+
+    async fn listed(status: QuoteStatus, quote_hash: &str) -> bool {
+        list_quotes(status).await.unwrap().as_array().unwrap().iter().any(|q| q["quote_hash"] == quote_hash)
+    }
+
+    #[tokio::test]
+    async fn match_and_expire_close_quotes() {
+        let (early, late) = (quote("registry-early", 20), quote("registry-late", 40));
+        register_quote(&early, [0x21; 32]).await.unwrap();
+        register_quote(&late, [0x22; 32]).await.unwrap();
+        let (early_hash, late_hash) = (hex::encode([0x21; 32]), hex::encode([0x22; 32]));
+
+        //a submission after the quote's expiry block does not match it
+        assert_eq!(match_task_submitted(&submitted(&early, 21), "").await, None);
+        let matched = match_task_submitted(&submitted(&late, 30), "").await;
+        assert_eq!(matched.as_deref(), Some(late_hash.as_str()));
+        //matched once, not again
+        assert_eq!(match_task_submitted(&submitted(&late, 30), "").await, None);
+        assert!(!QUOTES.lock().await.contains_key(&late_hash));
+        assert!(listed(QuoteStatus::Matched, &late_hash).await);
+
+        expire_quotes_before(21).await;
+        assert!(!QUOTES.lock().await.contains_key(&early_hash));
+        assert!(!listed(QuoteStatus::Outstanding, &early_hash).await);
+        assert!(listed(QuoteStatus::Expired, &early_hash).await);
+    }
+}
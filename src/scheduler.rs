@@ -0,0 +1,776 @@
+// This file is part of the aoraki-labs library.
+
+// The aoraki-labs library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// The aoraki-labs library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with the aoraki-labs library. If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use lazy_static::lazy_static;
+use log::*;
+use serde_derive::Serialize;
+use serde_json::{json, Value};
+use tokio::time::{self, Instant};
+
+use crate::work;
+
+pub const SCHEDULER_TIMEOUT_SECS: u64 = 30;
+pub const SCHEDULER_HEALTH_SECS: u64 = 10;   //between health checks
+const DELIVER_METHOD: &str = "DelieveTask";
+const REST_TASKS_PATH: &str = "tasks";
+const REST_HEALTH_PATH: &str = "health";
+pub const PULL_SPEC: &str = "pull";
+pub const SEGMENT_ATTEMPTS: u32 = 3;         //sends of a segment before its task fails, replaced by --segment_attempts
+
+lazy_static! {
+    pub static ref SCHEDULERS: tokio::sync::Mutex<Vec<SchedulerEndpoint>> = {   //the -s schedulers, in order given
+        tokio::sync::Mutex::new(vec![])
+    };
+    pub static ref PLACEMENT: tokio::sync::Mutex<Placement> = {
+        tokio::sync::Mutex::new(Placement::RoundRobin)
+    };
+    pub static ref ASSIGNED: tokio::sync::Mutex<HashMap<String, Assignment>> = {   //segment id -> the scheduler holding it
+        tokio::sync::Mutex::new(HashMap::new())
+    };
+    pub static ref MAX_ATTEMPTS: tokio::sync::Mutex<u32> = {
+        tokio::sync::Mutex::new(SEGMENT_ATTEMPTS)
+    };
+}
+
+/// a segment handed to the scheduler to prove
+#[derive(Debug, Clone, Serialize)]
+pub struct Segment {
+    pub project_id: String,
+    /// <task key>@<split id>, the id the proof comes back with
+    pub task_id: String,
+    /// hex instance of the task
+    pub instance: String,
+    pub degree: String,
+}
+
+/// a segment in flight: the scheduler holding it, when it was last sent and how often
+#[derive(Debug, Clone)]
+pub struct Assignment {
+    pub url: String,
+    pub segment: Segment,
+    pub sent_at: Instant,
+    /// how long its proof may take before it is sent again
+    pub timeout: Duration,
+    pub attempts: u32,
+}
+
+/// why a scheduler did not take a segment
+#[derive(Debug)]
+pub enum DeliveryError {
+    /// no answer: unreachable, timed out or a server error
+    Down(String),
+    /// the scheduler answered and refused it
+    Refused(String),
+}
+
+impl fmt::Display for DeliveryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeliveryError::Down(e) => write!(f, "down: {}", e),
+            DeliveryError::Refused(e) => write!(f, "refused: {}", e),
+        }
+    }
+}
+
+/// what the relayer needs from a scheduler, whatever protocol it speaks
+pub trait SchedulerClient {
+    /// hand a segment over, the scheduler's answer on success
+    async fn deliver_task(&self, segment: &Segment) -> Result<Value, DeliveryError>;
+    /// free segment slots for the project; query is the JSON-RPC method or the REST path answering them
+    async fn capacity(&self, project_id: &str, query: &str) -> Result<u64, String>;
+    /// whether the scheduler answers at all, any HTTP answer below 500 counts
+    async fn ping(&self) -> Result<(), String>;
+    fn url(&self) -> &str;
+}
+
+/// JSON-RPC 2.0 over HTTP POST, the protocol of the original schedulers
+pub struct JsonRpcScheduler {
+    url: String,
+    client: reqwest::Client,
+    next_id: AtomicU64,
+}
+
+/// plain REST: POST <url>/tasks with the segment as JSON, GET <url>/<path>?project_id= for capacity
+pub struct RestScheduler {
+    url: String,
+    client: reqwest::Client,
+}
+
+/// no service: segments wait in the relayer for provers calling demo/GetWork
+pub struct PullScheduler;
+
+pub enum Scheduler {
+    JsonRpc(JsonRpcScheduler),
+    Rest(RestScheduler),
+    Pull(PullScheduler),
+}
+
+fn http_client() -> Result<reqwest::Client, String> {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(SCHEDULER_TIMEOUT_SECS))
+        .build()
+        .map_err(|e| format!("scheduler http client: {}", e))
+}
+
+/// a JSON body of a 2xx answer, the status and body otherwise
+async fn read_response(url: &str, call: &str, response: Result<reqwest::Response, reqwest::Error>) -> Result<Value, DeliveryError> {
+    let response = response.map_err(|e| DeliveryError::Down(format!("scheduler {} {}: {}", url, call, e)))?;
+    let status = response.status();
+    let body = response.text().await.map_err(|e| DeliveryError::Down(format!("scheduler {} {}: {}", url, call, e)))?;
+    let err = format!("scheduler {} {}: http {}: {}", url, call, status, body.trim());
+    if status.is_server_error() {
+        return Err(DeliveryError::Down(err));
+    }
+    if !status.is_success() {
+        return Err(DeliveryError::Refused(err));
+    }
+    //some schedulers answer plain text
+    Ok(serde_json::from_str(&body).unwrap_or(Value::String(body)))
+}
+
+async fn ping_url(client: &reqwest::Client, url: &str) -> Result<(), String> {
+    let response = client.get(url).send().await.map_err(|e| format!("scheduler {}: {}", url, e))?;
+    if response.status().is_server_error() {
+        return Err(format!("scheduler {}: http {}", url, response.status()));
+    }
+    Ok(())
+}
+
+fn parse_capacity(answer: &Value) -> Result<u64, String> {
+    match answer {
+        Value::Number(n) => n.as_u64().ok_or(format!("invalid capacity {}", n)),
+        Value::String(s) => s.trim().parse::<u64>().map_err(|e| format!("invalid capacity {}: {}", s, e)),
+        _ => Err(format!("unexpected capacity answer {}", answer)),
+    }
+}
+
+impl JsonRpcScheduler {
+    pub fn new(url: &str) -> Result<Self, String> {
+        Ok(JsonRpcScheduler { url: url.to_string(), client: http_client()?, next_id: AtomicU64::new(1) })
+    }
+
+    /// the result of a call, the JSON-RPC error as an error
+    async fn rpc(&self, method: &str, params: Value) -> Result<Value, DeliveryError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let request = json!({"jsonrpc": "2.0", "method": method, "params": params, "id": id});
+        let response = self.client.post(&self.url).json(&request).send().await;
+        let response = read_response(&self.url, method, response).await?;
+        if let Some(err) = response.get("error").filter(|err| !err.is_null()) {
+            return Err(DeliveryError::Refused(format!("scheduler {} {}: {}", self.url, method, err)));
+        }
+        if response.get("id") != Some(&json!(id)) {
+            warn!("scheduler {} {} answered id {:?}, asked {}", self.url, method, response.get("id"), id);
+        }
+        response.get("result").cloned()
+            .ok_or(DeliveryError::Refused(format!("scheduler {} {}: no result in {}", self.url, method, response)))
+    }
+}
+
+impl SchedulerClient for JsonRpcScheduler {
+    async fn deliver_task(&self, segment: &Segment) -> Result<Value, DeliveryError> {
+        self.rpc(DELIVER_METHOD, json!([segment.project_id, segment.task_id, segment.instance, segment.degree])).await
+    }
+
+    async fn capacity(&self, project_id: &str, query: &str) -> Result<u64, String> {
+        parse_capacity(&self.rpc(query, json!([project_id])).await.map_err(|e| e.to_string())?)
+    }
+
+    async fn ping(&self) -> Result<(), String> {
+        ping_url(&self.client, &self.url).await
+    }
+
+    fn url(&self) -> &str {
+        &self.url
+    }
+}
+
+impl RestScheduler {
+    pub fn new(url: &str) -> Result<Self, String> {
+        Ok(RestScheduler { url: url.trim_end_matches('/').to_string(), client: http_client()? })
+    }
+}
+
+impl SchedulerClient for RestScheduler {
+    async fn deliver_task(&self, segment: &Segment) -> Result<Value, DeliveryError> {
+        let url = format!("{}/{}", self.url, REST_TASKS_PATH);
+        let response = self.client.post(&url).json(segment).send().await;
+        read_response(&url, "deliver task", response).await
+    }
+
+    async fn capacity(&self, project_id: &str, query: &str) -> Result<u64, String> {
+        let url = format!("{}/{}", self.url, query.trim_start_matches('/'));
+        let response = self.client.get(&url).query(&[("project_id", project_id)]).send().await;
+        parse_capacity(&read_response(&url, "capacity", response).await.map_err(|e| e.to_string())?)
+    }
+
+    async fn ping(&self) -> Result<(), String> {
+        ping_url(&self.client, &format!("{}/{}", self.url, REST_HEALTH_PATH)).await
+    }
+
+    fn url(&self) -> &str {
+        &self.url
+    }
+}
+
+impl SchedulerClient for PullScheduler {
+    async fn deliver_task(&self, segment: &Segment) -> Result<Value, DeliveryError> {
+        work::offer(segment).await;
+        Ok(json!("queued"))
+    }
+
+    async fn capacity(&self, _project_id: &str, _query: &str) -> Result<u64, String> {
+        Ok(work::free_slots().await)
+    }
+
+    async fn ping(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn url(&self) -> &str {
+        PULL_SPEC
+    }
+}
+
+impl Scheduler {
+    /// build a scheduler client from a spec:
+    ///   [jsonrpc:]<url>     JSON-RPC endpoint, DelieveTask
+    ///   rest:<url>          REST API base url
+    ///   pull                provers poll the relayer with demo/GetWork
+    pub fn from_spec(spec: &str) -> Result<Scheduler, String> {
+        if spec == PULL_SPEC {
+            Ok(Scheduler::Pull(PullScheduler))
+        } else if let Some(url) = spec.strip_prefix("rest:") {
+            Ok(Scheduler::Rest(RestScheduler::new(url)?))
+        } else {
+            Ok(Scheduler::JsonRpc(JsonRpcScheduler::new(spec.strip_prefix("jsonrpc:").unwrap_or(spec))?))
+        }
+    }
+
+    pub fn protocol(&self) -> &'static str {
+        match self {
+            Scheduler::JsonRpc(_) => "jsonrpc",
+            Scheduler::Rest(_) => "rest",
+            Scheduler::Pull(_) => "pull",
+        }
+    }
+}
+
+impl SchedulerClient for Scheduler {
+    async fn deliver_task(&self, segment: &Segment) -> Result<Value, DeliveryError> {
+        match self {
+            Scheduler::JsonRpc(client) => client.deliver_task(segment).await,
+            Scheduler::Rest(client) => client.deliver_task(segment).await,
+            Scheduler::Pull(client) => client.deliver_task(segment).await,
+        }
+    }
+
+    async fn capacity(&self, project_id: &str, query: &str) -> Result<u64, String> {
+        match self {
+            Scheduler::JsonRpc(client) => client.capacity(project_id, query).await,
+            Scheduler::Rest(client) => client.capacity(project_id, query).await,
+            Scheduler::Pull(client) => client.capacity(project_id, query).await,
+        }
+    }
+
+    async fn ping(&self) -> Result<(), String> {
+        match self {
+            Scheduler::JsonRpc(client) => client.ping().await,
+            Scheduler::Rest(client) => client.ping().await,
+            Scheduler::Pull(client) => client.ping().await,
+        }
+    }
+
+    fn url(&self) -> &str {
+        match self {
+            Scheduler::JsonRpc(client) => client.url(),
+            Scheduler::Rest(client) => client.url(),
+            Scheduler::Pull(client) => client.url(),
+        }
+    }
+}
+
+/// how a segment picks its scheduler among the healthy ones
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Placement {
+    /// in turn, each scheduler as often as its weight
+    RoundRobin,
+    /// the one with the fewest segments in flight per weight
+    LeastLoaded,
+}
+
+impl Placement {
+    pub fn from_str(s: &str) -> Option<Placement> {
+        match s {
+            "round_robin" => Some(Placement::RoundRobin),
+            "least_loaded" => Some(Placement::LeastLoaded),
+            _ => None,
+        }
+    }
+}
+
+/// a scheduler of the set, with its weight and health
+#[derive(Serialize)]
+pub struct SchedulerEndpoint {
+    #[serde(skip)]
+    pub client: Arc<Scheduler>,
+    pub url: String,
+    pub protocol: &'static str,
+    pub weight: u32,
+    pub healthy: bool,
+    pub delivered: u64,
+    pub refused: u64,
+    pub failures: u64,
+    pub last_error: Option<String>,
+    #[serde(skip)]
+    current: i64,          //smooth weighted round robin credit
+}
+
+impl SchedulerEndpoint {
+    /// a scheduler spec with an optional weight: <spec>[,<weight>]
+    pub fn from_spec(spec: &str) -> Result<SchedulerEndpoint, String> {
+        let (spec, weight) = match spec.rsplit_once(',') {
+            Some((spec, weight)) => (spec, weight.parse::<u32>().map_err(|e| format!("scheduler weight {}: {}", weight, e))?),
+            None => (spec, 1),
+        };
+        if weight == 0 {
+            return Err(format!("scheduler {} needs a weight of at least 1", spec));
+        }
+        let client = Scheduler::from_spec(spec)?;
+        Ok(SchedulerEndpoint {
+            url: client.url().to_string(),
+            protocol: client.protocol(),
+            client: Arc::new(client),
+            weight,
+            healthy: true,
+            delivered: 0,
+            refused: 0,
+            failures: 0,
+            last_error: None,
+            current: 0,
+        })
+    }
+}
+
+pub async fn set_schedulers(specs: Vec<String>, placement: Placement) -> Result<(), String> {
+    let endpoints = specs.iter().map(|spec| SchedulerEndpoint::from_spec(spec)).collect::<Result<Vec<_>, String>>()?;
+    if endpoints.is_empty() {
+        return Err("no scheduler configured".to_string());
+    }
+    for endpoint in endpoints.iter() {
+        info!("scheduler {} over {}, weight {}", endpoint.url, endpoint.protocol, endpoint.weight);
+    }
+    info!("segment placement {:?}", placement);
+    *SCHEDULERS.lock().await = endpoints;
+    *PLACEMENT.lock().await = placement;
+    Ok(())
+}
+
+async fn in_flight() -> HashMap<String, usize> {
+    let mut counts = HashMap::new();
+    for assignment in ASSIGNED.lock().await.values() {
+        *counts.entry(assignment.url.clone()).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// indexes of the endpoints to try, best first by the placement, skipping excluded urls;
+/// the unhealthy ones only when none is healthy, in the order given
+fn placement_order(endpoints: &mut [SchedulerEndpoint], placement: Placement, loads: &HashMap<String, usize>, exclude: &[String]) -> Vec<usize> {
+    let eligible: Vec<usize> = (0..endpoints.len()).filter(|&i| !exclude.contains(&endpoints[i].url)).collect();
+    let mut order: Vec<usize> = eligible.iter().copied().filter(|&i| endpoints[i].healthy).collect();
+    if order.is_empty() {
+        return eligible;
+    }
+    match placement {
+        Placement::RoundRobin => {
+            let total: i64 = order.iter().map(|&i| endpoints[i].weight as i64).sum();
+            for &i in order.iter() {
+                endpoints[i].current += endpoints[i].weight as i64;
+            }
+            order.sort_by_key(|&i| -endpoints[i].current);
+            endpoints[order[0]].current -= total;
+        },
+        Placement::LeastLoaded => {
+            let load = |i: usize| *loads.get(&endpoints[i].url).unwrap_or(&0) as f64 / endpoints[i].weight as f64;
+            order.sort_by(|&a, &b| load(a).total_cmp(&load(b)));
+        },
+    }
+    order
+}
+
+/// the schedulers to try, best first
+async fn candidates(exclude: &[String]) -> Vec<(String, Arc<Scheduler>)> {
+    let placement = *PLACEMENT.lock().await;
+    let loads = in_flight().await;
+    let mut endpoints = SCHEDULERS.lock().await;
+    placement_order(&mut endpoints, placement, &loads, exclude).into_iter()
+        .map(|i| (endpoints[i].url.clone(), endpoints[i].client.clone()))
+        .collect()
+}
+
+async fn record(url: &str, result: Result<(), &DeliveryError>) {
+    let mut endpoints = SCHEDULERS.lock().await;
+    let endpoint = match endpoints.iter_mut().find(|endpoint| endpoint.url == url) {
+        Some(r) => r,
+        None => return,
+    };
+    match result {
+        Ok(()) => {
+            endpoint.delivered += 1;
+            endpoint.healthy = true;
+        },
+        Err(DeliveryError::Refused(e)) => {
+            endpoint.refused += 1;
+            endpoint.last_error = Some(e.clone());
+        },
+        Err(DeliveryError::Down(e)) => {
+            endpoint.failures += 1;
+            endpoint.last_error = Some(e.clone());
+            if endpoint.healthy {
+                warn!("scheduler {} marked down: {}", url, e);
+            }
+            endpoint.healthy = false;
+        },
+    }
+}
+
+/// offer the segment to the schedulers in placement order, the url of the first taking it
+async fn place(segment: &Segment, exclude: &[String]) -> Result<String, String> {
+    let mut errors = vec![];
+    for (url, client) in candidates(exclude).await {
+        match client.deliver_task(segment).await {
+            Ok(answer) => {
+                info!("segment {} taken by scheduler {}: {}", segment.task_id, url, answer);
+                record(&url, Ok(())).await;
+                return Ok(url);
+            },
+            Err(e) => {
+                warn!("scheduler {} did not take segment {}: {}", url, segment.task_id, e);
+                record(&url, Err(&e)).await;
+                errors.push(e.to_string());
+            },
+        }
+    }
+    if errors.is_empty() {
+        return Err("no scheduler to deliver to".to_string());
+    }
+    Err(errors.join("; "))
+}
+
+/// hand a segment to a scheduler, failing over to the next ones; the url of the one holding it.
+/// its proof is expected back within timeout
+pub async fn deliver(segment: &Segment, timeout: Duration) -> Result<String, String> {
+    let url = place(segment, &[]).await?;
+    ASSIGNED.lock().await.insert(segment.task_id.clone(), Assignment {
+        url: url.clone(),
+        segment: segment.clone(),
+        sent_at: Instant::now(),
+        timeout,
+        attempts: 1,
+    });
+    Ok(url)
+}
+
+pub async fn set_segment_attempts(attempts: u32) -> Result<(), String> {
+    if attempts == 0 {
+        return Err("segment_attempts must be at least 1".to_string());
+    }
+    *MAX_ATTEMPTS.lock().await = attempts;
+    Ok(())
+}
+
+pub async fn segment_attempts() -> u32 {
+    *MAX_ATTEMPTS.lock().await
+}
+
+/// the segments whose proof did not come back within their timeout
+pub async fn overdue_segments() -> Vec<Assignment> {
+    ASSIGNED.lock().await.values()
+        .filter(|assignment| assignment.sent_at.elapsed() > assignment.timeout)
+        .cloned()
+        .collect()
+}
+
+/// send an overdue segment again, to another scheduler if one takes it, else to the same;
+/// the url of the one holding it now
+pub async fn redeliver(assignment: &Assignment) -> Result<String, String> {
+    let url = match place(&assignment.segment, std::slice::from_ref(&assignment.url)).await {
+        Ok(url) => url,
+        Err(_) => place(&assignment.segment, &[]).await?,
+    };
+    //unless its proof came back meanwhile
+    if let Some(assigned) = ASSIGNED.lock().await.get_mut(&assignment.segment.task_id) {
+        assigned.url = url.clone();
+        assigned.sent_at = Instant::now();
+        assigned.attempts += 1;
+    }
+    Ok(url)
+}
+
+/// the segment's proof came back, its scheduler holds one segment less
+pub async fn segment_done(segment_id: &str) {
+    ASSIGNED.lock().await.remove(segment_id);
+    work::forget(|id| id == segment_id).await;
+}
+
+/// forget the segments of a task that failed or expired
+pub async fn release_task(task_id: &str) {
+    let prefix = format!("{}@", task_id);
+    ASSIGNED.lock().await.retain(|segment_id, _| !segment_id.starts_with(&prefix));
+    work::forget(|segment_id| segment_id.starts_with(&prefix)).await;
+}
+
+/// free segment slots summed over the healthy schedulers answering, an error when none did
+pub async fn total_capacity(project_id: &str, query: &str) -> Result<u64, String> {
+    let clients: Vec<Arc<Scheduler>> = SCHEDULERS.lock().await.iter()
+        .filter(|endpoint| endpoint.healthy)
+        .map(|endpoint| endpoint.client.clone())
+        .collect();
+    let answers = futures::future::join_all(clients.iter().map(|client| client.capacity(project_id, query))).await;
+    let mut total = None;
+    let mut errors = vec![];
+    for answer in answers {
+        match answer {
+            Ok(free) => total = Some(total.unwrap_or(0) + free),
+            Err(e) => errors.push(e),
+        }
+    }
+    total.ok_or(if errors.is_empty() { "no healthy scheduler".to_string() } else { errors.join("; ") })
+}
+
+/// ping every scheduler, then move the segments held by the ones down to others
+async fn check_schedulers() {
+    let clients: Vec<(String, Arc<Scheduler>)> = SCHEDULERS.lock().await.iter()
+        .map(|endpoint| (endpoint.url.clone(), endpoint.client.clone()))
+        .collect();
+    for (url, client) in clients {
+        let result = client.ping().await;
+        let mut endpoints = SCHEDULERS.lock().await;
+        if let Some(endpoint) = endpoints.iter_mut().find(|endpoint| endpoint.url == url) {
+            match result {
+                Ok(()) if !endpoint.healthy => {
+                    info!("scheduler {} answers again", url);
+                    endpoint.healthy = true;
+                },
+                Err(e) => {
+                    if endpoint.healthy {
+                        warn!("scheduler {} marked down: {}", url, e);
+                    }
+                    endpoint.healthy = false;
+                    endpoint.last_error = Some(e);
+                },
+                _ => (),
+            }
+        }
+    }
+    redispatch_stranded().await;
+}
+
+async fn redispatch_stranded() {
+    let down: Vec<String> = SCHEDULERS.lock().await.iter()
+        .filter(|endpoint| !endpoint.healthy)
+        .map(|endpoint| endpoint.url.clone())
+        .collect();
+    if down.is_empty() {
+        return;
+    }
+    let stranded: Vec<(String, Segment)> = ASSIGNED.lock().await.values()
+        .filter(|assignment| down.contains(&assignment.url))
+        .map(|assignment| (assignment.url.clone(), assignment.segment.clone()))
+        .collect();
+    for (from, segment) in stranded {
+        match place(&segment, &down).await {
+            Ok(to) => {
+                info!("segment {} re-dispatched from {} to {}", segment.task_id, from, to);
+                //unless its proof came back meanwhile
+                if let Some(assigned) = ASSIGNED.lock().await.get_mut(&segment.task_id) {
+                    assigned.url = to;
+                    assigned.sent_at = Instant::now();
+                }
+            },
+            Err(e) => warn!("segment {} stays with {}, no other scheduler took it: {}", segment.task_id, from, e),
+        }
+    }
+}
+
+pub async fn track_schedulers() {
+    loop {
+        time::sleep(Duration::from_secs(SCHEDULER_HEALTH_SECS)).await;
+        check_schedulers().await;
+    }
+}
+
+pub async fn scheduler_status() -> Vec<Value> {
+    let loads = in_flight().await;
+    SCHEDULERS.lock().await.iter().map(|endpoint| {
+        let mut status = serde_json::to_value(endpoint).unwrap_or_default();
+        status["in_flight"] = (*loads.get(&endpoint.url).unwrap_or(&0)).into();
+        status
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::serve_http;
+
+    fn endpoints(specs: &[&str]) -> Vec<SchedulerEndpoint> {
+        specs.iter().map(|spec| SchedulerEndpoint::from_spec(spec).unwrap()).collect()
+    }
+
+    fn picks(endpoints: &mut [SchedulerEndpoint], placement: Placement, loads: &HashMap<String, usize>, exclude: &[String], rounds: usize) -> Vec<usize> {
+        (0..rounds).map(|_| placement_order(endpoints, placement, loads, exclude)[0]).collect()
+    }
+
+    #[test]
+    fn smooth_weighted_round_robin() {
+        let mut endpoints = endpoints(&["http://a:1,5", "http://b:1", "http://c:1"]);
+        let none = HashMap::new();
+        //spread out, not five in a row
+        assert_eq!(picks(&mut endpoints, Placement::RoundRobin, &none, &[], 7), vec![0, 0, 1, 0, 2, 0, 0]);
+        assert_eq!(picks(&mut endpoints, Placement::RoundRobin, &none, &[], 7), vec![0, 0, 1, 0, 2, 0, 0]);
+        //the rest of the order is the fallback for a refusal
+        assert_eq!(placement_order(&mut endpoints, Placement::RoundRobin, &none, &[]).len(), 3);
+    }
+
+    #[test]
+    fn down_schedulers_only_when_none_is_up() {
+        let mut endpoints = endpoints(&["http://a:1,2", "http://b:1", "http://c:1"]);
+        let none = HashMap::new();
+        endpoints[0].healthy = false;
+        let mut seen = picks(&mut endpoints, Placement::RoundRobin, &none, &[], 4);
+        seen.sort();
+        assert_eq!(seen, vec![1, 1, 2, 2]);
+        assert_eq!(placement_order(&mut endpoints, Placement::LeastLoaded, &none, &["http://b:1".to_string()]), vec![2]);
+        for endpoint in endpoints.iter_mut() {
+            endpoint.healthy = false;
+        }
+        assert_eq!(placement_order(&mut endpoints, Placement::RoundRobin, &none, &["http://b:1".to_string()]), vec![0, 2]);
+    }
+
+    #[test]
+    fn least_loaded_by_weight() {
+        let mut endpoints = endpoints(&["http://a:1,2", "http://b:1", "http://c:1"]);
+        let loads = HashMap::from([("http://a:1".to_string(), 4), ("http://b:1".to_string(), 1), ("http://c:1".to_string(), 3)]);
+        assert_eq!(placement_order(&mut endpoints, Placement::LeastLoaded, &loads, &[]), vec![1, 0, 2]);
+        assert_eq!(placement_order(&mut endpoints, Placement::LeastLoaded, &HashMap::new(), &[]), vec![0, 1, 2]);
+    }
+
+    fn segment() -> Segment {
+        Segment { project_id: "1".to_string(), task_id: format!("{}@0", "d7".repeat(32)), instance: "0102".to_string(), degree: "1".to_string() }
+    }
+
+    #[tokio::test]
+    async fn json_rpc_scheduler_answers() {
+        let url = serve_http(|_, request| {
+            let reply = |result: Value| json!({"jsonrpc": "2.0", "id": request["id"], "result": result});
+            match request["method"].as_str().unwrap_or_default() {
+                DELIVER_METHOD if request["params"] == json!(["1", format!("{}@0", "d7".repeat(32)), "0102", "1"]) => (200, reply(json!("accepted"))),
+                "FreeSlots" => (200, reply(json!("7"))),
+                "Busy" => (200, json!({"jsonrpc": "2.0", "id": request["id"], "error": {"code": -1, "message": "no prover"}})),
+                "Broken" => (502, json!("bad gateway")),
+                _ => (200, reply(json!({"slots": 1}))),
+            }
+        }).await;
+        let scheduler = Scheduler::from_spec(&format!("jsonrpc:{}", url)).unwrap();
+        assert_eq!((scheduler.protocol(), scheduler.url()), ("jsonrpc", url.as_str()));
+        assert_eq!(scheduler.deliver_task(&segment()).await.unwrap(), json!("accepted"));
+        assert_eq!(scheduler.capacity("1", "FreeSlots").await, Ok(7));
+        assert!(scheduler.capacity("1", "Other").await.unwrap_err().contains("unexpected capacity"));
+        let Scheduler::JsonRpc(client) = &scheduler else { unreachable!() };
+        assert!(matches!(client.rpc("Busy", json!([])).await, Err(DeliveryError::Refused(e)) if e.contains("no prover")));
+        assert!(matches!(client.rpc("Broken", json!([])).await, Err(DeliveryError::Down(e)) if e.contains("502")));
+        assert!(scheduler.ping().await.is_ok());
+
+        let dead = Scheduler::from_spec("http://127.0.0.1:9").unwrap();
+        assert!(matches!(dead.deliver_task(&segment()).await, Err(DeliveryError::Down(_))));
+        assert!(dead.ping().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rest_scheduler_answers() {
+        let url = serve_http(|line, body| match line.split(' ').nth(1).unwrap_or_default() {
+            "/tasks" if body["task_id"] == json!(format!("{}@0", "d7".repeat(32))) => (201, json!({"queued": true})),
+            "/tasks" => (409, json!("duplicate task")),
+            "/capacity?project_id=1" => (200, json!(3)),
+            "/health" => (503, json!("starting")),
+            _ => (404, json!("not found")),
+        }).await;
+        let scheduler = Scheduler::from_spec(&format!("rest:{}/", url)).unwrap();
+        assert_eq!((scheduler.protocol(), scheduler.url()), ("rest", url.as_str()));
+        assert_eq!(scheduler.deliver_task(&segment()).await.unwrap(), json!({"queued": true}));
+        let other = Segment { task_id: format!("{}@1", "d7".repeat(32)), ..segment() };
+        assert!(matches!(scheduler.deliver_task(&other).await, Err(DeliveryError::Refused(e)) if e.contains("409") && e.contains("duplicate task")));
+        assert_eq!(scheduler.capacity("1", "/capacity").await, Ok(3));
+        assert!(scheduler.capacity("2", "capacity").await.unwrap_err().contains("404"));
+        assert!(scheduler.ping().await.unwrap_err().contains("503"));
+    }
+
+    /// the schedulers and the segments they hold are process wide, one test drives them
+    #[tokio::test]
+    async fn overdue_segments_sent_again_elsewhere() {
+        let scheduler = |refuse: &'static str| serve_http(move |_, request| match request["params"][1].as_str() {
+            Some(task_id) if task_id.ends_with(refuse) => (200, json!({"jsonrpc": "2.0", "id": request["id"], "error": {"code": -1, "message": "full"}})),
+            _ => (200, json!({"jsonrpc": "2.0", "id": request["id"], "result": "accepted"})),
+        });
+        let (a, b) = (scheduler("none").await, scheduler("@1").await);
+        set_schedulers(vec![a.clone(), b.clone()], Placement::RoundRobin).await.unwrap();
+        assert!(set_segment_attempts(0).await.is_err());
+        assert_eq!(segment_attempts().await, SEGMENT_ATTEMPTS);
+
+        //a segment's share of the liability window, not less than the minimum
+        let timeout = crate::chain::segment_timeout(3600);
+        assert_eq!(timeout, Duration::from_secs(900));
+        assert_eq!(crate::chain::segment_timeout(100), Duration::from_secs(crate::chain::SEGMENT_TIMEOUT_MIN_SECS));
+
+        let task_key = "d8".repeat(32);
+        let held = |split_id: u32, sent_at: Instant| Assignment {
+            url: a.clone(),
+            segment: Segment { task_id: format!("{}@{}", task_key, split_id), ..segment() },
+            sent_at,
+            timeout,
+            attempts: 1,
+        };
+        let late = Instant::now() - timeout - Duration::from_secs(1);
+        for assignment in [held(0, late), held(1, late), held(2, Instant::now())] {
+            ASSIGNED.lock().await.insert(assignment.segment.task_id.clone(), assignment);
+        }
+        let mut overdue: Vec<Assignment> = overdue_segments().await.into_iter()
+            .filter(|assignment| assignment.segment.task_id.starts_with(&task_key))
+            .collect();
+        overdue.sort_by(|x, y| x.segment.task_id.cmp(&y.segment.task_id));
+        assert_eq!(overdue.iter().map(|assignment| assignment.segment.task_id.clone()).collect::<Vec<_>>(),
+            vec![format!("{}@0", task_key), format!("{}@1", task_key)]);
+
+        //to another scheduler, back to the same when the others refuse
+        assert_eq!(redeliver(&overdue[0]).await, Ok(b.clone()));
+        assert_eq!(redeliver(&overdue[1]).await, Ok(a.clone()));
+        {
+            let assigned = ASSIGNED.lock().await;
+            let (first, second) = (&assigned[&format!("{}@0", task_key)], &assigned[&format!("{}@1", task_key)]);
+            assert_eq!((first.url.as_str(), first.attempts, second.url.as_str(), second.attempts), (b.as_str(), 2, a.as_str(), 2));
+        }
+        assert!(overdue_segments().await.iter().all(|assignment| !assignment.segment.task_id.starts_with(&task_key)));
+
+        segment_done(&format!("{}@0", task_key)).await;
+        release_task(&task_key).await;
+        assert!(ASSIGNED.lock().await.keys().all(|segment_id| !segment_id.starts_with(&task_key)));
+    }
+}
@@ -8,6 +8,38 @@ diesel::table! {
         status -> Varchar,
         create_time -> Timestamp,
         update_time -> Timestamp,
+        instance -> Varchar,
+        requester -> Varchar,
+        prover -> Varchar,
+        reward_token -> Varchar,
+        reward_amount -> Varchar,
+        liability_token -> Varchar,
+        liability_amount -> Varchar,
+        liability_window -> Int8,
+        deadline -> Nullable<Timestamp>,
+        submit_tx_hash -> Nullable<Varchar>,
+        prove_tx_hash -> Nullable<Varchar>,
+        attempts -> Int4,
+    }
+}
+
+diesel::table! {
+    quotes (id) {
+        id -> Int8,
+        quote_hash -> Varchar,
+        prover -> Varchar,
+        instance -> Varchar,
+        reward_token -> Varchar,
+        reward_amount -> Varchar,
+        liability_token -> Varchar,
+        liability_amount -> Varchar,
+        liability_window -> Int8,
+        expiry -> Int8,
+        signature -> Varchar,
+        status -> Varchar,
+        task_id -> Nullable<Varchar>,
+        create_time -> Timestamp,
+        update_time -> Timestamp,
     }
 }
 
@@ -21,10 +53,27 @@ diesel::table! {
         status -> Varchar,
         create_time -> Timestamp,
         update_time -> Timestamp,
+        attempts -> Int4,
+        dispatch_time -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    task_events (id) {
+        id -> Int8,
+        project_id -> Varchar,
+        task_id -> Varchar,
+        task_split_id -> Nullable<Varchar>,
+        from_status -> Nullable<Varchar>,
+        to_status -> Varchar,
+        detail -> Nullable<Varchar>,
+        create_time -> Timestamp,
     }
 }
 
 diesel::allow_tables_to_appear_in_same_query!(
     big_proofs,
+    quotes,
     small_proofs,
+    task_events,
 );
@@ -16,33 +16,24 @@
 
 use jsonrpc_http_server::jsonrpc_core::{IoHandler, Params, Value};
 use jsonrpc_http_server::ServerBuilder;
-use log::info;
+use log::*;
 use serde_derive::{Deserialize,Serialize};
-use web3::ethabi::{Token, encode, Address};
-use web3::signing::keccak256;
 
-use ethers_signers::{Wallet,Signer};
-
-use crate::chain::{get_current_block_num, ProofMessage, PROOF_MSG_QUEUE, process_proof_data, PRIV_KEY};
-use std::str::FromStr;
-use ethereum_private_key_to_address::PrivateKey;
+use crate::chain::{ProofMessage, PROOF_MSG_QUEUE, process_proof_data, proof_received, parse_proof_id, check_proof, CONTRACT};
+use crate::chain::{OnChainTask, parse_task_key, read_proof_status, read_task_status, read_owner, replay_events};
+use crate::admission::admit_quote;
+use crate::pricing::{price_quote, PricingDecision};
+use crate::provider::provider_status;
+use crate::scheduler::scheduler_status;
+use crate::work::{get_work, submit_work};
+use crate::quote_registry::{register_quote, list_quotes, QuoteStatus};
+use crate::quote::{quote_expiry_block, TaskResponse, sign_quote, verify_quote, parse_address, chain_id};
+#[cfg(feature = "DB")]
 use crate::db::*;
 use crate::models::NewSmallProof;
-use crate::chain::{SEG_NUM, update_proof_response};
-
+#[cfg(feature = "local")]
+use crate::chain::update_proof_response;
 
-#[derive(Debug, Serialize, Deserialize,Default)]
-struct TaskResponse {
-    pub prover: String,
-    pub instance: String,
-    pub reward_token: String,
-    pub reward: u64,
-    pub liability_window: u64,
-    pub liability_token: String,
-    pub liability: u64,
-    pub expiry: u64,
-    pub signature: String,
-}
 
 #[derive(Debug, Serialize, Deserialize,Default)]
 pub struct ProofResponse {
@@ -50,9 +41,20 @@ pub struct ProofResponse {
     pub project_id: String,
     pub status: String,
     pub small_proofs: Vec<NewSmallProof>,
+    #[serde(default)]
+    pub on_chain: Option<OnChainTask>,    //readProofStatus, None when the chain could not be read
 }
 
 pub async fn start_rpc_server(addr:String) -> jsonrpc_http_server::Server {
+    info!("start the server on :{}",addr.clone());
+    ServerBuilder::new(rpc_handler())
+        .threads(4)
+        .start_http(&addr.parse().unwrap())
+        .unwrap()
+}
+
+/// the relayer's JSON-RPC methods
+fn rpc_handler() -> IoHandler {
     let mut io = IoHandler::default();
 
     io.add_method("ReceiveTask", |params: Params| async {
@@ -63,7 +65,7 @@ pub async fn start_rpc_server(addr:String) -> jsonrpc_http_server::Server {
                 return Ok(Value::String("parameter invalid".to_string()))
             },
         };
-        if req_input.len() != 6 {
+        if req_input.len() != 6 && req_input.len() != 7 {
             return Ok(Value::String("parameter invalid".to_string()))
         }
 
@@ -109,49 +111,183 @@ pub async fn start_rpc_server(addr:String) -> jsonrpc_http_server::Server {
             return Ok(Value::String("parameter invalid".to_string()))
         };
 
-        let priv_key = PRIV_KEY.lock().await;
-        let key = (*priv_key).clone();
-        let private_key = PrivateKey::from_str(key.as_str()).unwrap();
+        //contract the quote is for, the relayer's contract by default
+        let contract = match req_input.get(6) {
+            Some(Value::String(func_input)) => func_input.clone(),
+            Some(_) => return Ok(Value::String("parameter invalid".to_string())),
+            None => CONTRACT.lock().await.clone(),
+        };
+        let contract = match parse_address(&contract) {
+            Ok(r) => r,
+            Err(_) => return Ok(Value::String("parameter invalid".to_string())),
+        };
+        if parse_address(&reward_token).is_err() || parse_address(&liability_token).is_err() {
+            return Ok(Value::String("parameter invalid".to_string()))
+        }
 
-        let mut res:TaskResponse=TaskResponse::default();
-        res.prover=private_key.address();
-        res.instance=task_instance.clone();
-        res.reward_token=reward_token.clone();
-        res.reward=reward;
-        res.liability_window=liability_window;
-        res.liability_token=liability_token.clone();
-        res.liability=liability;
-        let block_num = get_current_block_num().unwrap();
-        res.expiry=block_num+2000;   //expire time + 2000 block time
+        if let Err(refusal) = admit_quote(liability_window).await {
+            info!("refuse quote for instance {}: {}", task_instance, refusal.reason);
+            return Ok(Value::String(format!("busy, retry after {}s: {}", refusal.retry_after, refusal.reason)))
+        }
 
-        //encode ABI function array
-        let mut data_vec:Vec<Token>=Vec::new();
+        let expiry = match quote_expiry_block().await {
+            Ok(r) => r,
+            Err(e) => {
+                error!("quote expiry unavailable: {}", e);
+                return Ok(Value::String("block number unavailable".to_string()))
+            },
+        };
+        let mut res = TaskResponse {
+            instance: task_instance.clone(),
+            reward_token: reward_token.clone(),
+            reward,
+            liability_window,
+            liability_token: liability_token.clone(),
+            liability,
+            ..Default::default()
+        };
+        res.expiry=expiry;
 
-        let instance_bytes = web3::types::Bytes::from(task_instance.clone());
-        let reward_token_addr = Address::from_str(reward_token.clone().as_str()).unwrap();
-        let liability_token_addr = Address::from_str(liability_token.clone().as_str()).unwrap();
+        match price_quote(&mut res).await {
+            PricingDecision::Accept => (),
+            PricingDecision::Counter(reasons) => {
+                info!("counter quote for instance {}: {:?}", task_instance, reasons);
+                res.counter = reasons;
+            },
+            PricingDecision::Reject(reasons) => {
+                info!("reject quote for instance {}: {:?}", task_instance, reasons);
+                return Ok(Value::String(format!("rejected: {}", reasons.join("; "))))
+            },
+        }
 
-        data_vec.push(Token::Bytes(instance_bytes.0));
-        data_vec.push(Token::Address(reward_token_addr));
-        data_vec.push(Token::Uint(reward.clone().into()));
-        data_vec.push(Token::Address(liability_token_addr.clone()));
-        data_vec.push(Token::Uint(liability.clone().into()));
-        data_vec.push(Token::Uint(res.expiry.into()));
-        data_vec.push(Token::Uint(liability_window.into()));
+        //legacy: signature(keccak256(abi.encode(instance, rewardToken, reward, liabilityToken, liability, expiry, deadlineWindow)))
+        //eip712: signature over the Quote struct in the contract's domain
+        let quote_hash = match sign_quote(&mut res, &contract).await {
+            Ok(r) => r,
+            Err(e) => {
+                error!("sign quote failed: {}", e);
+                return Ok(Value::String("sign quote failed".to_string()))
+            },
+        };
+        if let Err(e) = register_quote(&res, quote_hash).await {
+            error!("register quote failed: {}", e);
+            return Ok(Value::String("register quote failed".to_string()))
+        }
 
-        info!("keccak256 the content is {:?}",data_vec);
+        Ok(Value::String(serde_json::to_string(&res).unwrap()))
+    });
 
-        //signature(keccak256(abi.encode(instance, rewardToken, reward, liabilityToken, liability, expiry, deadlineWindow)))
+    io.add_method("demo/QueryQuotes", |params: Params| async {
+        info!("****** QueryQuotes msg ******");
+        let req_input: Vec<Value> = match params.parse(){
+            Ok(r) => r,
+            Err(_) => {
+                return Ok(Value::String("parameter invalid".to_string()))
+            },
+        };
+        if req_input.len() > 1 {
+            return Ok(Value::String("parameter invalid".to_string()))
+        }
 
-        let encode_sig_msg=encode(&data_vec);
-        let keccak_hash = keccak256(&encode_sig_msg);
+        //quote status, outstanding by default
+        let status = match req_input.first() {
+            Some(Value::String(func_input)) => match QuoteStatus::from_str(func_input) {
+                Some(r) => r,
+                None => return Ok(Value::String("parameter invalid".to_string())),
+            },
+            Some(_) => return Ok(Value::String("parameter invalid".to_string())),
+            None => QuoteStatus::Outstanding,
+        };
+        match list_quotes(status).await {
+            Ok(quotes) => Ok(Value::String(quotes.to_string())),
+            Err(e) => {
+                error!("Error listing quotes: {}", e);
+                Ok(Value::String("query quotes failed".to_string()))
+            },
+        }
+    });
 
-        let wallet:Wallet=key.parse().unwrap();
-        let signature = wallet.sign_message(keccak_hash.clone()).to_vec();
-        info!("keccak hash is:{},signature is {}",hex::encode(keccak_hash.clone()),hex::encode(signature.clone()));        
-        res.signature=hex::encode(signature.clone());
+    io.add_method("demo/QueryProviders", |_params: Params| async {
+        info!("****** QueryProviders msg ******");
+        let providers = provider_status().await;
+        Ok(Value::String(serde_json::to_string(&providers).unwrap()))
+    });
 
-        Ok(Value::String(serde_json::to_string(&res).unwrap()))
+    io.add_method("demo/QuerySchedulers", |_params: Params| async {
+        info!("****** QuerySchedulers msg ******");
+        let schedulers = scheduler_status().await;
+        Ok(Value::String(serde_json::to_string(&schedulers).unwrap()))
+    });
+
+    io.add_method("demo/Replay", |params: Params| async {
+        info!("****** Replay msg ******");
+        let req_input: Vec<Value> = match params.parse(){
+            Ok(r) => r,
+            Err(_) => {
+                return Ok(Value::String("parameter invalid".to_string()))
+            },
+        };
+        //first block, last block, only report (false by default)
+        let (from, to, dry_run) = match req_input.as_slice() {
+            [Value::Number(from), Value::Number(to)] => (from.as_u64(), to.as_u64(), false),
+            [Value::Number(from), Value::Number(to), Value::Bool(dry_run)] => (from.as_u64(), to.as_u64(), *dry_run),
+            _ => return Ok(Value::String("parameter invalid".to_string())),
+        };
+        let (from, to) = match (from, to) {
+            (Some(from), Some(to)) => (from, to),
+            _ => return Ok(Value::String("parameter invalid".to_string())),
+        };
+        match replay_events(from, to, dry_run).await {
+            Ok(tasks) => {
+                let known = tasks.iter().filter(|task| task.known).count();
+                let enqueued = tasks.iter().filter(|task| task.enqueued).count();
+                info!("replay of {} to {} found {} tasks, {} already known, {} enqueued", from, to, tasks.len(), known, enqueued);
+                Ok(Value::String(serde_json::to_string(&tasks).unwrap()))
+            },
+            Err(e) => {
+                error!("replay {} to {}: {}", from, to, e);
+                Ok(Value::String(e))
+            },
+        }
+    });
+
+    io.add_method("demo/VerifyQuote", |params: Params| async {
+        info!("****** VerifyQuote msg ******");
+        let req_input: Vec<Value> = match params.parse(){
+            Ok(r) => r,
+            Err(_) => {
+                return Ok(Value::String("parameter invalid".to_string()))
+            },
+        };
+        if req_input.is_empty() || req_input.len() > 2 {
+            return Ok(Value::String("parameter invalid".to_string()))
+        }
+
+        //quote as returned by ReceiveTask
+        let quote: TaskResponse = match &req_input[0] {
+            Value::String(func_input) => match serde_json::from_str(func_input) {
+                Ok(r) => r,
+                Err(_) => return Ok(Value::String("parameter invalid".to_string())),
+            },
+            _ => return Ok(Value::String("parameter invalid".to_string())),
+        };
+        let contract = match req_input.get(1) {
+            Some(Value::String(func_input)) => func_input.clone(),
+            Some(_) => return Ok(Value::String("parameter invalid".to_string())),
+            None => CONTRACT.lock().await.clone(),
+        };
+        let contract = match parse_address(&contract) {
+            Ok(r) => r,
+            Err(_) => return Ok(Value::String("parameter invalid".to_string())),
+        };
+        let chain_id = match chain_id().await {
+            Ok(r) => r,
+            Err(e) => return Ok(Value::String(e)),
+        };
+        match verify_quote(&quote, chain_id, &contract) {
+            Ok(signer) => Ok(Value::String(format!("{:?}", signer))),
+            Err(e) => Ok(Value::String(e)),
+        }
     });
 
     io.add_method("demo/SendProofBack", |params: Params| async {
@@ -185,11 +321,69 @@ pub async fn start_rpc_server(addr:String) -> jsonrpc_http_server::Server {
         }else{
             return Ok(Value::String("parameter invalid".to_string()))
         }; 
-        receive_proof(task_id, zkproof, degree).await;  
+        if let Err(e) = parse_proof_id(&task_id).and_then(|_| check_proof(&zkproof)) {
+            warn!("SendProofBack rejected: {}", e);
+            return Ok(Value::String("parameter invalid".to_string()))
+        }
+        //a duplicate is acknowledged like the first one so the scheduler stops resending it
+        receive_proof(task_id, zkproof, degree).await;
         Ok(Value::String("success".to_string()))
         
     }); 
 
+    io.add_method("demo/GetWork", |params: Params| async {
+        info!("****** GetWork msg ******");
+        let req_input: Vec<Value> = match params.parse(){
+            Ok(r) => r,
+            Err(_) => {
+                return Ok(Value::String("parameter invalid".to_string()))
+            },
+        };
+        //worker name, segments wanted (1 by default)
+        let (worker, max) = match req_input.as_slice() {
+            [Value::String(worker)] => (worker.clone(), 1),
+            [Value::String(worker), Value::Number(max)] => match max.as_u64() {
+                Some(max) if max > 0 => (worker.clone(), max as usize),
+                _ => return Ok(Value::String("parameter invalid".to_string())),
+            },
+            _ => return Ok(Value::String("parameter invalid".to_string())),
+        };
+        if worker.is_empty() {
+            return Ok(Value::String("parameter invalid".to_string()))
+        }
+        let leased = get_work(&worker, max).await;
+        Ok(Value::String(serde_json::to_string(&leased).unwrap()))
+    });
+
+    io.add_method("demo/SubmitWork", |params: Params| async {
+        info!("****** SubmitWork msg ******");
+        let req_input: Vec<Value> = match params.parse(){
+            Ok(r) => r,
+            Err(_) => {
+                return Ok(Value::String("parameter invalid".to_string()))
+            },
+        };
+        //lease id, segment id as leased, zkproof
+        let (lease_id, task_id, zkproof) = match req_input.as_slice() {
+            [Value::String(lease_id), Value::String(task_id), Value::String(zkproof)] => (lease_id.clone(), task_id.clone(), zkproof.clone()),
+            _ => return Ok(Value::String("parameter invalid".to_string())),
+        };
+        if let Err(e) = parse_proof_id(&task_id).and_then(|_| check_proof(&zkproof)) {
+            warn!("SubmitWork rejected: {}", e);
+            return Ok(Value::String("parameter invalid".to_string()))
+        }
+        let degree = match submit_work(&lease_id, &task_id).await {
+            Ok(degree) => degree,
+            Err(e) => {
+                warn!("SubmitWork rejected: {}", e);
+                return Ok(Value::String(e))
+            },
+        };
+        //resent proofs are acknowledged like SendProofBack
+        receive_proof(task_id, zkproof, degree).await;
+        Ok(Value::String("success".to_string()))
+    });
+
     io.add_method("demo/QueryProofs", |params: Params| async {
         info!("****** QueryProofs msg ******");
         let req_input: Vec<Value> = match params.parse(){
@@ -218,51 +412,242 @@ pub async fn start_rpc_server(addr:String) -> jsonrpc_http_server::Server {
                 Ok(proof_response) => {
                     res = proof_response;
                 },
-                Err(e) => eprintln!("Error updating proof response: {}", e),
+                Err(e) => error!("Error updating proof response: {}", e),
             }
         }
 
         #[cfg(feature = "DB")]
         {
-            // // 从db.rs中根据上面的task_id参数和"demo"作为project_id, 调用get_big_proof_status函数和get_small_proof_status_and_percentage函数获取big_proof和对应small_proof
-            // res.big_proof = get_big_proof_status("demo", task_id);
-            // // 循环SEG_NUM 次，以“demo”作为project_id, task_id作为task_id, i作为task_split_id, 调用get_small_proof_status_and_percentage函数获取small_proof
-            // for i in 0..SEG_NUM {
-            //     res.small_proof.push(get_small_proof_status_and_percentage("demo", task_id, i.to_string()));
-            // }
+            match get_proof_response("demo", &task_id).await {
+                Ok(proof_response) => {
+                    res = proof_response;
+                },
+                Err(e) => error!("Error getting proof response: {}", e),
+            }
+        }
+
+        //whole tasks only, segments are not on chain
+        if let Ok(task_key) = parse_task_key(&task_id) {
+            match read_proof_status(task_key).await {
+                Ok(on_chain) => res.on_chain = Some(on_chain),
+                Err(e) => warn!("read on-chain status of task {}: {}", task_id, e),
+            }
         }
 
         Ok(Value::String(serde_json::to_string(&res).unwrap()))
         
     });
-    info!("start the server on :{}",addr.clone());
-    let server = ServerBuilder::new(io)
-        .threads(4)
-        .start_http(&addr.parse().unwrap())
-        .unwrap();
-    
-    server
+
+    io.add_method("chain/TaskStatus", |params: Params| async {
+        info!("****** chain TaskStatus msg ******");
+        let task_key = match task_key_param(params) {
+            Some(r) => r,
+            None => return Ok(Value::String("parameter invalid".to_string())),
+        };
+        match read_task_status(task_key).await {
+            Ok(task) => Ok(Value::String(serde_json::to_string(&task).unwrap())),
+            Err(e) => Ok(Value::String(e)),
+        }
+    });
+
+    io.add_method("chain/ProofStatus", |params: Params| async {
+        info!("****** chain ProofStatus msg ******");
+        let task_key = match task_key_param(params) {
+            Some(r) => r,
+            None => return Ok(Value::String("parameter invalid".to_string())),
+        };
+        match read_proof_status(task_key).await {
+            Ok(task) => Ok(Value::String(serde_json::to_string(&task).unwrap())),
+            Err(e) => Ok(Value::String(e)),
+        }
+    });
+
+    io.add_method("chain/Owner", |_params: Params| async {
+        info!("****** chain Owner msg ******");
+        match read_owner().await {
+            Ok(owner) => Ok(Value::String(format!("{:?}", owner))),
+            Err(e) => Ok(Value::String(e)),
+        }
+    });
+    #[cfg(feature = "DB")]
+    io.add_method("demo/QueryTaskEvents", |params: Params| async {
+        info!("****** QueryTaskEvents msg ******");
+        let req_input: Vec<Value> = match params.parse(){
+            Ok(r) => r,
+            Err(_) => {
+                return Ok(Value::String("parameter invalid".to_string()))
+            },
+        };
+        if req_input.len() != 1 {
+            return Ok(Value::String("parameter invalid".to_string()))
+        }
+
+        //task param
+        let task_id = if  let Value::String(func_input)=req_input[0].clone(){
+            func_input
+        }else{
+            return Ok(Value::String("parameter invalid".to_string()))
+        };
+
+        match get_task_events("demo", &task_id).await {
+            Ok(events) => Ok(Value::String(serde_json::to_string(&events).unwrap())),
+            Err(e) => {
+                error!("{}", e);
+                Ok(Value::String("query failed".to_string()))
+            },
+        }
+    });
+
+    io
 }
 
 
-pub async fn receive_proof(task_id:String,proof:String,degree:String){
+/// the single task key parameter of the chain/ methods
+fn task_key_param(params: Params) -> Option<[u8; 32]> {
+    let req_input: Vec<Value> = params.parse().ok()?;
+    match req_input.as_slice() {
+        [Value::String(task_key)] => parse_task_key(task_key).ok(),
+        _ => None,
+    }
+}
+
+/// queue a proof from the scheduler, false when the same task or segment proof is queued or taken already
+pub async fn receive_proof(task_id:String,proof:String,degree:String) -> bool {
+    if proof_queued(&task_id).await || proof_received(&task_id).await {
+        info!("duplicate proof of {:?}, acknowledged and ignored", task_id);
+        return false;
+    }
+    //checked again under the lock, for the same proof coming in twice at once
+    let mut queue = PROOF_MSG_QUEUE.lock().await;
+    if queue.iter().any(|msg| msg.task_id == task_id) {
+        info!("duplicate proof of {:?}, acknowledged and ignored", task_id);
+        return false;
+    }
     info!("receive scheduler proof info of {:?},data is {:?},add to queue",task_id,proof);
     let msg:ProofMessage=ProofMessage { task_id, proof, degree };
-    let mut queue = PROOF_MSG_QUEUE.lock().await;
     queue.push_back(msg);
+    true
+}
+
+/// whether the proof is waiting in the queue or being processed
+async fn proof_queued(task_id: &str) -> bool {
+    PROOF_MSG_QUEUE.lock().await.iter().any(|msg| msg.task_id == task_id)
 }
 
+/// process the queued proofs in order; each stays at the front of the queue while it is processed,
+/// so a duplicate sent meanwhile is recognized, but the lock is not held over the proof submission
 pub async fn loop_proof_data() -> web3::Result<()> {
-    let mut queue = PROOF_MSG_QUEUE.lock().await;
-    while queue.len() > 0 {
-        info!("start to process the proof data of len : {}",queue.len());
-        let item = queue.pop_front().unwrap();
+    loop {
+        let item = {
+            let queue = PROOF_MSG_QUEUE.lock().await;
+            match queue.front() {
+                Some(item) => {
+                    info!("start to process the proof data of len : {}",queue.len());
+                    item.clone()
+                },
+                None => break,
+            }
+        };
         process_proof_data(&item).await;
+        PROOF_MSG_QUEUE.lock().await.pop_front();
     }
     Ok(())
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use web3::types::{Bytes, U256};
+    use crate::chain::TaskSubmittedEvent;
+    use crate::quote_registry::match_task_submitted;
+
+    /// call one method of the handler, answering its result
+    async fn call(io: &IoHandler, method: &str, params: Value) -> Value {
+        let request = serde_json::json!({"jsonrpc": "2.0", "method": method, "params": params, "id": 1});
+        let response = io.handle_request(&request.to_string()).await.unwrap();
+        serde_json::from_str::<Value>(&response).unwrap()["result"].clone()
+    }
 
+    #[tokio::test]
+    async fn proofs_checked_before_queueing() {
+        let io = rpc_handler();
+        let task_key = "c3".repeat(32);
+        for (task_id, proof) in [(format!("{}@0", &task_key[2..]), "ab"), (format!("{}@9", task_key), "ab"),
+            (format!("{}@x", task_key), "ab"), (format!("0x{}", task_key), "ab"), (format!("{}@0", task_key), "zz"),
+            (format!("{}@0", task_key), "")] {
+            assert_eq!(call(&io, "demo/SendProofBack", serde_json::json!([task_id, proof, "1"])).await, "parameter invalid", "{}", task_id);
+            assert_eq!(call(&io, "demo/SubmitWork", serde_json::json!(["lease", task_id, proof])).await, "parameter invalid", "{}", task_id);
+        }
+        assert!(!proof_queued(&format!("{}@0", task_key)).await);
+
+        let task_id = format!("{}@1", task_key);
+        assert_eq!(call(&io, "demo/SendProofBack", serde_json::json!([task_id, "0xab", "1"])).await, "success");
+        assert!(proof_queued(&task_id).await);
+        //a resent proof is acknowledged but queued once
+        assert_eq!(call(&io, "demo/SendProofBack", serde_json::json!([task_id, "0xab", "1"])).await, "success");
+        assert_eq!(PROOF_MSG_QUEUE.lock().await.iter().filter(|msg| msg.task_id == task_id).count(), 1);
+        PROOF_MSG_QUEUE.lock().await.retain(|msg| msg.task_id != task_id);
+    }
 
+    #[tokio::test]
+    async fn query_quotes_by_status() {
+        #[cfg(feature = "DB")]
+        init_test_pool();
+        let io = rpc_handler();
+        let quote = TaskResponse {
+            prover: "0x00000000000000000000000000000000000000b1".to_string(),
+            instance: "query-quotes".to_string(),
+            reward_token: "0x00000000000000000000000000000000000000b2".to_string(),
+            reward: 7,
+            liability_window: 60,
+            liability_token: "0x00000000000000000000000000000000000000b3".to_string(),
+            liability: 3,
+            expiry: 1_000_000,
+            ..Default::default()
+        };
+        register_quote(&quote, [0x36; 32]).await.unwrap();
+        let quote_hash = hex::encode([0x36; 32]);
+        let listed = |answer: Value| -> Vec<Value> {
+            serde_json::from_str::<Vec<Value>>(answer.as_str().unwrap()).unwrap().into_iter()
+                .filter(|q| q["quote_hash"] == quote_hash.as_str())
+                .collect()
+        };
+        assert_eq!(listed(call(&io, "demo/QueryQuotes", serde_json::json!([])).await).len(), 1);
+        assert!(listed(call(&io, "demo/QueryQuotes", serde_json::json!(["matched"])).await).is_empty());
+
+        let event = TaskSubmittedEvent {
+            prover: parse_address(&quote.prover).unwrap(),
+            instance: Bytes(quote.instance.as_bytes().to_vec()),
+            task_key: web3::types::H256::repeat_byte(0x36),
+            reward_token: parse_address(&quote.reward_token).unwrap(),
+            reward_amount: U256::from(quote.reward),
+            liability_window: quote.liability_window,
+            liability_token: parse_address(&quote.liability_token).unwrap(),
+            liability_amount: U256::from(quote.liability),
+            block_number: 500,
+            ..Default::default()
+        };
+        assert_eq!(match_task_submitted(&event, &quote.prover).await, Some(quote_hash.clone()));
+        assert!(listed(call(&io, "demo/QueryQuotes", serde_json::json!(["outstanding"])).await).is_empty());
+        let matched = listed(call(&io, "demo/QueryQuotes", serde_json::json!(["matched"])).await);
+        assert_eq!(matched.len(), 1);
+        assert_eq!(matched[0]["task_id"], event.task_id());
+        assert_eq!(matched[0]["status"], "matched");
+
+        for params in [serde_json::json!(["pending"]), serde_json::json!([1]), serde_json::json!(["matched", "expired"])] {
+            assert_eq!(call(&io, "demo/QueryQuotes", params).await, "parameter invalid");
+        }
+    }
 
-
+    #[tokio::test]
+    async fn chain_methods_check_the_task_key() {
+        let io = rpc_handler();
+        let task_key = "c5".repeat(32);
+        for method in ["chain/TaskStatus", "chain/ProofStatus"] {
+            for params in [serde_json::json!([]), serde_json::json!([&task_key[2..]]), serde_json::json!([format!("{}@0", task_key)]),
+                serde_json::json!([format!("{}zz", &task_key[2..])]), serde_json::json!([1]), serde_json::json!([&task_key, &task_key])] {
+                assert_eq!(call(&io, method, params.clone()).await, "parameter invalid", "{} {}", method, params);
+            }
+        }
+    }
+}
@@ -0,0 +1,322 @@
+// This file is part of the aoraki-labs library.
+
+// The aoraki-labs library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// The aoraki-labs library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with the aoraki-labs library. If not, see <https://www.gnu.org/licenses/>.
+
+use std::env;
+use std::fs;
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use lazy_static::lazy_static;
+use log::*;
+use serde_json::{json, Value};
+use web3::{
+    Web3,
+    transports::Http,
+    signing::{self, Key, SecretKey},
+    types::{Address, Bytes, TransactionParameters},
+};
+
+pub const REMOTE_SIGNER_TIMEOUT_SECS: u64 = 10;
+pub const REMOTE_SIGNER_CONNECT_TIMEOUT_SECS: u64 = 5;
+
+static PROVER_SIGNER: OnceLock<Arc<Signer>> = OnceLock::new();   //prover identity, signs quotes
+
+lazy_static! {
+    pub static ref GAS_SIGNERS: tokio::sync::Mutex<Vec<Arc<Signer>>> = {   //hot wallets paying for transactions
+        tokio::sync::Mutex::new(Vec::new())
+    };
+}
+
+static GAS_SIGNER_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+/// the key that signs quotes and transactions, wherever it lives
+pub enum Signer {
+    Local(LocalSigner),
+    Remote(RemoteSigner),
+}
+
+/// a key held in memory, from a keystore, an env var or the command line
+pub struct LocalSigner {
+    key: SecretKey,
+    address: Address,
+}
+
+/// a web3signer compatible JSON-RPC endpoint (eth_accounts, eth_sign, eth_signTransaction)
+pub struct RemoteSigner {
+    url: String,
+    address: Address,
+    client: reqwest::Client,
+}
+
+impl LocalSigner {
+    pub fn from_hex(key: &str) -> Result<Self, String> {
+        let key = SecretKey::from_str(key.trim().trim_start_matches("0x"))
+            .map_err(|_| "invalid private key".to_string())?;
+        Ok(Self::from_key(key))
+    }
+
+    pub fn from_key(key: SecretKey) -> Self {
+        let address = (&key).address();
+        LocalSigner { key, address }
+    }
+
+    /// 65 byte r|s|v signature of a digest, v 27/28
+    fn sign_digest(&self, digest: [u8; 32]) -> Result<Vec<u8>, String> {
+        let sig = (&self.key).sign(&digest, None)
+            .map_err(|e| format!("sign message: {}", e))?;
+        let mut bytes = Vec::with_capacity(65);
+        bytes.extend_from_slice(sig.r.as_bytes());
+        bytes.extend_from_slice(sig.s.as_bytes());
+        bytes.push(sig.v as u8);
+        Ok(bytes)
+    }
+}
+
+impl RemoteSigner {
+    /// without an address the first account the signer reports is used
+    pub async fn connect(url: &str, address: Option<&str>) -> Result<Self, String> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(REMOTE_SIGNER_TIMEOUT_SECS))
+            .connect_timeout(Duration::from_secs(REMOTE_SIGNER_CONNECT_TIMEOUT_SECS))
+            .build()
+            .map_err(|e| format!("remote signer http client: {}", e))?;
+        let mut remote = RemoteSigner {
+            url: url.to_string(),
+            address: Address::zero(),
+            client,
+        };
+        let accounts = remote.rpc("eth_accounts", json!([])).await?;
+        let accounts: Vec<Address> = serde_json::from_value(accounts)
+            .map_err(|e| format!("remote signer eth_accounts: {}", e))?;
+        remote.address = match address {
+            Some(addr) => {
+                let addr = Address::from_str(addr.trim_start_matches("0x"))
+                    .map_err(|e| format!("remote signer address {}: {}", addr, e))?;
+                if !accounts.contains(&addr) {
+                    return Err(format!("remote signer does not hold {:?}", addr));
+                }
+                addr
+            },
+            None => *accounts.first().ok_or("remote signer has no accounts")?,
+        };
+        Ok(remote)
+    }
+
+    async fn rpc(&self, method: &str, params: Value) -> Result<Value, String> {
+        let request = json!({"jsonrpc": "2.0", "method": method, "params": params, "id": 1});
+        let response: Value = self.client.post(&self.url)
+            .json(&request)
+            .send().await
+            .map_err(|e| format!("remote signer {}: {}", method, e))?
+            .json().await
+            .map_err(|e| format!("remote signer {}: {}", method, e))?;
+        if let Some(err) = response.get("error") {
+            return Err(format!("remote signer {}: {}", method, err));
+        }
+        response.get("result").cloned().ok_or(format!("remote signer {}: no result", method))
+    }
+}
+
+impl Signer {
+    /// build a signer from a spec:
+    ///   keystore:<json file>,<password file>   encrypted JSON keystore
+    ///   env:<VAR>                              hex key in an environment variable
+    ///   remote:<url>[,<address>]               web3signer compatible remote signer
+    ///   <hex key>                              plaintext key, visible in ps and shell history
+    pub async fn from_spec(spec: &str) -> Result<Signer, String> {
+        if let Some(rest) = spec.strip_prefix("keystore:") {
+            let (path, password_file) = rest.split_once(',')
+                .ok_or("keystore signer needs keystore:<json file>,<password file>")?;
+            let password = fs::read_to_string(password_file)
+                .map_err(|e| format!("read password file {}: {}", password_file, e))?;
+            let key = eth_keystore::decrypt_key(path, password.trim_end_matches(['\r', '\n']))
+                .map_err(|e| format!("decrypt keystore {}: {}", path, e))?;
+            let key = SecretKey::from_slice(&key).map_err(|_| format!("invalid key in keystore {}", path))?;
+            Ok(Signer::Local(LocalSigner::from_key(key)))
+        } else if let Some(var) = spec.strip_prefix("env:") {
+            let key = env::var(var).map_err(|_| format!("env var {} is not set", var))?;
+            Ok(Signer::Local(LocalSigner::from_hex(&key)?))
+        } else if let Some(rest) = spec.strip_prefix("remote:") {
+            let (url, address) = match rest.rsplit_once(',') {
+                Some((url, address)) => (url, Some(address)),
+                None => (rest, None),
+            };
+            Ok(Signer::Remote(RemoteSigner::connect(url, address).await?))
+        } else {
+            warn!("using a plaintext private key, prefer a keystore, env or remote signer");
+            Ok(Signer::Local(LocalSigner::from_hex(spec)?))
+        }
+    }
+
+    pub fn address(&self) -> Address {
+        match self {
+            Signer::Local(local) => local.address,
+            Signer::Remote(remote) => remote.address,
+        }
+    }
+
+    /// eth_sign of a 32 byte hash: EIP-191 prefixed, 65 byte r|s|v with v 27/28
+    pub async fn sign_hash(&self, hash: [u8; 32]) -> Result<Vec<u8>, String> {
+        match self {
+            Signer::Local(local) => local.sign_digest(signing::hash_message(hash).into()),
+            Signer::Remote(remote) => {
+                let sig = remote.rpc("eth_sign", json!([remote.address, format!("0x{}", hex::encode(hash))])).await?;
+                let sig: Bytes = serde_json::from_value(sig).map_err(|e| format!("remote signer eth_sign: {}", e))?;
+                Ok(sig.0)
+            },
+        }
+    }
+
+    /// EIP-712 signature: the local key signs the digest directly, a remote signer gets the
+    /// typed data JSON through eth_signTypedData and computes the same digest itself
+    pub async fn sign_typed_data(&self, digest: [u8; 32], typed_data: Value) -> Result<Vec<u8>, String> {
+        match self {
+            Signer::Local(local) => local.sign_digest(digest),
+            Signer::Remote(remote) => {
+                let sig = remote.rpc("eth_signTypedData", json!([remote.address, typed_data])).await?;
+                let sig: Bytes = serde_json::from_value(sig).map_err(|e| format!("remote signer eth_signTypedData: {}", e))?;
+                Ok(sig.0)
+            },
+        }
+    }
+
+    /// sign a transaction, returning the raw bytes ready for eth_sendRawTransaction
+    pub async fn sign_transaction(&self, web3: &Web3<Http>, tx: TransactionParameters) -> Result<Bytes, String> {
+        match self {
+            Signer::Local(local) => {
+                let signed = web3.accounts().sign_transaction(tx, &local.key).await
+                    .map_err(|e| format!("sign transaction: {}", e))?;
+                Ok(signed.raw_transaction)
+            },
+            Signer::Remote(remote) => {
+                let chain_id = match tx.chain_id {
+                    Some(r) => r.into(),
+                    None => web3.eth().chain_id().await.map_err(|e| format!("chain id: {}", e))?,
+                };
+                let request = json!([{
+                    "from": remote.address,
+                    "to": tx.to,
+                    "gas": tx.gas,
+                    "gasPrice": tx.gas_price,
+                    "nonce": tx.nonce,
+                    "value": tx.value,
+                    "data": tx.data,
+                    "chainId": chain_id,
+                }]);
+                let raw = remote.rpc("eth_signTransaction", request).await?;
+                serde_json::from_value(raw).map_err(|e| format!("remote signer eth_signTransaction: {}", e))
+            },
+        }
+    }
+}
+
+/// the prover identity is set once at startup
+pub fn set_prover_signer(signer: Signer) -> Result<(), String> {
+    let address = signer.address();
+    PROVER_SIGNER.set(Arc::new(signer)).map_err(|_| "prover signer already set".to_string())?;
+    info!("prover identity is {:?}", address);
+    Ok(())
+}
+
+/// without gas wallets the prover key pays for its own transactions
+pub async fn set_gas_signers(signers: Vec<Signer>) {
+    for signer in signers.iter() {
+        info!("gas wallet {:?}", signer.address());
+    }
+    let mut current = GAS_SIGNERS.lock().await;
+    *current = signers.into_iter().map(Arc::new).collect();
+}
+
+pub fn prover_signer() -> Result<Arc<Signer>, String> {
+    PROVER_SIGNER.get().cloned().ok_or("no prover signer configured".to_string())
+}
+
+/// the gas wallets take turns so no single hot key carries every transaction
+pub async fn next_gas_signer() -> Result<Arc<Signer>, String> {
+    let signers = GAS_SIGNERS.lock().await;
+    if signers.is_empty() {
+        return prover_signer();
+    }
+    let index = GAS_SIGNER_INDEX.fetch_add(1, Ordering::Relaxed) % signers.len();
+    Ok(signers[index].clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::serve_rpc;
+
+    const KEY_ONE: &str = "0x0000000000000000000000000000000000000000000000000000000000000001";
+    const ADDRESS_ONE: &str = "7e5f4552091a69125d5dfcb7b8c2659029395bdf";
+    const KEY_TWO: &str = "0000000000000000000000000000000000000000000000000000000000000002";
+
+    #[tokio::test]
+    async fn specs_build_signers() {
+        env::set_var("SIGNER_TEST_KEY", KEY_ONE);
+        let signer = Signer::from_spec("env:SIGNER_TEST_KEY").await.unwrap();
+        assert_eq!(hex::encode(signer.address()), ADDRESS_ONE);
+        assert_eq!(Signer::from_spec(KEY_ONE).await.unwrap().address(), signer.address());
+        assert!(Signer::from_spec("env:SIGNER_TEST_UNSET").await.is_err());
+        assert!(Signer::from_spec("keystore:./key.json").await.is_err());
+        assert!(Signer::from_spec("0xnot a key").await.is_err());
+
+        //an EIP-191 signature recovers to the signer's address
+        let hash = [7u8; 32];
+        let sig = signer.sign_hash(hash).await.unwrap();
+        assert_eq!(sig.len(), 65);
+        let recovered = signing::recover(signing::hash_message(hash).as_bytes(), &sig[..64], sig[64] as i32 - 27).unwrap();
+        assert_eq!(recovered, signer.address());
+    }
+
+    #[tokio::test]
+    async fn remote_signer_holds_the_address() {
+        let url = serve_rpc(|method| match method {
+            "eth_accounts" => Some(json!([format!("0x{}", ADDRESS_ONE)])),
+            "eth_sign" => Some(json!(format!("0x{}", "11".repeat(65)))),
+            _ => None,
+        }).await;
+        let remote = Signer::from_spec(&format!("remote:{}", url)).await.unwrap();
+        assert_eq!(hex::encode(remote.address()), ADDRESS_ONE);
+        assert_eq!(remote.sign_hash([1u8; 32]).await.unwrap(), vec![0x11; 65]);
+        let other = format!("remote:{},0x{}", url, "22".repeat(20));
+        assert!(Signer::from_spec(&other).await.err().unwrap().contains("does not hold"));
+        //nothing listening
+        assert!(Signer::from_spec("remote:http://127.0.0.1:9").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn gas_wallets_take_turns_apart_from_the_prover() {
+        //the same prover key as the quote tests, whichever sets it first
+        let prover_key = "11".repeat(32);
+        set_prover_signer(Signer::Local(LocalSigner::from_hex(&prover_key).unwrap())).ok();
+        assert!(set_prover_signer(Signer::Local(LocalSigner::from_hex(KEY_TWO).unwrap())).is_err());
+        let prover = prover_signer().unwrap().address();
+        assert_eq!(prover, LocalSigner::from_hex(&prover_key).unwrap().address);
+        //without gas wallets the prover pays
+        assert_eq!(next_gas_signer().await.unwrap().address(), prover);
+
+        set_gas_signers(vec![
+            Signer::Local(LocalSigner::from_hex(KEY_ONE).unwrap()),
+            Signer::Local(LocalSigner::from_hex(KEY_TWO).unwrap()),
+        ]).await;
+        let turns: Vec<Address> = futures::future::join_all((0..4).map(|_| next_gas_signer())).await
+            .into_iter().map(|signer| signer.unwrap().address()).collect();
+        assert!(!turns.contains(&prover));
+        assert_ne!(turns[0], turns[1]);
+        assert_eq!((turns[0], turns[1]), (turns[2], turns[3]));
+        assert_eq!(prover_signer().unwrap().address(), prover);
+    }
+}
@@ -0,0 +1,245 @@
+// This file is part of the aoraki-labs library.
+
+// The aoraki-labs library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// The aoraki-labs library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with the aoraki-labs library. If not, see <https://www.gnu.org/licenses/>.
+
+//! segments waiting for provers polling the relayer with GetWork, the "pull" scheduler
+
+use std::collections::{HashMap, VecDeque};
+use lazy_static::lazy_static;
+use log::*;
+use serde_derive::Serialize;
+use tokio::time::{Duration, Instant};
+
+use crate::scheduler::Segment;
+
+pub const WORK_LEASE_SECS: u64 = 300;     //replaced by --work_lease
+pub const MAX_WORK_PER_POLL: usize = 16;  //segments one GetWork may lease
+
+lazy_static! {
+    pub static ref WORK: tokio::sync::Mutex<WorkQueue> = {
+        tokio::sync::Mutex::new(WorkQueue::default())
+    };
+    pub static ref WORK_LEASE: tokio::sync::Mutex<Duration> = {   //how long a prover holds a segment
+        tokio::sync::Mutex::new(Duration::from_secs(WORK_LEASE_SECS))
+    };
+}
+
+struct Lease {
+    id: String,
+    worker: String,
+    expires_at: Instant,
+}
+
+struct WorkItem {
+    segment: Segment,
+    lease: Option<Lease>,
+    leases: Vec<String>,    //every lease handed out, a late proof on an older one is still taken
+}
+
+#[derive(Default)]
+pub struct WorkQueue {
+    pending: VecDeque<String>,          //segment ids waiting for a prover, oldest first
+    items: HashMap<String, WorkItem>,   //segment id -> pending or leased segment
+    workers: HashMap<String, (Instant, usize)>,  //worker -> last GetWork and the segments it asked for
+}
+
+/// a segment leased to a prover by GetWork
+#[derive(Debug, Serialize)]
+pub struct LeasedWork {
+    pub lease_id: String,
+    pub project_id: String,
+    /// <task key>@<split id>, to send back with the proof
+    pub task_id: String,
+    pub instance: String,
+    pub degree: String,
+    /// seconds until the segment may be handed to another prover
+    pub lease_secs: u64,
+}
+
+pub async fn set_work_lease(secs: u64) -> Result<(), String> {
+    if secs == 0 {
+        return Err("work_lease must be at least 1s".to_string());
+    }
+    *WORK_LEASE.lock().await = Duration::from_secs(secs);
+    Ok(())
+}
+
+impl WorkQueue {
+    /// queue a segment for the provers, a segment queued already loses its lease and waits again
+    fn offer(&mut self, segment: &Segment) {
+        let queued = match self.items.get_mut(&segment.task_id) {
+            Some(item) => {
+                let was_pending = item.lease.is_none();
+                item.lease = None;
+                was_pending
+            },
+            None => {
+                self.items.insert(segment.task_id.clone(), WorkItem { segment: segment.clone(), lease: None, leases: vec![] });
+                false
+            },
+        };
+        if !queued {
+            self.pending.push_back(segment.task_id.clone());
+        }
+    }
+
+    /// lease up to max pending segments to worker at now, oldest first; expired leases are pending again
+    fn get_work(&mut self, worker: &str, max: usize, lease: Duration, now: Instant) -> Vec<LeasedWork> {
+        let max = max.min(MAX_WORK_PER_POLL);
+        self.workers.retain(|_, (seen, _)| now.duration_since(*seen) < lease);
+        self.workers.insert(worker.to_string(), (now, max));
+        let mut expired = vec![];
+        for (segment_id, item) in self.items.iter_mut() {
+            if item.lease.as_ref().map(|lease| lease.expires_at <= now).unwrap_or(false) {
+                let old = item.lease.take().unwrap();
+                info!("lease {} of segment {} by {} expired", old.id, segment_id, old.worker);
+                expired.push(segment_id.clone());
+            }
+        }
+        expired.sort();
+        self.pending.extend(expired);
+        let mut leased = vec![];
+        while leased.len() < max {
+            let segment_id = match self.pending.pop_front() {
+                Some(r) => r,
+                None => break,
+            };
+            let item = match self.items.get_mut(&segment_id) {
+                Some(r) => r,
+                None => continue,
+            };
+            let id = format!("{:016x}", rand::random::<u64>());
+            item.lease = Some(Lease { id: id.clone(), worker: worker.to_string(), expires_at: now + lease });
+            item.leases.push(id.clone());
+            info!("segment {} leased to {} as {}", segment_id, worker, id);
+            leased.push(LeasedWork {
+                lease_id: id,
+                project_id: item.segment.project_id.clone(),
+                task_id: segment_id,
+                instance: item.segment.instance.clone(),
+                degree: item.segment.degree.clone(),
+                lease_secs: lease.as_secs(),
+            });
+        }
+        leased
+    }
+
+    /// the degree of the segment when the lease was handed out for it
+    fn submit_work(&self, lease_id: &str, segment_id: &str) -> Result<String, String> {
+        let item = match self.items.get(segment_id) {
+            Some(r) => r,
+            None => return Err(format!("segment {} is not queued, proven or failed already", segment_id)),
+        };
+        if !item.leases.iter().any(|id| id == lease_id) {
+            return Err(format!("lease {} is not one of segment {}", lease_id, segment_id));
+        }
+        Ok(item.segment.degree.clone())
+    }
+
+    fn forget(&mut self, filter: impl Fn(&str) -> bool) {
+        self.items.retain(|segment_id, _| !filter(segment_id));
+        self.pending.retain(|segment_id| !filter(segment_id));
+    }
+
+    /// the segments asked for by the provers that polled within a lease period before now, less the segments pending or leased
+    fn free_slots(&self, lease: Duration, now: Instant) -> u64 {
+        let slots: usize = self.workers.values().filter(|(seen, _)| now.duration_since(*seen) < lease).map(|(_, max)| max).sum();
+        slots.saturating_sub(self.items.len()) as u64
+    }
+}
+
+/// queue a segment for the provers, a segment queued already loses its lease and waits again
+pub async fn offer(segment: &Segment) {
+    WORK.lock().await.offer(segment);
+}
+
+/// lease up to max pending segments to worker, oldest first; expired leases are pending again
+pub async fn get_work(worker: &str, max: usize) -> Vec<LeasedWork> {
+    let lease = *WORK_LEASE.lock().await;
+    WORK.lock().await.get_work(worker, max, lease, Instant::now())
+}
+
+/// check a SubmitWork: the degree of the segment when the lease was handed out for it.
+/// the segment stays queued until its proof is accepted, segment_done then forgets it
+pub async fn submit_work(lease_id: &str, segment_id: &str) -> Result<String, String> {
+    WORK.lock().await.submit_work(lease_id, segment_id)
+}
+
+/// forget the queued segments the filter matches
+pub async fn forget(filter: impl Fn(&str) -> bool) {
+    WORK.lock().await.forget(filter);
+}
+
+/// the segments asked for by the provers that polled within a lease period, less the segments pending or leased
+pub async fn free_slots() -> u64 {
+    let lease = *WORK_LEASE.lock().await;
+    WORK.lock().await.free_slots(lease, Instant::now())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(id: &str) -> Segment {
+        Segment { project_id: "demo".to_string(), task_id: id.to_string(), instance: "00".to_string(), degree: "2".to_string() }
+    }
+
+    #[test]
+    fn lease_expiry_and_reissue() {
+        let mut work = WorkQueue::default();
+        let lease = Duration::from_secs(1);
+        let start = Instant::now();
+        work.offer(&segment("t@0"));
+        work.offer(&segment("t@1"));
+
+        let first = work.get_work("alice", 1, lease, start);
+        assert_eq!(first.len(), 1);
+        assert_eq!((first[0].task_id.as_str(), first[0].lease_secs), ("t@0", 1));
+        let second = work.get_work("bob", 5, lease, start);
+        assert_eq!(second.iter().map(|w| w.task_id.as_str()).collect::<Vec<_>>(), vec!["t@1"]);
+        assert!(work.get_work("carol", 1, lease, start).is_empty());
+        //alice and bob asked for 6, carol for 1, two are out
+        assert_eq!(work.free_slots(lease, start), 5);
+        //a lease is held up to its last instant
+        assert!(work.get_work("carol", 1, lease, start + Duration::from_millis(999)).is_empty());
+
+        let later = start + lease;
+        let reissued = work.get_work("carol", 1, lease, later);
+        assert_eq!(reissued.len(), 1);
+        assert_eq!(reissued[0].task_id, "t@0");
+        assert_ne!(reissued[0].lease_id, first[0].lease_id);
+        //only carol polled within the lease
+        assert_eq!(work.free_slots(lease, later), 0);
+
+        //the expired lease still proves the segment, a foreign one does not
+        assert_eq!(work.submit_work(&first[0].lease_id, "t@0"), Ok("2".to_string()));
+        assert_eq!(work.submit_work(&reissued[0].lease_id, "t@0"), Ok("2".to_string()));
+        assert!(work.submit_work(&second[0].lease_id, "t@0").is_err());
+        assert!(work.submit_work("0000000000000000", "t@1").is_err());
+
+        //an offered segment loses its lease and is handed out again
+        work.offer(&segment("t@1"));
+        let resent = work.get_work("dave", 1, lease, later);
+        assert_eq!(resent[0].task_id, "t@1");
+
+        //once proven the segment is forgotten and its leases refused
+        work.forget(|id| id.starts_with("t@"));
+        assert!(work.submit_work(&reissued[0].lease_id, "t@0").is_err());
+
+        for i in 0..MAX_WORK_PER_POLL + 4 {
+            work.offer(&segment(&format!("u@{}", i)));
+        }
+        assert_eq!(work.get_work("erin", 100, lease, later).len(), MAX_WORK_PER_POLL);
+    }
+}